@@ -16,7 +16,8 @@ use futures::task::{
 };
 use futures::executor::{Notify, NotifyHandle, spawn};
 use serde_json;
-use event::{AsyncOutcome, SpanId, TraceEvent};
+use event::{new_span_id, AsyncOutcome, SpanId, TraceEvent};
+use filter;
 use state::TRACER_STATE;
 
 /// Atomic slot of a single parked task.  Note that this only parks at most one
@@ -53,7 +54,7 @@ pub trait TraceFuture: Future + Sized where Self::Error : Debug {
 
     fn with_metadata<S: Into<String>>(self, name: S, meta: serde_json::Value) -> TracedFuture<Self> {
         TracedFuture {
-            state: TraceState::Created { name: name.into(), metadata: meta },
+            state: TraceState::Created { name: name.into(), metadata: meta, spawned: None },
             inner: self,
         }
     }
@@ -64,11 +65,20 @@ enum TraceState {
     Created {
         name: String,
         metadata: serde_json::Value,
+        // `Some` for task roots handed to an executor, holding the span that
+        // spawned them (if any).  These may be polled from any thread, under
+        // any (or no) current span, and outlive the span that spawned them,
+        // so they hang off the thread that first polls them instead.
+        spawned: Option<Option<SpanId>>,
     },
     Executing {
         parent: SpanId,
         id: SpanId,
+        spawned: bool,
     },
+    // Filtered out, or a spawned task on an untraced thread: just poll
+    // the inner future, leaving the current span alone.
+    Untraced,
     Resolved,
     Poisoned,
 }
@@ -92,6 +102,27 @@ impl<F> DerefMut for TracedFuture<F> {
 }
 
 impl<F> TracedFuture<F> {
+    /// Wrap a future that is about to be spawned as a task root.  Unlike
+    /// `traced`, the span's parent is the thread span of whichever thread
+    /// first polls it, and the span that spawned it, if any, is recorded as
+    /// `spawned_by` in its metadata.  That id is written as a decimal string,
+    /// since metadata is passed through as is and JavaScript would round ids
+    /// above 2^53.
+    pub fn spawned<S: Into<String>>(inner: F, name: S, spawned_by: Option<SpanId>) -> Self {
+        let metadata = match spawned_by {
+            Some(id) => json!({ "spawned_by": id.0.to_string() }),
+            None => serde_json::Value::Null,
+        };
+        TracedFuture {
+            state: TraceState::Created {
+                name: name.into(),
+                metadata,
+                spawned: Some(spawned_by),
+            },
+            inner,
+        }
+    }
+
     pub fn into_inner(self) -> F {
         self.inner
     }
@@ -102,35 +133,58 @@ impl<F: Future> Future for TracedFuture<F> where F::Error : Debug {
     type Error = F::Error;
 
     fn poll(&mut self) -> Poll<F::Item, F::Error> {
+        if let TraceState::Untraced = self.state {
+            return self.inner.poll();
+        }
         TRACER_STATE.with(|c| {
-            let (parent_id, span_id) = {
+            let (prev_span, span_id) = {
                 let mut st = c.borrow_mut();
-                let (parent_id, span_id) = match mem::replace(&mut self.state, TraceState::Poisoned) {
+                let prev_span = st.current_span;
+                let span_id = match mem::replace(&mut self.state, TraceState::Poisoned) {
                     // First poll!  Let's set up our execution state.
-                    TraceState::Created { name, metadata } => {
-                        let span_id = SpanId::new();
-                        let parent_id = st.current_span.expect("Missing parent span");
+                    TraceState::Created { name, metadata, spawned } => {
+                        let parent_id = if !filter::enabled(&name) {
+                            None
+                        } else {
+                            match spawned {
+                                None => Some(st.current_span.expect("Missing parent span")),
+                                Some(_) => st.thread_span,
+                            }
+                        };
+                        let parent_id = match parent_id {
+                            Some(parent_id) => parent_id,
+                            None => {
+                                self.state = TraceState::Untraced;
+                                drop(st);
+                                return self.inner.poll();
+                            },
+                        };
+                        let span_id = new_span_id();
 
                         let event = TraceEvent::AsyncStart {
-                            name: name,
+                            name,
                             id: span_id,
-                            parent_id: parent_id,
+                            parent_id,
                             ts: st.now(),
-                            metadata: metadata,
+                            metadata,
                         };
                         st.emit(event);
 
                         self.state = TraceState::Executing {
                             parent: parent_id,
                             id: span_id,
+                            spawned: spawned.is_some(),
                         };
-                        (parent_id, span_id)
+                        span_id
                     },
-                    TraceState::Executing { parent, id } => {
-                        assert_eq!(st.current_span, Some(parent), "Parent span changed across execution");
-                        self.state = TraceState::Executing { parent, id };
-                        (parent, id)
+                    TraceState::Executing { parent, id, spawned } => {
+                        if !spawned {
+                            assert_eq!(st.current_span, Some(parent), "Parent span changed across execution");
+                        }
+                        self.state = TraceState::Executing { parent, id, spawned };
+                        id
                     },
+                    TraceState::Untraced => unreachable!(),
                     TraceState::Resolved => panic!("Polled after resolved"),
                     TraceState::Poisoned => panic!("Polled after panic"),
                 };
@@ -142,7 +196,7 @@ impl<F: Future> Future for TracedFuture<F> where F::Error : Debug {
                 st.emit(on_event);
                 st.current_span = Some(span_id);
 
-                (parent_id, span_id)
+                (prev_span, span_id)
             };
 
             let notifier = Notifier { parent_task: AtomicTask::default(), parked_span: span_id };
@@ -156,7 +210,7 @@ impl<F: Future> Future for TracedFuture<F> where F::Error : Debug {
 
             let mut st = c.borrow_mut();
 
-            st.current_span = Some(parent_id);
+            st.current_span = prev_span;
             let off_event = TraceEvent::AsyncOffCPU {
                 id: span_id,
                 ts: st.now(),
@@ -199,7 +253,7 @@ impl Notify for Notifier {
         TRACER_STATE.with(|c| {
             let should_log = {
                 let mut st = c.borrow_mut();
-                let should_log = !st.currently_logging_wakeup;
+                let should_log = !st.currently_logging_wakeup && filter::is_enabled();
                 if should_log {
                     if let Some(current_span) = st.current_span {
                         let event = TraceEvent::Wakeup {
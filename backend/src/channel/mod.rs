@@ -0,0 +1,109 @@
+//! Traced wrappers around `futures::sync` channels.  Each send and receive is
+//! recorded as a `Mark` on the current span along with the channel's running
+//! message count, and a send that unparks a waiting receiver records a
+//! `Wakeup` from the sending span to the span the receiver was parked in,
+//! whichever thread either end lives on.
+
+use std::mem;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use event::{SpanId, TraceEvent};
+use filter;
+use state::TRACER_STATE;
+
+pub mod mpsc;
+pub mod oneshot;
+
+#[derive(Debug)]
+pub(crate) struct Link {
+    name: String,
+    // The span the receiving end last went to sleep in, if it's waiting.
+    parked: Mutex<Option<SpanId>>,
+    sent: AtomicUsize,
+    received: AtomicUsize,
+}
+
+impl Link {
+    pub(crate) fn new(name: String) -> Self {
+        Link {
+            name,
+            parked: Mutex::new(None),
+            sent: AtomicUsize::new(0),
+            received: AtomicUsize::new(0),
+        }
+    }
+
+    /// Run `send`, and if it succeeds, record the send and wake up the
+    /// receiver's span.  The `Notifier` wakeup that the send would otherwise
+    /// trigger is suppressed, since we log a more precise one.
+    pub(crate) fn send<R, F>(&self, send: F, ok: fn(&R) -> bool) -> R
+        where F: FnOnce() -> R
+    {
+        let logging = TRACER_STATE.with(|c| {
+            mem::replace(&mut c.borrow_mut().currently_logging_wakeup, true)
+        });
+        let result = send();
+        TRACER_STATE.with(|c| c.borrow_mut().currently_logging_wakeup = logging);
+
+        if !ok(&result) {
+            return result;
+        }
+        let count = self.sent.fetch_add(1, Ordering::SeqCst) + 1;
+        let parked = self.parked.lock().unwrap().take();
+        if filter::is_enabled() {
+            TRACER_STATE.with(|c| {
+                let mut st = c.borrow_mut();
+                if let Some(current_span) = st.current_span {
+                    let event = TraceEvent::Mark {
+                        id: current_span,
+                        name: "send".to_string(),
+                        ts: st.now(),
+                        metadata: json!({ "channel": self.name, "count": count }),
+                    };
+                    st.emit(event);
+                    if let Some(parked_span) = parked {
+                        let event = TraceEvent::Wakeup {
+                            waking_span: current_span,
+                            parked_span,
+                            ts: st.now(),
+                        };
+                        st.emit(event);
+                    }
+                }
+            });
+        }
+        result
+    }
+
+    /// Park the receiver in the current span.  This must happen before the
+    /// receiver polls the channel: a send that lands after the poll finds it
+    /// empty would otherwise see no parked span and log no wakeup.
+    fn park(&self) {
+        let current_span = TRACER_STATE.with(|c| c.borrow().current_span);
+        *self.parked.lock().unwrap() = current_span;
+    }
+
+    fn unpark(&self) {
+        self.parked.lock().unwrap().take();
+    }
+
+    fn receive(&self) {
+        self.unpark();
+        let count = self.received.fetch_add(1, Ordering::SeqCst) + 1;
+        if !filter::is_enabled() {
+            return;
+        }
+        TRACER_STATE.with(|c| {
+            let mut st = c.borrow_mut();
+            if let Some(current_span) = st.current_span {
+                let event = TraceEvent::Mark {
+                    id: current_span,
+                    name: "recv".to_string(),
+                    ts: st.now(),
+                    metadata: json!({ "channel": self.name, "count": count }),
+                };
+                st.emit(event);
+            }
+        })
+    }
+}
@@ -0,0 +1,171 @@
+use std::sync::Arc;
+use futures::{
+    Async,
+    AsyncSink,
+    Poll,
+    Sink,
+    StartSend,
+    Stream,
+};
+use futures::sync::mpsc;
+pub use futures::sync::mpsc::{SendError, TrySendError};
+use super::Link;
+
+pub fn channel<T, S: Into<String>>(name: S, buffer: usize) -> (Sender<T>, Receiver<T>) {
+    let link = Arc::new(Link::new(name.into()));
+    let (tx, rx) = mpsc::channel(buffer);
+    (Sender { inner: tx, link: link.clone() }, Receiver { inner: rx, link })
+}
+
+pub fn unbounded<T, S: Into<String>>(name: S) -> (UnboundedSender<T>, UnboundedReceiver<T>) {
+    let link = Arc::new(Link::new(name.into()));
+    let (tx, rx) = mpsc::unbounded();
+    (UnboundedSender { inner: tx, link: link.clone() }, UnboundedReceiver { inner: rx, link })
+}
+
+fn started<T, E>(result: &StartSend<T, E>) -> bool {
+    matches!(*result, Ok(AsyncSink::Ready))
+}
+
+pub(crate) fn received<T, E, F>(link: &Link, poll: F) -> Poll<Option<T>, E>
+    where F: FnOnce() -> Poll<Option<T>, E>
+{
+    link.park();
+    let result = poll();
+    match result {
+        Ok(Async::Ready(Some(_))) => link.receive(),
+        Ok(Async::NotReady) => (),
+        _ => link.unpark(),
+    }
+    result
+}
+
+#[derive(Debug)]
+pub struct Sender<T> {
+    inner: mpsc::Sender<T>,
+    link: Arc<Link>,
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Sender { inner: self.inner.clone(), link: self.link.clone() }
+    }
+}
+
+impl<T> Sender<T> {
+    pub fn try_send(&mut self, msg: T) -> Result<(), TrySendError<T>> {
+        let inner = &mut self.inner;
+        self.link.send(move || inner.try_send(msg), Result::is_ok)
+    }
+
+    pub fn poll_ready(&mut self) -> Poll<(), SendError<()>> {
+        self.inner.poll_ready()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.inner.is_closed()
+    }
+}
+
+impl<T> Sink for Sender<T> {
+    type SinkItem = T;
+    type SinkError = SendError<T>;
+
+    fn start_send(&mut self, msg: T) -> StartSend<T, SendError<T>> {
+        let inner = &mut self.inner;
+        self.link.send(move || inner.start_send(msg), started)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), SendError<T>> {
+        self.inner.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), SendError<T>> {
+        self.inner.close()
+    }
+}
+
+#[derive(Debug)]
+pub struct Receiver<T> {
+    inner: mpsc::Receiver<T>,
+    link: Arc<Link>,
+}
+
+impl<T> Receiver<T> {
+    pub fn close(&mut self) {
+        self.inner.close()
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<T>, ()> {
+        let inner = &mut self.inner;
+        received(&self.link, move || inner.poll())
+    }
+}
+
+#[derive(Debug)]
+pub struct UnboundedSender<T> {
+    inner: mpsc::UnboundedSender<T>,
+    link: Arc<Link>,
+}
+
+impl<T> Clone for UnboundedSender<T> {
+    fn clone(&self) -> Self {
+        UnboundedSender { inner: self.inner.clone(), link: self.link.clone() }
+    }
+}
+
+impl<T> UnboundedSender<T> {
+    pub fn unbounded_send(&self, msg: T) -> Result<(), SendError<T>> {
+        let inner = &self.inner;
+        self.link.send(move || inner.unbounded_send(msg), Result::is_ok)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.inner.is_closed()
+    }
+}
+
+impl<T> Sink for UnboundedSender<T> {
+    type SinkItem = T;
+    type SinkError = SendError<T>;
+
+    fn start_send(&mut self, msg: T) -> StartSend<T, SendError<T>> {
+        let inner = &mut self.inner;
+        self.link.send(move || inner.start_send(msg), started)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), SendError<T>> {
+        self.inner.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), SendError<T>> {
+        self.inner.close()
+    }
+}
+
+#[derive(Debug)]
+pub struct UnboundedReceiver<T> {
+    inner: mpsc::UnboundedReceiver<T>,
+    link: Arc<Link>,
+}
+
+impl<T> UnboundedReceiver<T> {
+    pub fn close(&mut self) {
+        self.inner.close()
+    }
+}
+
+impl<T> Stream for UnboundedReceiver<T> {
+    type Item = T;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<T>, ()> {
+        let inner = &mut self.inner;
+        received(&self.link, move || inner.poll())
+    }
+}
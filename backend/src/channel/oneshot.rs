@@ -0,0 +1,60 @@
+use std::sync::Arc;
+use futures::{
+    Async,
+    Future,
+    Poll,
+};
+use futures::sync::oneshot;
+pub use futures::sync::oneshot::Canceled;
+use super::Link;
+
+pub fn channel<T, S: Into<String>>(name: S) -> (Sender<T>, Receiver<T>) {
+    let link = Arc::new(Link::new(name.into()));
+    let (tx, rx) = oneshot::channel();
+    (Sender { inner: tx, link: link.clone() }, Receiver { inner: rx, link })
+}
+
+#[derive(Debug)]
+pub struct Sender<T> {
+    inner: oneshot::Sender<T>,
+    link: Arc<Link>,
+}
+
+impl<T> Sender<T> {
+    pub fn send(self, t: T) -> Result<(), T> {
+        let inner = self.inner;
+        self.link.send(move || inner.send(t), Result::is_ok)
+    }
+
+    pub fn is_canceled(&self) -> bool {
+        self.inner.is_canceled()
+    }
+}
+
+#[derive(Debug)]
+pub struct Receiver<T> {
+    inner: oneshot::Receiver<T>,
+    link: Arc<Link>,
+}
+
+impl<T> Receiver<T> {
+    pub fn close(&mut self) {
+        self.inner.close()
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Item = T;
+    type Error = Canceled;
+
+    fn poll(&mut self) -> Poll<T, Canceled> {
+        self.link.park();
+        let result = self.inner.poll();
+        match result {
+            Ok(Async::Ready(_)) => self.link.receive(),
+            Ok(Async::NotReady) => (),
+            Err(_) => self.link.unpark(),
+        }
+        result
+    }
+}
@@ -0,0 +1,65 @@
+// Pass-through versions of the `TraceFuture` API, used when tracing is
+// compiled out with the `disabled` feature.
+
+use std::fmt::Debug;
+use std::ops::{
+    Deref,
+    DerefMut,
+};
+use futures::{
+    Future,
+    Poll,
+};
+use serde_json;
+use event::SpanId;
+
+pub trait TraceFuture: Future + Sized where Self::Error : Debug {
+    #[inline(always)]
+    fn traced<S: Into<String>>(self, _name: S) -> TracedFuture<Self> {
+        TracedFuture { inner: self }
+    }
+
+    #[inline(always)]
+    fn with_metadata<S: Into<String>>(self, _name: S, _meta: serde_json::Value) -> TracedFuture<Self> {
+        TracedFuture { inner: self }
+    }
+}
+impl<F: Future + Sized> TraceFuture for F where F::Error : Debug {}
+
+pub struct TracedFuture<F> {
+    inner: F,
+}
+
+impl<F> Deref for TracedFuture<F> {
+    type Target = F;
+    fn deref(&self) -> &F {
+        &self.inner
+    }
+}
+
+impl<F> DerefMut for TracedFuture<F> {
+    fn deref_mut(&mut self) -> &mut F {
+        &mut self.inner
+    }
+}
+
+impl<F> TracedFuture<F> {
+    #[inline(always)]
+    pub fn spawned<S: Into<String>>(inner: F, _name: S, _spawned_by: Option<SpanId>) -> Self {
+        TracedFuture { inner }
+    }
+
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+}
+
+impl<F: Future> Future for TracedFuture<F> where F::Error : Debug {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    #[inline(always)]
+    fn poll(&mut self) -> Poll<F::Item, F::Error> {
+        self.inner.poll()
+    }
+}
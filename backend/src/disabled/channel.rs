@@ -0,0 +1,34 @@
+// Pass-through versions of the traced channels, used when tracing is compiled
+// out with the `disabled` feature: they're the `futures::sync` channels.
+
+pub mod mpsc {
+    use futures::sync::mpsc;
+    pub use futures::sync::mpsc::{
+        Receiver,
+        SendError,
+        Sender,
+        TrySendError,
+        UnboundedReceiver,
+        UnboundedSender,
+    };
+
+    #[inline(always)]
+    pub fn channel<T, S: Into<String>>(_name: S, buffer: usize) -> (Sender<T>, Receiver<T>) {
+        mpsc::channel(buffer)
+    }
+
+    #[inline(always)]
+    pub fn unbounded<T, S: Into<String>>(_name: S) -> (UnboundedSender<T>, UnboundedReceiver<T>) {
+        mpsc::unbounded()
+    }
+}
+
+pub mod oneshot {
+    use futures::sync::oneshot;
+    pub use futures::sync::oneshot::{Canceled, Receiver, Sender};
+
+    #[inline(always)]
+    pub fn channel<T, S: Into<String>>(_name: S) -> (Sender<T>, Receiver<T>) {
+        oneshot::channel()
+    }
+}
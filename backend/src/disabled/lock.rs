@@ -0,0 +1,161 @@
+// Pass-through versions of the traced locks, used when tracing is compiled
+// out with the `disabled` feature.
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{
+    Deref,
+    DerefMut,
+};
+use std::sync::{
+    LockResult,
+    Mutex,
+    MutexGuard,
+    PoisonError,
+    RwLock,
+    RwLockReadGuard,
+    RwLockWriteGuard,
+    TryLockError,
+    TryLockResult,
+};
+
+fn map_result<G, H, F: FnOnce(G) -> H>(result: LockResult<G>, f: F) -> LockResult<H> {
+    match result {
+        Ok(guard) => Ok(f(guard)),
+        Err(e) => Err(PoisonError::new(f(e.into_inner()))),
+    }
+}
+
+fn map_try_result<G, H, F: FnOnce(G) -> H>(result: TryLockResult<G>, f: F) -> TryLockResult<H> {
+    match result {
+        Ok(guard) => Ok(f(guard)),
+        Err(TryLockError::Poisoned(e)) => Err(TryLockError::Poisoned(PoisonError::new(f(e.into_inner())))),
+        Err(TryLockError::WouldBlock) => Err(TryLockError::WouldBlock),
+    }
+}
+
+pub struct TracedGuard<'a, G> {
+    guard: G,
+    _lock: PhantomData<&'a ()>,
+}
+
+fn guard<'a, G>(guard: G) -> TracedGuard<'a, G> {
+    TracedGuard { guard, _lock: PhantomData }
+}
+
+pub type TracedMutexGuard<'a, T> = TracedGuard<'a, MutexGuard<'a, T>>;
+pub type TracedRwLockReadGuard<'a, T> = TracedGuard<'a, RwLockReadGuard<'a, T>>;
+pub type TracedRwLockWriteGuard<'a, T> = TracedGuard<'a, RwLockWriteGuard<'a, T>>;
+
+impl<'a, G: Deref> Deref for TracedGuard<'a, G> {
+    type Target = G::Target;
+    fn deref(&self) -> &G::Target {
+        &self.guard
+    }
+}
+
+impl<'a, G: DerefMut> DerefMut for TracedGuard<'a, G> {
+    fn deref_mut(&mut self) -> &mut G::Target {
+        &mut self.guard
+    }
+}
+
+pub struct TracedMutex<T: ?Sized> {
+    name: String,
+    inner: Mutex<T>,
+}
+
+impl<T> TracedMutex<T> {
+    #[inline(always)]
+    pub fn new<S: Into<String>>(name: S, t: T) -> Self {
+        TracedMutex { name: name.into(), inner: Mutex::new(t) }
+    }
+
+    pub fn into_inner(self) -> LockResult<T> {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> TracedMutex<T> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline(always)]
+    pub fn lock(&self) -> LockResult<TracedMutexGuard<'_, T>> {
+        map_result(self.inner.lock(), guard)
+    }
+
+    #[inline(always)]
+    pub fn try_lock(&self) -> TryLockResult<TracedMutexGuard<'_, T>> {
+        map_try_result(self.inner.try_lock(), guard)
+    }
+
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        self.inner.get_mut()
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for TracedMutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TracedMutex")
+            .field("name", &self.name)
+            .field("inner", &&self.inner)
+            .finish()
+    }
+}
+
+pub struct TracedRwLock<T: ?Sized> {
+    name: String,
+    inner: RwLock<T>,
+}
+
+impl<T> TracedRwLock<T> {
+    #[inline(always)]
+    pub fn new<S: Into<String>>(name: S, t: T) -> Self {
+        TracedRwLock { name: name.into(), inner: RwLock::new(t) }
+    }
+
+    pub fn into_inner(self) -> LockResult<T> {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> TracedRwLock<T> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline(always)]
+    pub fn read(&self) -> LockResult<TracedRwLockReadGuard<'_, T>> {
+        map_result(self.inner.read(), guard)
+    }
+
+    #[inline(always)]
+    pub fn write(&self) -> LockResult<TracedRwLockWriteGuard<'_, T>> {
+        map_result(self.inner.write(), guard)
+    }
+
+    #[inline(always)]
+    pub fn try_read(&self) -> TryLockResult<TracedRwLockReadGuard<'_, T>> {
+        map_try_result(self.inner.try_read(), guard)
+    }
+
+    #[inline(always)]
+    pub fn try_write(&self) -> TryLockResult<TracedRwLockWriteGuard<'_, T>> {
+        map_try_result(self.inner.try_write(), guard)
+    }
+
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        self.inner.get_mut()
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for TracedRwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TracedRwLock")
+            .field("name", &self.name)
+            .field("inner", &&self.inner)
+            .finish()
+    }
+}
@@ -0,0 +1,50 @@
+// Pass-through versions of the thread and span guards, used when tracing is
+// compiled out with the `disabled` feature.
+
+use serde_json;
+use event::SpanId;
+use state::Logger;
+
+pub struct TracedThread {
+    _private: (),
+}
+
+impl TracedThread {
+    #[inline(always)]
+    pub fn new<S: Into<String>>(_name: S, _writer: Box<dyn Logger>) -> Self {
+        TracedThread { _private: () }
+    }
+}
+
+pub struct SyncSpan {
+    _private: (),
+}
+
+impl SyncSpan {
+    #[inline(always)]
+    pub fn new<S: Into<String>>(_name: S) -> Self {
+        SyncSpan { _private: () }
+    }
+
+    #[inline(always)]
+    pub fn with_metadata<S: Into<String>>(_name: S, _meta: serde_json::Value) -> Self {
+        SyncSpan { _private: () }
+    }
+
+    #[inline(always)]
+    pub fn id(&self) -> Option<SpanId> {
+        None
+    }
+}
+
+#[derive(Default)]
+pub struct IdleSpan {
+    _private: (),
+}
+
+impl IdleSpan {
+    #[inline(always)]
+    pub fn new() -> Self {
+        IdleSpan { _private: () }
+    }
+}
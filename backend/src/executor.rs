@@ -0,0 +1,170 @@
+use std::cell::RefCell;
+use std::panic::Location;
+use std::rc::Rc;
+use futures::{
+    Async,
+    Future,
+    Poll,
+    Stream,
+};
+use futures::future::{ExecuteError, Executor};
+use futures::stream::futures_unordered::FuturesUnordered;
+use futures::task::{self, Task};
+use async::TracedFuture;
+use event::SpanId;
+use state::TRACER_STATE;
+
+fn current_span() -> Option<SpanId> {
+    TRACER_STATE.with(|c| c.borrow().current_span)
+}
+
+#[track_caller]
+fn spawn_site() -> String {
+    let location = Location::caller();
+    format!("{}:{}", location.file(), location.line())
+}
+
+/// Wraps an `Executor` so that every future spawned on it becomes a
+/// `TracedFuture`.  Tasks are named after their spawn site unless spawned with
+/// `spawn_named`.
+///
+/// Tasks are *not* parented to the span that spawned them.  A task often
+/// first runs after its spawner has ended, and on another thread, so it would
+/// start under a closed span and have its CPU time drawn on the spawner's
+/// thread.  Instead each task goes under the thread span of the thread that
+/// first polls it, and the spawning span, if any, is recorded as `spawned_by`
+/// (a decimal string id) in its metadata.
+#[derive(Clone)]
+pub struct TracedExecutor<E> {
+    inner: E,
+}
+
+impl<E> TracedExecutor<E> {
+    pub fn new(inner: E) -> Self {
+        TracedExecutor { inner }
+    }
+
+    pub fn into_inner(self) -> E {
+        self.inner
+    }
+
+    #[track_caller]
+    pub fn spawn<F>(&self, future: F) -> Result<(), ExecuteError<F>>
+        where F: Future<Item = (), Error = ()>,
+              E: Executor<TracedFuture<F>>
+    {
+        self.spawn_named(spawn_site(), future)
+    }
+
+    pub fn spawn_named<S, F>(&self, name: S, future: F) -> Result<(), ExecuteError<F>>
+        where S: Into<String>,
+              F: Future<Item = (), Error = ()>,
+              E: Executor<TracedFuture<F>>
+    {
+        let future = TracedFuture::spawned(future, name, current_span());
+        self.inner.execute(future)
+            .map_err(|e| ExecuteError::new(e.kind(), e.into_future().into_inner()))
+    }
+}
+
+impl<E, F> Executor<F> for TracedExecutor<E>
+    where F: Future<Item = (), Error = ()>,
+          E: Executor<TracedFuture<F>>
+{
+    #[track_caller]
+    fn execute(&self, future: F) -> Result<(), ExecuteError<F>> {
+        self.spawn_named(spawn_site(), future)
+    }
+}
+
+type LocalTask = TracedFuture<Box<dyn Future<Item = (), Error = ()>>>;
+
+#[derive(Default)]
+struct Queue {
+    pending: Vec<LocalTask>,
+    runner: Option<Task>,
+}
+
+/// A single-threaded runner whose tasks are all traced, as with
+/// `TracedExecutor`.  Handles are cheap to clone and may be used to spawn more
+/// tasks from inside running ones.
+#[derive(Clone, Default)]
+pub struct TracedCurrentThread {
+    queue: Rc<RefCell<Queue>>,
+}
+
+impl TracedCurrentThread {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[track_caller]
+    pub fn spawn<F>(&self, future: F)
+        where F: Future<Item = (), Error = ()> + 'static
+    {
+        self.spawn_named(spawn_site(), future)
+    }
+
+    pub fn spawn_named<S, F>(&self, name: S, future: F)
+        where S: Into<String>,
+              F: Future<Item = (), Error = ()> + 'static
+    {
+        let future: Box<dyn Future<Item = (), Error = ()>> = Box::new(future);
+        let mut queue = self.queue.borrow_mut();
+        queue.pending.push(TracedFuture::spawned(future, name, current_span()));
+        if let Some(runner) = queue.runner.take() {
+            runner.notify();
+        }
+    }
+
+    /// Block the current thread until every spawned task (including ones
+    /// spawned while running) has completed.
+    pub fn run(&self) {
+        let run = Run {
+            tasks: FuturesUnordered::new(),
+            queue: self.queue.clone(),
+        };
+        let _ = run.wait();
+    }
+}
+
+impl<F> Executor<F> for TracedCurrentThread
+    where F: Future<Item = (), Error = ()> + 'static
+{
+    #[track_caller]
+    fn execute(&self, future: F) -> Result<(), ExecuteError<F>> {
+        self.spawn_named(spawn_site(), future);
+        Ok(())
+    }
+}
+
+struct Run {
+    tasks: FuturesUnordered<LocalTask>,
+    queue: Rc<RefCell<Queue>>,
+}
+
+impl Future for Run {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        loop {
+            {
+                let mut queue = self.queue.borrow_mut();
+                for task in queue.pending.drain(..) {
+                    self.tasks.push(task);
+                }
+                queue.runner = Some(task::current());
+            }
+            // Errors from individual tasks are dropped, like any other executor.
+            let done = match self.tasks.poll() {
+                Ok(Async::Ready(Some(()))) | Err(()) => continue,
+                Ok(Async::Ready(None)) => true,
+                Ok(Async::NotReady) => false,
+            };
+            if self.queue.borrow().pending.is_empty() {
+                return Ok(if done { Async::Ready(()) } else { Async::NotReady });
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::cell::RefCell;
+use std::env;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+// Bumped by every `set_filter`, so threads know their copy is stale.
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static! {
+    static ref FILTER: RwLock<Option<Arc<Filter>>> = RwLock::new(Filter::from_env().map(Arc::new));
+}
+
+thread_local! {
+    // This thread's copy of `FILTER`, and the generation it was taken at, so
+    // starting a span doesn't take the lock.
+    static CACHED: RefCell<Option<(usize, Option<Arc<Filter>>)>> = const { RefCell::new(None) };
+}
+
+/// Turn span recording on or off for the whole process.  Spans that are
+/// already open when this is called are still closed normally; threads are
+/// always recorded, so that later spans have somewhere to go.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Replace the filter deciding which spans are recorded.  `None` records
+/// everything.  The initial filter is read from `CYCLOTRON_FILTER`.
+pub fn set_filter(filter: Option<Filter>) {
+    *FILTER.write().unwrap() = filter.map(Arc::new);
+    GENERATION.fetch_add(1, Ordering::Release);
+}
+
+fn matches(filter: Option<&Filter>, name: &str) -> bool {
+    filter.map(|f| f.matches(name)).unwrap_or(true)
+}
+
+/// Whether a span named `name` starting now should be recorded.  Spans that
+/// aren't leave the current span alone, so their children are attributed to
+/// the nearest recorded ancestor instead.
+pub fn enabled(name: &str) -> bool {
+    if !is_enabled() {
+        return false;
+    }
+    let generation = GENERATION.load(Ordering::Acquire);
+    let cached = CACHED.try_with(|c| {
+        let mut cached = c.borrow_mut();
+        if cached.as_ref().map(|c| c.0 != generation).unwrap_or(true) {
+            *cached = Some((generation, FILTER.read().unwrap().clone()));
+        }
+        matches(cached.as_ref().and_then(|c| c.1.as_deref()), name)
+    });
+    // Spans started while the thread's being torn down go to the lock.
+    cached.unwrap_or_else(|_| matches(FILTER.read().unwrap().as_deref(), name))
+}
+
+/// Selects spans by name prefix.  A span is recorded if it matches no
+/// excluded prefix, and either there are no included prefixes or it matches
+/// one of them.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Filter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl Filter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse a comma-separated list of prefixes, where a leading `-` excludes
+    /// instead of includes: `"rpc,db,-db::ping"`.
+    pub fn parse(spec: &str) -> Self {
+        spec.split(',')
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .fold(Filter::new(), |filter, p| match p.strip_prefix('-') {
+                Some(p) => filter.exclude(p),
+                None => filter.include(p),
+            })
+    }
+
+    pub fn from_env() -> Option<Self> {
+        env::var("CYCLOTRON_FILTER").ok().map(|spec| Filter::parse(&spec))
+    }
+
+    pub fn include<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.include.push(prefix.into());
+        self
+    }
+
+    pub fn exclude<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.exclude.push(prefix.into());
+        self
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        !self.exclude.iter().any(|p| name.starts_with(p.as_str()))
+            && (self.include.is_empty() || self.include.iter().any(|p| name.starts_with(p.as_str())))
+    }
+}
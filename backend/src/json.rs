@@ -2,24 +2,37 @@ use std::fs::File;
 use std::io::{BufWriter, Write};
 use serde_json;
 
+pub use cyclotron_model::json::{IdFormat, JsonFormat, TimestampFormat};
 use event::TraceEvent;
 use state::Logger;
 
 pub struct JsonWriter {
     file: BufWriter<File>,
+    format: JsonFormat,
 }
 
 impl JsonWriter {
     pub fn new(f: File) -> Self {
-        JsonWriter { file: BufWriter::new(f) }
+        Self::with_format(f, JsonFormat::default())
+    }
+
+    /// Write events in `format`, such as `JsonFormat::version(2)` for string
+    /// ids that survive being parsed by JavaScript, or version 3 for compact
+    /// timestamps too.
+    pub fn with_format(f: File, format: JsonFormat) -> Self {
+        JsonWriter { file: BufWriter::new(f), format }
     }
 }
 
 impl Logger for JsonWriter {
     fn write(&mut self, event: TraceEvent) {
-        serde_json::to_writer(&mut self.file, &event)
-            .expect("Failed to write to logfile");
-        self.file.write(b"\n").expect("Failed to write newline");
+        let result = if self.format == JsonFormat::default() {
+            serde_json::to_writer(&mut self.file, &event)
+        } else {
+            serde_json::to_writer(&mut self.file, &self.format.to_value(&event))
+        };
+        result.expect("Failed to write to logfile");
+        self.file.write_all(b"\n").expect("Failed to write newline");
     }
     fn flush(&mut self) {
         self.file.flush().expect("Failed to flush");
@@ -1,22 +1,48 @@
+
+extern crate cyclotron_model;
 extern crate futures;
 extern crate rand;
-extern crate serde;
+#[cfg(feature = "redact")]
+extern crate regex;
+#[cfg_attr(any(test, not(feature = "disabled")), macro_use)]
 extern crate serde_json;
 #[macro_use]
 extern crate lazy_static;
-#[allow(unused_imports)]
-#[macro_use]
-extern crate serde_derive;
+#[cfg(feature = "redact")]
+extern crate siphasher;
 
+#[cfg_attr(feature = "disabled", path = "disabled/async.rs")]
 mod async;
+#[cfg_attr(feature = "disabled", path = "disabled/channel.rs")]
+pub mod channel;
 mod event;
+mod executor;
+pub mod filter;
+#[cfg_attr(feature = "disabled", path = "disabled/lock.rs")]
+mod lock;
+mod pool;
+pub mod recording;
+#[cfg(feature = "redact")]
+pub mod redact;
 mod state;
+#[cfg_attr(feature = "disabled", path = "disabled/sync.rs")]
 mod sync;
 pub mod json;
+pub mod validate;
 
 pub use async::{TraceFuture, TracedFuture};
-pub use event::TraceEvent;
-pub use sync::{TracedThread, SyncSpan};
+pub use event::{AsyncOutcome, SpanId, TraceEvent};
+pub use executor::{TracedCurrentThread, TracedExecutor};
+pub use lock::{
+    TracedGuard,
+    TracedMutex,
+    TracedMutexGuard,
+    TracedRwLock,
+    TracedRwLockReadGuard,
+    TracedRwLockWriteGuard,
+};
+pub use pool::{TracedThreadPool, WorkerHook};
+pub use sync::{IdleSpan, TracedThread, SyncSpan};
 pub use state::{DebugLogger, NoopLogger, Logger};
 
 #[cfg(test)]
@@ -0,0 +1,294 @@
+use std::fmt;
+use std::ops::{
+    Deref,
+    DerefMut,
+};
+use std::sync::{
+    LockResult,
+    Mutex,
+    MutexGuard,
+    PoisonError,
+    RwLock,
+    RwLockReadGuard,
+    RwLockWriteGuard,
+    TryLockError,
+    TryLockResult,
+};
+use serde_json;
+use event::{new_span_id, SpanId, TraceEvent};
+use filter;
+use state::TRACER_STATE;
+use sync::SyncSpan;
+
+fn map_result<G, H, F: FnOnce(G) -> H>(result: LockResult<G>, f: F) -> LockResult<H> {
+    match result {
+        Ok(guard) => Ok(f(guard)),
+        Err(e) => Err(PoisonError::new(f(e.into_inner()))),
+    }
+}
+
+// Lock spans are only recorded on threads that are being traced, so that the
+// wrappers can be dropped in anywhere.
+fn span(name: &str, metadata: &serde_json::Value) -> Option<SyncSpan> {
+    let traced = TRACER_STATE.with(|c| c.borrow().current_span.is_some());
+    if traced {
+        Some(SyncSpan::with_metadata(name, metadata.clone()))
+    } else {
+        None
+    }
+}
+
+// The "holding lock" span.  Unlike a `SyncSpan`, it never becomes the current
+// span: guards can be dropped in any order, or after the task that took them
+// has returned from `poll`, so its start and end are written directly.
+struct HoldSpan {
+    id: SpanId,
+}
+
+impl HoldSpan {
+    fn new(metadata: &serde_json::Value) -> Option<Self> {
+        if !filter::enabled("holding lock") {
+            return None;
+        }
+        TRACER_STATE.with(|c| {
+            let mut st = c.borrow_mut();
+            let parent_id = st.current_span?;
+            let id = new_span_id();
+            let event = TraceEvent::SyncStart {
+                name: "holding lock".to_string(),
+                id,
+                parent_id,
+                ts: st.now(),
+                metadata: metadata.clone(),
+            };
+            st.emit(event);
+            Some(HoldSpan { id })
+        })
+    }
+}
+
+impl Drop for HoldSpan {
+    fn drop(&mut self) {
+        TRACER_STATE.with(|c| {
+            let mut st = c.borrow_mut();
+            let event = TraceEvent::SyncEnd {
+                id: self.id,
+                ts: st.now(),
+            };
+            st.emit(event);
+        })
+    }
+}
+
+#[derive(Default)]
+struct LockState {
+    // The "holding lock" span that most recently released the lock.
+    last_release: Mutex<Option<SpanId>>,
+}
+
+impl LockState {
+    fn acquire<'a, G, T, L>(&'a self, metadata: &serde_json::Value, try_lock: T, lock: L)
+        -> LockResult<TracedGuard<'a, G>>
+        where T: FnOnce() -> TryLockResult<G>,
+              L: FnOnce() -> LockResult<G>
+    {
+        let result = match try_lock() {
+            Ok(guard) => Ok(guard),
+            Err(TryLockError::Poisoned(e)) => Err(e),
+            Err(TryLockError::WouldBlock) => {
+                let wait = span("waiting for lock", metadata);
+                let result = lock();
+                if let Some(ref wait) = wait {
+                    self.woken(wait);
+                }
+                result
+            },
+        };
+        map_result(result, |guard| self.held(metadata, guard))
+    }
+
+    fn try_acquire<'a, G, T>(&'a self, metadata: &serde_json::Value, try_lock: T)
+        -> TryLockResult<TracedGuard<'a, G>>
+        where T: FnOnce() -> TryLockResult<G>
+    {
+        match try_lock() {
+            Ok(guard) => Ok(self.held(metadata, guard)),
+            Err(TryLockError::Poisoned(e)) => {
+                Err(TryLockError::Poisoned(PoisonError::new(self.held(metadata, e.into_inner()))))
+            },
+            Err(TryLockError::WouldBlock) => Err(TryLockError::WouldBlock),
+        }
+    }
+
+    fn held<G>(&self, metadata: &serde_json::Value, guard: G) -> TracedGuard<'_, G> {
+        TracedGuard {
+            guard,
+            span: HoldSpan::new(metadata),
+            last_release: &self.last_release,
+        }
+    }
+
+    fn woken(&self, wait: &SyncSpan) {
+        let releaser = *self.last_release.lock().unwrap();
+        if let (Some(waking_span), Some(parked_span)) = (releaser, wait.id()) {
+            TRACER_STATE.with(|c| {
+                let mut st = c.borrow_mut();
+                let event = TraceEvent::Wakeup {
+                    waking_span,
+                    parked_span,
+                    ts: st.now(),
+                };
+                st.emit(event);
+            })
+        }
+    }
+}
+
+/// RAII guard for `TracedMutex` and `TracedRwLock`.  The "holding lock" span
+/// lasts as long as the guard, which can be dropped in any order.  Spans
+/// started while holding the lock aren't nested under it.
+pub struct TracedGuard<'a, G> {
+    guard: G,
+    span: Option<HoldSpan>,
+    last_release: &'a Mutex<Option<SpanId>>,
+}
+
+pub type TracedMutexGuard<'a, T> = TracedGuard<'a, MutexGuard<'a, T>>;
+pub type TracedRwLockReadGuard<'a, T> = TracedGuard<'a, RwLockReadGuard<'a, T>>;
+pub type TracedRwLockWriteGuard<'a, T> = TracedGuard<'a, RwLockWriteGuard<'a, T>>;
+
+impl<'a, G: Deref> Deref for TracedGuard<'a, G> {
+    type Target = G::Target;
+    fn deref(&self) -> &G::Target {
+        &self.guard
+    }
+}
+
+impl<'a, G: DerefMut> DerefMut for TracedGuard<'a, G> {
+    fn deref_mut(&mut self) -> &mut G::Target {
+        &mut self.guard
+    }
+}
+
+impl<'a, G> Drop for TracedGuard<'a, G> {
+    fn drop(&mut self) {
+        // Runs before `guard` is dropped, so the next acquirer always sees us.
+        *self.last_release.lock().unwrap() = self.span.as_ref().map(|s| s.id);
+    }
+}
+
+/// A `std::sync::Mutex` that records a "waiting for lock" span while blocked
+/// on it and a "holding lock" span while its guard is live, both tagged with
+/// the lock's name.  An acquirer that had to wait gets a `Wakeup` from the
+/// span that released the lock to it.
+pub struct TracedMutex<T: ?Sized> {
+    name: String,
+    metadata: serde_json::Value,
+    state: LockState,
+    inner: Mutex<T>,
+}
+
+impl<T> TracedMutex<T> {
+    pub fn new<S: Into<String>>(name: S, t: T) -> Self {
+        let name = name.into();
+        TracedMutex {
+            metadata: json!({ "lock": name }),
+            name,
+            state: LockState::default(),
+            inner: Mutex::new(t),
+        }
+    }
+
+    pub fn into_inner(self) -> LockResult<T> {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> TracedMutex<T> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn lock(&self) -> LockResult<TracedMutexGuard<'_, T>> {
+        self.state.acquire(&self.metadata, || self.inner.try_lock(), || self.inner.lock())
+    }
+
+    pub fn try_lock(&self) -> TryLockResult<TracedMutexGuard<'_, T>> {
+        self.state.try_acquire(&self.metadata, || self.inner.try_lock())
+    }
+
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        self.inner.get_mut()
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for TracedMutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TracedMutex")
+            .field("name", &self.name)
+            .field("inner", &&self.inner)
+            .finish()
+    }
+}
+
+/// A `std::sync::RwLock` traced like `TracedMutex`; spans additionally record
+/// whether the lock was taken for reading or writing.
+pub struct TracedRwLock<T: ?Sized> {
+    name: String,
+    read_metadata: serde_json::Value,
+    write_metadata: serde_json::Value,
+    state: LockState,
+    inner: RwLock<T>,
+}
+
+impl<T> TracedRwLock<T> {
+    pub fn new<S: Into<String>>(name: S, t: T) -> Self {
+        let name = name.into();
+        TracedRwLock {
+            read_metadata: json!({ "lock": name, "mode": "read" }),
+            write_metadata: json!({ "lock": name, "mode": "write" }),
+            name,
+            state: LockState::default(),
+            inner: RwLock::new(t),
+        }
+    }
+
+    pub fn into_inner(self) -> LockResult<T> {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> TracedRwLock<T> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn read(&self) -> LockResult<TracedRwLockReadGuard<'_, T>> {
+        self.state.acquire(&self.read_metadata, || self.inner.try_read(), || self.inner.read())
+    }
+
+    pub fn write(&self) -> LockResult<TracedRwLockWriteGuard<'_, T>> {
+        self.state.acquire(&self.write_metadata, || self.inner.try_write(), || self.inner.write())
+    }
+
+    pub fn try_read(&self) -> TryLockResult<TracedRwLockReadGuard<'_, T>> {
+        self.state.try_acquire(&self.read_metadata, || self.inner.try_read())
+    }
+
+    pub fn try_write(&self) -> TryLockResult<TracedRwLockWriteGuard<'_, T>> {
+        self.state.try_acquire(&self.write_metadata, || self.inner.try_write())
+    }
+
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        self.inner.get_mut()
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for TracedRwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TracedRwLock")
+            .field("name", &self.name)
+            .field("inner", &&self.inner)
+            .finish()
+    }
+}
@@ -0,0 +1,259 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use futures::{Async, Future};
+use futures::executor::{self, Notify, NotifyHandle, Spawn};
+use futures::future::{ExecuteError, Executor};
+use async::TracedFuture;
+use executor::TracedExecutor;
+use state::Logger;
+use sync::{IdleSpan, TracedThread};
+
+thread_local! {
+    static WORKER: RefCell<Option<TracedThread>> = const { RefCell::new(None) };
+}
+
+/// Registers worker threads as `TracedThread`s named `<prefix>-N`, all
+/// writing to one shared logger.  Call `after_start` and `before_stop` from
+/// the corresponding hooks of an existing pool (e.g. `futures_cpupool::Builder`),
+/// or use `TracedThreadPool`, which does this itself.
+#[derive(Clone)]
+pub struct WorkerHook {
+    logger: Arc<Mutex<dyn Logger>>,
+    prefix: String,
+    next_id: Arc<AtomicUsize>,
+}
+
+impl WorkerHook {
+    pub fn new<L: Logger + 'static>(logger: L) -> Self {
+        Self::with_prefix("pool", logger)
+    }
+
+    pub fn with_prefix<S: Into<String>, L: Logger + 'static>(prefix: S, logger: L) -> Self {
+        WorkerHook {
+            logger: Arc::new(Mutex::new(logger)),
+            prefix: prefix.into(),
+            next_id: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn after_start(&self) {
+        let n = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let thread = TracedThread::new(format!("{}-{}", self.prefix, n), Box::new(self.logger.clone()));
+        WORKER.with(|w| *w.borrow_mut() = Some(thread));
+    }
+
+    pub fn before_stop(&self) {
+        WORKER.with(|w| w.borrow_mut().take());
+        self.logger.lock().unwrap().flush();
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+#[derive(Default)]
+struct Queue {
+    jobs: VecDeque<Job>,
+    shutdown: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    queue: Mutex<Queue>,
+    condvar: Condvar,
+}
+
+impl Shared {
+    fn submit(&self, job: Job) {
+        self.queue.lock().unwrap().jobs.push_back(job);
+        self.condvar.notify_one();
+    }
+
+    fn work(&self, hook: &WorkerHook) {
+        hook.after_start();
+        loop {
+            // The idle span is started and ended without holding the queue
+            // lock, so logging never holds up other workers or submitters.
+            let job = {
+                let mut idle = None;
+                loop {
+                    let mut queue = self.queue.lock().unwrap();
+                    if !queue.jobs.is_empty() || queue.shutdown {
+                        break queue.jobs.pop_front();
+                    }
+                    if idle.is_none() {
+                        drop(queue);
+                        idle = Some(IdleSpan::new());
+                    } else {
+                        drop(self.condvar.wait(queue).unwrap());
+                    }
+                }
+            };
+            match job {
+                Some(job) => job(),
+                None => break,
+            }
+        }
+        hook.before_stop();
+    }
+}
+
+struct Pool {
+    shared: Arc<Shared>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().shutdown = true;
+        self.shared.condvar.notify_all();
+        let current = thread::current().id();
+        for thread in self.threads.drain(..) {
+            if thread.thread().id() != current {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+/// A fixed-size pool of traced worker threads.  Time spent waiting for work
+/// is recorded as an idle span, and every future spawned on the pool is traced
+/// as with `TracedExecutor`.  Workers finish any queued work and exit once
+/// the last handle is dropped.
+#[derive(Clone)]
+pub struct TracedThreadPool {
+    pool: Arc<Pool>,
+}
+
+impl TracedThreadPool {
+    pub fn new<L: Logger + 'static>(size: usize, logger: L) -> Self {
+        Self::with_hook(size, WorkerHook::new(logger))
+    }
+
+    pub fn with_hook(size: usize, hook: WorkerHook) -> Self {
+        let shared = Arc::new(Shared::default());
+        let threads = (0..size).map(|_| {
+            let shared = shared.clone();
+            let hook = hook.clone();
+            thread::spawn(move || shared.work(&hook))
+        }).collect();
+        TracedThreadPool {
+            pool: Arc::new(Pool { shared, threads }),
+        }
+    }
+
+    /// Run a closure on one of the workers.
+    pub fn execute_fn<F: FnOnce() + Send + 'static>(&self, f: F) {
+        self.pool.shared.submit(Box::new(f));
+    }
+
+    #[track_caller]
+    pub fn spawn<F>(&self, future: F)
+        where F: Future<Item = (), Error = ()> + Send + 'static
+    {
+        TracedExecutor::new(Raw(self)).spawn(future).ok();
+    }
+
+    pub fn spawn_named<S, F>(&self, name: S, future: F)
+        where S: Into<String>,
+              F: Future<Item = (), Error = ()> + Send + 'static
+    {
+        TracedExecutor::new(Raw(self)).spawn_named(name, future).ok();
+    }
+}
+
+impl<F> Executor<F> for TracedThreadPool
+    where F: Future<Item = (), Error = ()> + Send + 'static
+{
+    #[track_caller]
+    fn execute(&self, future: F) -> Result<(), ExecuteError<F>> {
+        self.spawn(future);
+        Ok(())
+    }
+}
+
+// Runs futures on the pool without wrapping them again.
+struct Raw<'a>(&'a TracedThreadPool);
+
+impl<'a, F> Executor<TracedFuture<F>> for Raw<'a>
+    where F: Future<Item = (), Error = ()> + Send + 'static
+{
+    fn execute(&self, future: TracedFuture<F>) -> Result<(), ExecuteError<TracedFuture<F>>> {
+        let task = Arc::new(Task {
+            state: AtomicUsize::new(IDLE),
+            future: Mutex::new(Some(executor::spawn(Box::new(future)))),
+            shared: self.0.pool.shared.clone(),
+        });
+        task.schedule();
+        Ok(())
+    }
+}
+
+const IDLE: usize = 0;
+const SCHEDULED: usize = 1;
+const RUNNING: usize = 2;
+const NOTIFIED: usize = 3;
+const DONE: usize = 4;
+
+type BoxFuture = Box<dyn Future<Item = (), Error = ()> + Send>;
+
+struct Task {
+    state: AtomicUsize,
+    future: Mutex<Option<Spawn<BoxFuture>>>,
+    shared: Arc<Shared>,
+}
+
+impl Task {
+    fn run(self: Arc<Self>) {
+        self.state.store(RUNNING, Ordering::SeqCst);
+        let handle = NotifyHandle::from(Arc::new(Waker(self.clone())));
+        let mut future = self.future.lock().unwrap();
+        loop {
+            let done = match future.as_mut() {
+                Some(f) => match f.poll_future_notify(&handle, 0) {
+                    Ok(Async::NotReady) => false,
+                    Ok(Async::Ready(())) | Err(()) => true,
+                },
+                None => true,
+            };
+            if done {
+                self.state.store(DONE, Ordering::SeqCst);
+                *future = None;
+                return;
+            }
+            // Poll again if we were notified while running.
+            match self.state.compare_exchange(RUNNING, IDLE, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => return,
+                Err(_) => self.state.store(RUNNING, Ordering::SeqCst),
+            }
+        }
+    }
+
+    fn schedule(self: Arc<Self>) {
+        let mut state = self.state.load(Ordering::SeqCst);
+        loop {
+            let next = match state {
+                IDLE => SCHEDULED,
+                RUNNING => NOTIFIED,
+                _ => return,
+            };
+            match self.state.compare_exchange(state, next, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(IDLE) => break,
+                Ok(_) => return,
+                Err(actual) => state = actual,
+            }
+        }
+        let shared = self.shared.clone();
+        shared.submit(Box::new(move || self.run()));
+    }
+}
+
+struct Waker(Arc<Task>);
+
+impl Notify for Waker {
+    fn notify(&self, _: usize) {
+        self.0.clone().schedule();
+    }
+}
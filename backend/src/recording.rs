@@ -0,0 +1,240 @@
+//! An in-memory `Logger` for tests, and a span tree built from its events
+//! with fluent assertions about the async structure of the traced code:
+//!
+//! ```ignore
+//! let tree = logger.tree();
+//! tree.span("join3")
+//!     .has_child("okay")
+//!     .outcome(AsyncOutcome::Success)
+//!     .polls(1);
+//! ```
+//!
+//! Assertions panic with a description of what was found instead.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use serde_json;
+use cyclotron_model::Reconstructor;
+use event::{AsyncOutcome, SpanId, TraceEvent};
+use state::Logger;
+
+/// Records every event it is given.  Clones share the same record, so one
+/// can be handed to each `TracedThread` and inspected afterwards.
+#[derive(Clone, Default)]
+pub struct RecordingLogger {
+    events: Arc<Mutex<Vec<TraceEvent>>>,
+}
+
+impl RecordingLogger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn events(&self) -> Vec<TraceEvent> {
+        self.events.lock().unwrap().clone()
+    }
+
+    pub fn tree(&self) -> SpanTree {
+        SpanTree::new(&self.events.lock().unwrap())
+    }
+}
+
+impl Logger for RecordingLogger {
+    fn write(&mut self, event: TraceEvent) {
+        self.events.lock().unwrap().push(event);
+    }
+}
+
+pub use cyclotron_model::SpanKind;
+
+#[derive(Clone, Debug)]
+pub struct SpanNode {
+    pub id: SpanId,
+    pub name: String,
+    pub kind: SpanKind,
+    pub parent: Option<SpanId>,
+    pub children: Vec<SpanId>,
+    pub metadata: serde_json::Value,
+    pub start: Duration,
+    pub end: Option<Duration>,
+    pub outcome: Option<AsyncOutcome>,
+    /// Number of times the span went on-CPU.  Always 1 for closed sync spans.
+    pub polls: usize,
+    /// Spans that woke this one up, in order.
+    pub woken_by: Vec<SpanId>,
+    /// `Mark` events within this span, by name.
+    pub marks: Vec<(String, serde_json::Value)>,
+}
+
+/// Spans reconstructed from a list of events.
+#[derive(Clone, Debug, Default)]
+pub struct SpanTree {
+    nodes: HashMap<SpanId, SpanNode>,
+    // All span ids, ordered by start time.
+    order: Vec<SpanId>,
+}
+
+impl SpanTree {
+    /// Panics if the events don't make a well-formed trace.
+    pub fn new(events: &[TraceEvent]) -> Self {
+        let mut reconstructor = Reconstructor::new();
+        let mut spans = vec![];
+        for (i, event) in events.iter().enumerate() {
+            match reconstructor.add_event(event.clone()) {
+                Ok(Some(span)) => spans.push(span),
+                Ok(None) => (),
+                Err(e) => panic!("Can't rebuild spans from event {} ({:?}): {}", i + 1, event, e),
+            }
+        }
+        spans.extend(reconstructor.into_active());
+        spans.sort_by_key(|s| s.start);
+
+        let mut tree = SpanTree::default();
+        for span in spans {
+            tree.order.push(span.id);
+            tree.nodes.insert(span.id, SpanNode {
+                id: span.id,
+                polls: span.polls(),
+                woken_by: span.woken_by.iter().map(|w| w.waking_span).collect(),
+                marks: span.marks.into_iter().map(|m| (m.name, m.metadata)).collect(),
+                name: span.name,
+                kind: span.kind,
+                parent: span.parent_id,
+                children: vec![],
+                metadata: span.metadata,
+                start: span.start,
+                end: span.end,
+                outcome: span.outcome,
+            });
+        }
+        let nodes = &mut tree.nodes;
+        for &id in &tree.order {
+            if let Some(parent) = nodes[&id].parent.and_then(|p| nodes.get_mut(&p)) {
+                parent.children.push(id);
+            }
+        }
+        tree
+    }
+
+    pub fn get(&self, id: SpanId) -> Option<&SpanNode> {
+        self.nodes.get(&id)
+    }
+
+    /// All spans with the given name, in order of starting.
+    pub fn find(&self, name: &str) -> Vec<SpanRef<'_>> {
+        self.order.iter()
+            .map(|id| SpanRef { tree: self, node: &self.nodes[id] })
+            .filter(|s| s.node.name == name)
+            .collect()
+    }
+
+    /// The first span to start with the given name.
+    pub fn span(&self, name: &str) -> SpanRef<'_> {
+        match self.find(name).first() {
+            Some(&span) => span,
+            None => panic!("No span named {:?}; spans are {:?}", name, self.names()),
+        }
+    }
+
+    fn names(&self) -> Vec<&str> {
+        self.order.iter().map(|id| self.nodes[id].name.as_str()).collect()
+    }
+}
+
+/// A span in a `SpanTree`.  The assertion methods return the span they are
+/// called on, so they can be chained.
+#[derive(Copy, Clone)]
+pub struct SpanRef<'a> {
+    tree: &'a SpanTree,
+    node: &'a SpanNode,
+}
+
+impl<'a> fmt::Debug for SpanRef<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} span {:?}", self.node.kind, self.node.name)
+    }
+}
+
+impl<'a> SpanRef<'a> {
+    pub fn node(&self) -> &'a SpanNode {
+        self.node
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = SpanRef<'a>> + 'a {
+        let tree = self.tree;
+        self.node.children.iter().map(move |id| SpanRef { tree, node: &tree.nodes[id] })
+    }
+
+    pub fn parent(&self) -> SpanRef<'a> {
+        match self.node.parent.and_then(|p| self.tree.nodes.get(&p)) {
+            Some(node) => SpanRef { tree: self.tree, node },
+            None => panic!("{:?} has no parent", self),
+        }
+    }
+
+    /// The first child with the given name.
+    pub fn has_child(&self, name: &str) -> SpanRef<'a> {
+        match self.children().find(|c| c.node.name == name) {
+            Some(child) => child,
+            None => {
+                let names: Vec<_> = self.children().map(|c| c.node.name.clone()).collect();
+                panic!("{:?} has no child {:?}; children are {:?}", self, name, names)
+            },
+        }
+    }
+
+    /// Assert that no child has the given name.
+    pub fn lacks_child(self, name: &str) -> Self {
+        assert!(self.children().all(|c| c.node.name != name), "{:?} has a child {:?}", self, name);
+        self
+    }
+
+    pub fn child_count(self, n: usize) -> Self {
+        assert_eq!(self.node.children.len(), n, "Wrong number of children for {:?}", self);
+        self
+    }
+
+    pub fn kind(self, kind: SpanKind) -> Self {
+        assert_eq!(self.node.kind, kind, "Wrong kind for {:?}", self);
+        self
+    }
+
+    pub fn closed(self) -> Self {
+        assert!(self.node.end.is_some(), "{:?} was never closed", self);
+        self
+    }
+
+    pub fn outcome(self, outcome: AsyncOutcome) -> Self {
+        assert_eq!(self.node.outcome.as_ref(), Some(&outcome), "Wrong outcome for {:?}", self);
+        self
+    }
+
+    pub fn polls(self, n: usize) -> Self {
+        assert_eq!(self.node.polls, n, "Wrong number of polls for {:?}", self);
+        self
+    }
+
+    pub fn metadata(self, metadata: serde_json::Value) -> Self {
+        assert_eq!(self.node.metadata, metadata, "Wrong metadata for {:?}", self);
+        self
+    }
+
+    /// Assert that a span with the given name woke this one up.
+    pub fn woken_by(self, name: &str) -> Self {
+        let wakers: Vec<_> = self.node.woken_by.iter()
+            .filter_map(|id| self.tree.nodes.get(id))
+            .map(|n| n.name.as_str())
+            .collect();
+        assert!(wakers.contains(&name), "{:?} was not woken by {:?}; wakers are {:?}", self, name, wakers);
+        self
+    }
+
+    /// Assert that this span recorded `n` marks with the given name.
+    pub fn marks(self, name: &str, n: usize) -> Self {
+        let count = self.node.marks.iter().filter(|m| m.0 == name).count();
+        assert_eq!(count, n, "Wrong number of {:?} marks for {:?}", name, self);
+        self
+    }
+}
@@ -0,0 +1,205 @@
+//! Scrub sensitive data from traces while keeping their structure and timing,
+//! either when they're written (`RedactingLogger`) or afterwards
+//! (`Redactor::redact`).
+//!
+//! Rules apply in order to every event's metadata, span and thread names,
+//! mark names and error messages.  Keys match at any depth of the metadata,
+//! and are dropped or hashed before any replacements run.
+
+use std::fmt;
+use std::hash::Hasher;
+use regex::{self, Regex};
+use serde_json::Value;
+use siphasher::sip::SipHasher24;
+
+use event::{AsyncOutcome, TraceEvent};
+use state::Logger;
+
+#[derive(Clone, Debug)]
+enum Rule {
+    Drop(String),
+    Hash(String),
+    Replace(Regex, String),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Redactor {
+    rules: Vec<Rule>,
+    key: (u64, u64),
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Redactor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse rules, one per line, with `#` starting a comment:
+    ///
+    /// ```text
+    /// drop user_id
+    /// hash session
+    /// replace /home/[^/]+ -> /home/user
+    /// salt some-secret
+    /// ```
+    pub fn parse(spec: &str) -> Result<Self, ParseError> {
+        let mut redactor = Redactor::new();
+        for (i, line) in spec.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| ParseError { line: i + 1, message };
+            let (verb, rest) = match line.find(char::is_whitespace) {
+                Some(n) => (&line[..n], line[n..].trim()),
+                None => (line, ""),
+            };
+            if rest.is_empty() {
+                return Err(error(format!("{:?} needs an argument", verb)));
+            }
+            redactor = match verb {
+                "drop" => redactor.drop_key(rest),
+                "hash" => redactor.hash_key(rest),
+                "salt" => redactor.salt(rest),
+                "replace" => {
+                    let (pattern, replacement) = match rest.find(" -> ") {
+                        Some(n) => (rest[..n].trim(), rest[n + 4..].trim()),
+                        None => return Err(error("expected `replace <regex> -> <replacement>`".to_string())),
+                    };
+                    redactor.replace(pattern, replacement).map_err(|e| error(e.to_string()))?
+                },
+                _ => return Err(error(format!("unknown rule {:?}", verb))),
+            };
+        }
+        Ok(redactor)
+    }
+
+    /// Remove `key` from metadata.
+    pub fn drop_key<S: Into<String>>(mut self, key: S) -> Self {
+        self.rules.push(Rule::Drop(key.into()));
+        self
+    }
+
+    /// Replace the value of `key` with a hash of it, so equal values can
+    /// still be matched up.
+    pub fn hash_key<S: Into<String>>(mut self, key: S) -> Self {
+        self.rules.push(Rule::Hash(key.into()));
+        self
+    }
+
+    /// Replace matches of `pattern` in names, messages and metadata strings,
+    /// with `$1`-style references to groups as in `Regex::replace_all`.
+    pub fn replace<S: Into<String>>(mut self, pattern: &str, replacement: S) -> Result<Self, regex::Error> {
+        self.rules.push(Rule::Replace(Regex::new(pattern)?, replacement.into()));
+        Ok(self)
+    }
+
+    /// Key the hash.  Without a secret salt, hashes of guessable values such
+    /// as user ids can be reversed by hashing every candidate.
+    pub fn salt(mut self, salt: &str) -> Self {
+        let half = |k0| {
+            let mut hasher = SipHasher24::new_with_keys(k0, 0);
+            hasher.write(salt.as_bytes());
+            hasher.finish()
+        };
+        self.key = (half(0), half(1));
+        self
+    }
+
+    fn hash(&self, value: &Value) -> Value {
+        let mut hasher = SipHasher24::new_with_keys(self.key.0, self.key.1);
+        match *value {
+            Value::String(ref s) => hasher.write(s.as_bytes()),
+            _ => hasher.write(value.to_string().as_bytes()),
+        }
+        Value::String(format!("{:016x}", hasher.finish()))
+    }
+
+    fn string(&self, s: &mut String) {
+        for rule in &self.rules {
+            if let Rule::Replace(ref regex, ref replacement) = *rule {
+                let replaced = regex.replace_all(s, replacement.as_str()).into_owned();
+                *s = replaced;
+            }
+        }
+    }
+
+    fn value(&self, value: &mut Value) {
+        match *value {
+            Value::Object(ref mut map) => {
+                for rule in &self.rules {
+                    match *rule {
+                        Rule::Drop(ref key) => {
+                            map.remove(key);
+                        },
+                        Rule::Hash(ref key) => {
+                            if let Some(v) = map.get_mut(key) {
+                                *v = self.hash(v);
+                            }
+                        },
+                        Rule::Replace(..) => (),
+                    }
+                }
+                for v in map.values_mut() {
+                    self.value(v);
+                }
+            },
+            Value::Array(ref mut values) => {
+                for v in values {
+                    self.value(v);
+                }
+            },
+            Value::String(ref mut s) => self.string(s),
+            _ => (),
+        }
+    }
+
+    /// Apply the rules to one event.  Ids and timestamps are left alone.
+    pub fn redact(&self, mut event: TraceEvent) -> TraceEvent {
+        match event {
+            TraceEvent::AsyncStart { ref mut name, ref mut metadata, .. }
+            | TraceEvent::SyncStart { ref mut name, ref mut metadata, .. }
+            | TraceEvent::Mark { ref mut name, ref mut metadata, .. } => {
+                self.string(name);
+                self.value(metadata);
+            },
+            TraceEvent::ThreadStart { ref mut name, .. } => self.string(name),
+            TraceEvent::AsyncEnd { outcome: AsyncOutcome::Error(ref mut message), .. } => self.string(message),
+            _ => (),
+        }
+        event
+    }
+}
+
+/// A `Logger` that redacts events before passing them on.
+pub struct RedactingLogger<L> {
+    inner: L,
+    redactor: Redactor,
+}
+
+impl<L: Logger> RedactingLogger<L> {
+    pub fn new(inner: L, redactor: Redactor) -> Self {
+        RedactingLogger { inner, redactor }
+    }
+}
+
+impl<L: Logger> Logger for RedactingLogger<L> {
+    fn write(&mut self, event: TraceEvent) {
+        let event = self.redactor.redact(event);
+        self.inner.write(event)
+    }
+    fn flush(&mut self) {
+        self.inner.flush()
+    }
+}
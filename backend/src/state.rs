@@ -24,7 +24,16 @@ impl Logger for DebugLogger {
     }
 }
 
-impl<T: Logger> Logger for Arc<Mutex<T>> {
+impl<L: Logger + ?Sized> Logger for Box<L> {
+    fn write(&mut self, event: TraceEvent) {
+        (**self).write(event)
+    }
+    fn flush(&mut self) {
+        (**self).flush()
+    }
+}
+
+impl<T: Logger + ?Sized> Logger for Arc<Mutex<T>> {
     fn write(&mut self, event: TraceEvent) {
         self.lock().unwrap().write(event)
     }
@@ -40,11 +49,15 @@ impl Logger for NoopLogger {
     }
 }
 
+// Only the pass-through guards use this when tracing is disabled.
+#[cfg_attr(feature = "disabled", allow(dead_code))]
 pub struct TracerState {
     pub current_span: Option<SpanId>,
+    // The `TracedThread` span, which spawned tasks hang off of.
+    pub thread_span: Option<SpanId>,
     pub currently_logging_wakeup: bool,
 
-    pub writer: Option<Box<Logger>>,
+    pub writer: Option<Box<dyn Logger>>,
 
     start: Instant,
     since_epoch: Duration,
@@ -56,6 +69,7 @@ impl Default for TracerState {
         let now = Instant::now();
         TracerState {
             current_span: None,
+            thread_span: None,
             currently_logging_wakeup: false,
             writer: None,
 
@@ -65,8 +79,9 @@ impl Default for TracerState {
     }
 }
 
+#[cfg_attr(feature = "disabled", allow(dead_code))]
 impl TracerState {
-    pub fn start(&mut self, writer: Box<Logger>) {
+    pub fn start(&mut self, writer: Box<dyn Logger>) {
         // assert!(self.writer.is_none());
         self.writer = Some(writer);
     }
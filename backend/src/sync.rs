@@ -1,5 +1,7 @@
 use serde_json;
-use event::{SpanId, TraceEvent};
+use cyclotron_model::spans::IDLE_SPAN_NAME;
+use event::{new_span_id, SpanId, TraceEvent};
+use filter;
 use state::{TRACER_STATE, Logger};
 
 pub struct TracedThread {
@@ -7,14 +9,15 @@ pub struct TracedThread {
 }
 
 impl TracedThread {
-    pub fn new<S: Into<String>>(name: S, writer: Box<Logger>) -> Self {
+    pub fn new<S: Into<String>>(name: S, writer: Box<dyn Logger>) -> Self {
         TRACER_STATE.with(|c| {
             let mut st = c.borrow_mut();
             st.start(writer);
-            let span_id = SpanId::new();
+            let span_id = new_span_id();
 
             assert!(st.current_span.is_none());
             st.current_span = Some(span_id);
+            st.thread_span = Some(span_id);
 
             let event = TraceEvent::ThreadStart {
                 name: name.into(),
@@ -33,6 +36,7 @@ impl Drop for TracedThread {
         TRACER_STATE.with(|c| {
             let mut st = c.borrow_mut();
             st.current_span = None;
+            st.thread_span = None;
 
             let event = TraceEvent::ThreadEnd {
                 id: self.id,
@@ -44,8 +48,8 @@ impl Drop for TracedThread {
 }
 
 pub struct SyncSpan {
-    parent: SpanId,
-    id: SpanId,
+    // (parent, id), or `None` if filtered out.
+    span: Option<(SpanId, SpanId)>,
 }
 
 impl SyncSpan {
@@ -54,39 +58,100 @@ impl SyncSpan {
     }
 
     pub fn with_metadata<S: Into<String>>(name: S, meta: serde_json::Value) -> Self {
+        let name = name.into();
+        if !filter::enabled(&name) {
+            return SyncSpan { span: None };
+        }
         TRACER_STATE.with(|c| {
             let mut st = c.borrow_mut();
 
-            let span_id = SpanId::new();
+            let span_id = new_span_id();
             let parent_id = st.current_span.take().expect("Missing parent span");
             st.current_span = Some(span_id);
 
             let event = TraceEvent::SyncStart {
-                name: name.into(),
+                name,
                 id: span_id,
-                parent_id: parent_id,
+                parent_id,
                 ts: st.now(),
                 metadata: meta,
             };
             st.emit(event);
 
-            SyncSpan {
-                parent: parent_id,
-                id: span_id,
-            }
+            SyncSpan { span: Some((parent_id, span_id)) }
         })
     }
+
+    pub fn id(&self) -> Option<SpanId> {
+        self.span.map(|(_, id)| id)
+    }
 }
 
 impl Drop for SyncSpan {
     fn drop(&mut self) {
+        let (parent, id) = match self.span {
+            Some(span) => span,
+            None => return,
+        };
         TRACER_STATE.with(|c| {
             let mut st = c.borrow_mut();
-            assert_eq!(st.current_span, Some(self.id), "Current span changed during SyncSpan");
-            st.current_span = Some(self.parent);
+            assert_eq!(st.current_span, Some(id), "Current span changed during SyncSpan");
+            st.current_span = Some(parent);
 
             let event = TraceEvent::SyncEnd {
-                id: self.id,
+                id,
+                ts: st.now(),
+            };
+            st.emit(event);
+        })
+    }
+}
+
+/// Marks the current thread as idle (e.g. a pool worker waiting for work)
+/// until dropped.
+pub struct IdleSpan {
+    id: Option<SpanId>,
+}
+
+impl IdleSpan {
+    pub fn new() -> Self {
+        if !filter::enabled(IDLE_SPAN_NAME) {
+            return IdleSpan { id: None };
+        }
+        TRACER_STATE.with(|c| {
+            let mut st = c.borrow_mut();
+
+            let span_id = new_span_id();
+            let parent_id = st.current_span.expect("Missing parent span");
+
+            let event = TraceEvent::IdleStart {
+                id: span_id,
+                parent_id,
+                ts: st.now(),
+            };
+            st.emit(event);
+
+            IdleSpan { id: Some(span_id) }
+        })
+    }
+}
+
+impl Default for IdleSpan {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for IdleSpan {
+    fn drop(&mut self) {
+        let id = match self.id {
+            Some(id) => id,
+            None => return,
+        };
+        TRACER_STATE.with(|c| {
+            let mut st = c.borrow_mut();
+            let event = TraceEvent::IdleEnd {
+                id,
                 ts: st.now(),
             };
             st.emit(event);
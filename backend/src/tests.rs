@@ -1,23 +1,44 @@
+// Tests about the shape of recorded traces are skipped under `disabled`.
+#![cfg_attr(feature = "disabled", allow(unused_imports))]
+
+use std::cell::Cell;
 use std::fs::File;
 use std::thread;
 use std::time::Duration;
 use futures::{
     future,
+    Async,
     Future,
+    Poll,
     Stream,
 };
+use cyclotron_model::spans::IDLE_SPAN_NAME;
+use futures::future::{ExecuteError, Executor};
 use std::sync::{Arc, Mutex};
 use futures::sync::oneshot;
 use futures::stream::futures_unordered::FuturesUnordered;
 use state::Logger;
 use ::{
     DebugLogger,
+    TracedCurrentThread,
+    TracedExecutor,
+    TracedMutex,
+    TracedRwLock,
+    TracedThreadPool,
     TracedThread,
     SyncSpan,
     TraceFuture,
+    TracedFuture,
 };
 
-use json::JsonWriter;
+use channel;
+use filter::Filter;
+use json::{JsonFormat, JsonWriter};
+use recording::{RecordingLogger, SpanKind, SpanTree};
+#[cfg(feature = "redact")]
+use redact::{RedactingLogger, Redactor};
+use validate::{self, ViolationKind};
+use {AsyncOutcome, SpanId, TraceEvent};
 
 #[test]
 fn test_sync() {
@@ -66,3 +87,560 @@ fn test_async() {
 
     logger.flush();
 }
+
+fn assert_valid(logger: &RecordingLogger) {
+    let mut validator = validate::Validator::new();
+    for (i, event) in logger.events().iter().enumerate() {
+        validator.event(i + 1, event);
+    }
+    assert_eq!(validator.finish(), vec![]);
+}
+
+#[test]
+fn test_current_thread() {
+    let logger = RecordingLogger::new();
+    {
+        let _thread = TracedThread::new("test_current_thread", Box::new(logger.clone()));
+        let runner = TracedCurrentThread::new();
+        let (tx, rx) = oneshot::channel::<usize>();
+        let result = Arc::new(Mutex::new(None));
+
+        let result_ = result.clone();
+        runner.spawn_named("receiver", rx.map(move |x| *result_.lock().unwrap() = Some(x)).map_err(|_| ()));
+        let runner_ = runner.clone();
+        runner.spawn_named("spawner", future::lazy(move || {
+            // Spawned from inside a task that's finished by the time it runs.
+            runner_.spawn(future::lazy(move || tx.send(5).map_err(|_| ())));
+            Ok(())
+        }));
+        runner.run();
+        assert_eq!(*result.lock().unwrap(), Some(5));
+    }
+    if cfg!(feature = "disabled") {
+        return;
+    }
+
+    let tree = logger.tree();
+    let root = tree.span("test_current_thread").child_count(3);
+    let spawner = root.has_child("spawner")
+        .outcome(AsyncOutcome::Success)
+        .child_count(0)
+        .metadata(json!({ "spawned_by": root.node().id.0.to_string() }));
+    let sender = root.children()
+        .find(|c| c.node().name.contains("tests.rs:"))
+        .expect("no task named after its spawn site");
+    sender.outcome(AsyncOutcome::Success).metadata(json!({ "spawned_by": spawner.node().id.0.to_string() }));
+    root.has_child("receiver").woken_by(&sender.node().name);
+    assert_valid(&logger);
+}
+
+// Runs each task to completion on a new traced thread.
+struct ThreadExecutor {
+    logger: RecordingLogger,
+    threads: Cell<usize>,
+}
+
+impl<F: Future<Item = (), Error = ()> + Send + 'static> Executor<F> for ThreadExecutor {
+    fn execute(&self, future: F) -> Result<(), ExecuteError<F>> {
+        let (logger, n) = (self.logger.clone(), self.threads.get());
+        self.threads.set(n + 1);
+        thread::spawn(move || {
+            let _thread = TracedThread::new(format!("test_executor:worker-{}", n), Box::new(logger));
+            future.wait()
+        }).join().unwrap().unwrap();
+        Ok(())
+    }
+}
+
+#[test]
+fn test_executor() {
+    let logger = RecordingLogger::new();
+    let executor = TracedExecutor::new(ThreadExecutor { logger: logger.clone(), threads: Cell::new(0) });
+    let (tx, rx) = oneshot::channel::<usize>();
+
+    // Spawning outside of any span still traces the task on the thread that
+    // runs it, just without `spawned_by`.
+    executor.spawn(future::lazy(move || tx.send(1).map_err(|_| ()))).unwrap();
+
+    {
+        let _thread = TracedThread::new("test_executor", Box::new(logger.clone()));
+        let _span = SyncSpan::new("spawning");
+        executor.spawn_named("recv", rx.map(|x| assert_eq!(x, 1)).map_err(|_| ())).unwrap();
+    }
+    if cfg!(feature = "disabled") {
+        return;
+    }
+
+    let tree = logger.tree();
+    let spawning = tree.span("spawning").closed().child_count(0);
+    let first = tree.span("test_executor:worker-0").child_count(1).children().next().unwrap();
+    assert!(first.node().name.contains("tests.rs:"), "{:?}", first);
+    first.outcome(AsyncOutcome::Success).metadata(json!(null));
+    tree.span("test_executor:worker-1")
+        .has_child("recv")
+        .outcome(AsyncOutcome::Success)
+        .metadata(json!({ "spawned_by": spawning.node().id.0.to_string() }));
+    assert_valid(&logger);
+}
+
+#[test]
+fn test_pool() {
+    let logger = RecordingLogger::new();
+    {
+        let pool = TracedThreadPool::new(2, logger.clone());
+        let (tx, rx) = oneshot::channel::<usize>();
+        let (done_tx, done_rx) = oneshot::channel::<usize>();
+        pool.spawn_named("recv", rx.map(move |x| done_tx.send(x + 1).unwrap()).map_err(|_| ()));
+        pool.execute_fn(move || tx.send(1).unwrap());
+        assert_eq!(done_rx.wait().unwrap(), 2);
+        // Let both workers run out of work before shutting the pool down.
+        thread::sleep(Duration::from_millis(10));
+    }
+    if cfg!(feature = "disabled") {
+        return;
+    }
+
+    let tree = logger.tree();
+    for name in &["pool-0", "pool-1"] {
+        tree.span(name)
+            .kind(SpanKind::Thread)
+            .closed()
+            .has_child(IDLE_SPAN_NAME)
+            .kind(SpanKind::Idle)
+            .closed();
+    }
+    let recv = tree.span("recv").outcome(AsyncOutcome::Success);
+    assert!(recv.parent().node().name.starts_with("pool-"), "{:?}", recv.parent());
+    assert_valid(&logger);
+}
+
+#[test]
+fn test_locks() {
+    let logger = RecordingLogger::new();
+    let counter = Arc::new(TracedMutex::new("counter", 0));
+    let table = Arc::new(TracedRwLock::new("table", vec![1, 2, 3]));
+    {
+        let _thread = TracedThread::new("test_locks", Box::new(logger.clone()));
+        let guard = counter.lock().unwrap();
+        let (counter_, table_, logger_) = (counter.clone(), table.clone(), logger.clone());
+        let waiter = thread::spawn(move || {
+            let _thread = TracedThread::new("test_locks:waiter", Box::new(logger_));
+            *counter_.lock().unwrap() += 1;
+            table_.write().unwrap().push(4);
+        });
+        // Hold on until the waiter has found the lock taken.  Nothing's
+        // recorded to wait for when tracing is disabled.
+        let waiting = |e: &TraceEvent| match *e {
+            TraceEvent::SyncStart { ref name, .. } => name == "waiting for lock",
+            _ => false,
+        };
+        while !cfg!(feature = "disabled") && !logger.events().iter().any(&waiting) {
+            thread::yield_now();
+        }
+        drop(guard);
+        waiter.join().unwrap();
+
+        assert_eq!(*counter.lock().unwrap(), 1);
+        assert_eq!(table.read().unwrap().len(), 4);
+        assert!(counter.try_lock().is_ok());
+    }
+    if cfg!(feature = "disabled") {
+        return;
+    }
+
+    let tree = logger.tree();
+    let holding = tree.span("test_locks")
+        .has_child("holding lock")
+        .metadata(json!({ "lock": "counter" }));
+    tree.span("test_locks:waiter")
+        .has_child("waiting for lock")
+        .metadata(json!({ "lock": "counter" }))
+        .closed()
+        .woken_by("holding lock");
+    assert_eq!(tree.span("waiting for lock").node().woken_by, vec![holding.node().id]);
+    let table = json!({ "lock": "table", "mode": "write" });
+    assert!(tree.find("holding lock").iter().any(|s| s.node().metadata == table));
+}
+
+#[test]
+fn test_lock_release_order() {
+    let logger = RecordingLogger::new();
+    let (first, second) = (TracedMutex::new("first", ()), TracedMutex::new("second", ()));
+    {
+        let _thread = TracedThread::new("test_lock_release_order", Box::new(logger.clone()));
+        // Hand over hand: let go of the first lock while still holding the
+        // second.
+        let a = first.lock().unwrap();
+        let b = second.lock().unwrap();
+        drop(a);
+        let _span = SyncSpan::new("between");
+        drop(b);
+    }
+    if cfg!(feature = "disabled") {
+        return;
+    }
+
+    let tree = logger.tree();
+    let thread = tree.span("test_lock_release_order").child_count(3);
+    for held in tree.find("holding lock") {
+        held.closed();
+        assert_eq!(held.parent().node().id, thread.node().id);
+    }
+    thread.has_child("between").closed();
+    assert_valid(&logger);
+}
+
+#[test]
+fn test_lock_across_poll() {
+    let logger = RecordingLogger::new();
+    let lock = TracedMutex::new("lock", 0);
+    {
+        let _thread = TracedThread::new("test_lock_across_poll", Box::new(logger.clone()));
+        // Taken while the task is on-CPU and released after it's returned.
+        let mut guard = future::lazy(|| Ok::<_, ()>(lock.lock().unwrap()))
+            .traced("locker")
+            .wait()
+            .unwrap();
+        *guard += 1;
+        drop(guard);
+    }
+    assert_eq!(*lock.lock().unwrap(), 1);
+    if cfg!(feature = "disabled") {
+        return;
+    }
+
+    let tree = logger.tree();
+    tree.span("locker")
+        .outcome(AsyncOutcome::Success)
+        .has_child("holding lock")
+        .metadata(json!({ "lock": "lock" }))
+        .closed();
+    assert_valid(&logger);
+}
+
+#[test]
+fn test_channels() {
+    let logger = RecordingLogger::new();
+    {
+        let _thread = TracedThread::new("test_channels", Box::new(logger.clone()));
+        let (tx, rx) = channel::mpsc::unbounded::<usize, _>("numbers");
+        let (done_tx, done_rx) = channel::oneshot::channel::<usize, _>("done");
+        let logger_ = logger.clone();
+        let sender = thread::spawn(move || {
+            let _thread = TracedThread::new("test_channels:sender", Box::new(logger_));
+            let _span = SyncSpan::new("sending");
+            thread::sleep(Duration::from_millis(10));
+            for i in 0..4 {
+                tx.unbounded_send(i).unwrap();
+            }
+            drop(tx);
+            assert_eq!(done_rx.wait().unwrap(), 6);
+        });
+
+        let sum = rx.fold(0, |a, b| Ok::<_, ()>(a + b))
+            .traced("sum")
+            .wait()
+            .unwrap();
+        done_tx.send(sum).unwrap();
+        sender.join().unwrap();
+    }
+    if cfg!(feature = "disabled") {
+        return;
+    }
+
+    let tree = logger.tree();
+    tree.span("sending").marks("send", 4);
+    tree.span("test_channels").marks("send", 1);
+    tree.span("sum")
+        .outcome(AsyncOutcome::Success)
+        .marks("recv", 4)
+        .woken_by("sending");
+}
+
+// A send that lands after the receiver finds the channel empty, but before
+// it returns `NotReady`, must still wake the span it's parked in.
+#[test]
+#[cfg(not(feature = "disabled"))]
+fn test_channel_send_while_polling() {
+    let logger = RecordingLogger::new();
+    {
+        let _thread = TracedThread::new("test_channel_send_while_polling", Box::new(logger.clone()));
+        let link = Arc::new(channel::Link::new("race".to_string()));
+        let _span = SyncSpan::new("receiving");
+        let link_ = link.clone();
+        let logger_ = logger.clone();
+        let result: Poll<Option<usize>, ()> = channel::mpsc::received(&link, move || {
+            thread::spawn(move || {
+                let _thread = TracedThread::new("test_channel_send_while_polling:sender", Box::new(logger_));
+                let _span = SyncSpan::new("sending");
+                link_.send(|| Ok::<(), ()>(()), Result::is_ok).unwrap();
+            }).join().unwrap();
+            Ok(Async::NotReady)
+        });
+        assert_eq!(result, Ok(Async::NotReady));
+    }
+
+    logger.tree().span("receiving").woken_by("sending");
+    assert_valid(&logger);
+}
+
+#[test]
+fn test_filter() {
+    let filter = Filter::parse("rpc, db,-db::ping");
+    assert!(filter.matches("rpc::call"));
+    assert!(filter.matches("db::query"));
+    assert!(!filter.matches("db::ping"));
+    assert!(!filter.matches("http"));
+    assert!(Filter::new().matches("anything"));
+}
+
+#[cfg(not(feature = "disabled"))]
+#[test]
+fn test_filter_spans() {
+    // Only exclude a name nothing else uses, since the filter is process-wide.
+    ::filter::set_filter(Some(Filter::new().exclude("test_filter:hidden")));
+    let logger = RecordingLogger::new();
+    {
+        let _thread = TracedThread::new("test_filter", Box::new(logger.clone()));
+        {
+            let hidden = SyncSpan::new("test_filter:hidden");
+            assert!(hidden.id().is_none());
+            let result = future::ok::<_, ()>(1).traced("async child").wait();
+            assert_eq!(result, Ok(1));
+            let child = SyncSpan::new("child");
+            assert!(child.id().is_some());
+        }
+        let result = future::ok::<_, ()>(1)
+            .traced("test_filter:hidden")
+            .map(|x| x + 1)
+            .traced("shown")
+            .wait();
+        assert_eq!(result, Ok(2));
+    }
+    ::filter::set_filter(None);
+
+    // Children of hidden spans go to the nearest span that was recorded.
+    let tree = logger.tree();
+    let thread = tree.span("test_filter").lacks_child("test_filter:hidden");
+    thread.has_child("child").closed();
+    thread.has_child("async child").outcome(AsyncOutcome::Success);
+    thread.has_child("shown").outcome(AsyncOutcome::Success).child_count(0);
+    assert_valid(&logger);
+}
+
+#[cfg(feature = "disabled")]
+#[test]
+fn test_disabled() {
+    use std::mem;
+    use TracedFuture;
+
+    let _thread = TracedThread::new("test_disabled", Box::new(DebugLogger));
+    assert!(SyncSpan::new("span").id().is_none());
+    assert_eq!(mem::size_of::<TracedFuture<future::FutureResult<u64, ()>>>(),
+               mem::size_of::<future::FutureResult<u64, ()>>());
+    assert_eq!(future::ok::<_, ()>(1).traced("one").wait(), Ok(1));
+}
+
+#[cfg(not(feature = "disabled"))]
+#[test]
+fn test_recording() {
+    let logger = RecordingLogger::new();
+    let thread = TracedThread::new("test_recording", Box::new(logger.clone()));
+
+    let (tx, rx) = oneshot::channel::<usize>();
+    let okay = future::ok::<usize, usize>(10).traced("okay");
+    let not_okay = future::err::<usize, usize>(11).traced("not okay");
+    let rx = rx.map_err(|_| 0).traced("rx");
+    let tx = future::lazy(move || tx.send(1).map_err(|_| 0)).traced("tx");
+    let result = okay.join(not_okay.then(|_| Ok(12)))
+        .traced("join")
+        .join(rx.join(tx))
+        .traced("root")
+        .wait();
+    assert_eq!(result, Ok(((10, 12), (1, ()))));
+    drop(thread);
+
+    let tree = logger.tree();
+    let root = tree.span("test_recording")
+        .kind(SpanKind::Thread)
+        .closed()
+        .has_child("root")
+        .kind(SpanKind::Async)
+        .outcome(AsyncOutcome::Success)
+        .child_count(3);
+    root.has_child("join")
+        .polls(1)
+        .child_count(2)
+        .has_child("not okay")
+        .outcome(AsyncOutcome::Error("11".to_string()));
+    root.has_child("rx")
+        .polls(2)
+        .woken_by("tx")
+        .parent()
+        .lacks_child("okay");
+    assert_eq!(tree.find("okay").len(), 1);
+}
+
+#[test]
+#[should_panic(expected = "Can't rebuild spans from event 2")]
+fn test_recording_malformed() {
+    SpanTree::new(&[
+        TraceEvent::ThreadStart { name: "t".to_string(), id: SpanId(1), ts: Duration::from_millis(1) },
+        TraceEvent::SyncEnd { id: SpanId(2), ts: Duration::from_millis(2) },
+    ]);
+}
+
+#[test]
+fn test_validate() {
+    let trace = r#"{"ThreadStart":{"name":"a","id":1,"ts":{"secs":1,"nanos":0}}}
+{"ThreadStart":{"name":"a","id":2,"ts":{"secs":1,"nanos":0}}}
+{"AsyncStart":{"name":"x","id":3,"parent_id":1,"ts":{"secs":2,"nanos":0},"metadata":null}}
+{"AsyncOnCPU":{"id":3,"ts":{"secs":2,"nanos":0}}}
+{"AsyncOnCPU":{"id":3,"ts":{"secs":1,"nanos":0}}}
+{"SyncStart":{"name":"y","id":4,"parent_id":9,"ts":{"secs":3,"nanos":0},"metadata":null}}
+not json
+{"SyncEnd":{"id":4,"ts":{"secs":3,"nanos":0}}}
+{"SyncEnd":{"id":4,"ts":{"secs":3,"nanos":0}}}
+{"AsyncEnd":{"id":3,"ts":{"secs":3,"nanos":0},"outcome":"Success"}}
+"#;
+    let violations: Vec<_> = validate::validate(trace.as_bytes()).unwrap()
+        .into_iter()
+        .map(|v| (v.line, v.kind))
+        .collect();
+    assert_eq!(violations[0], (2, ViolationKind::DuplicateThreadName("a".to_string())));
+    assert_eq!(violations[1], (5, ViolationKind::DoubleOnCPU(SpanId(3))));
+    assert!(matches!(violations[2], (5, ViolationKind::NonMonotonic { ref thread, .. }) if thread == "a"));
+    assert_eq!(violations[3], (6, ViolationKind::UnknownParent { id: SpanId(4), parent_id: SpanId(9) }));
+    assert!(matches!(violations[4], (7, ViolationKind::Parse(_))));
+    assert_eq!(violations[5], (9, ViolationKind::DoubleEnd(SpanId(4))));
+    assert_eq!(violations[6], (10, ViolationKind::EndOnCPU(SpanId(3))));
+    assert_eq!(violations[7], (1, ViolationKind::LeftOpen(SpanId(1))));
+    assert_eq!(violations[8], (2, ViolationKind::LeftOpen(SpanId(2))));
+    assert_eq!(violations.len(), 9);
+}
+
+#[test]
+fn test_easy_log() {
+    // Written by hand for the TypeScript frontend, which reads every number
+    // as seconds.
+    let trace = include_str!("../../examples/easy.log");
+    assert_eq!(validate::validate(trace.as_bytes()).unwrap(), vec![]);
+    let events: Vec<TraceEvent> = trace.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+    assert_eq!(events.len(), 36);
+    assert_eq!(events[3].ts(), Duration::from_secs(265));
+    assert_eq!(events[35].ts(), Duration::from_secs(2000));
+}
+
+#[cfg(not(feature = "disabled"))]
+#[test]
+fn test_validate_recorded() {
+    let logger = RecordingLogger::new();
+    {
+        let _thread = TracedThread::new("test_validate_recorded", Box::new(logger.clone()));
+        let _span = SyncSpan::new("outer");
+        let (tx, rx) = oneshot::channel::<usize>();
+        let tx = future::lazy(move || tx.send(1).map_err(|_| 0)).traced("tx");
+        rx.map_err(|_| 0).traced("rx").join(tx).traced("join").wait().unwrap();
+    }
+    assert_valid(&logger);
+}
+
+#[cfg(feature = "redact")]
+#[test]
+fn test_redact() {
+    let rules = "
+        # Keep requests from the same session together.
+        hash session
+        drop user
+        salt not-very-secret
+        replace /home/[^/]+ -> /home/someone
+    ";
+    let redactor = Redactor::parse(rules).unwrap();
+    let recording = RecordingLogger::new();
+    let mut logger = RedactingLogger::new(recording.clone(), redactor.clone());
+    let start = |id, session: &str| TraceEvent::AsyncStart {
+        name: "open /home/alice/notes".to_string(),
+        id: SpanId(id),
+        parent_id: SpanId(1),
+        ts: Duration::from_millis(id),
+        metadata: json!({ "session": session, "args": [{ "user": "alice", "path": "/home/alice" }] }),
+    };
+    logger.write(start(2, "s1"));
+    logger.write(start(3, "s1"));
+    logger.write(start(4, "s2"));
+    logger.write(TraceEvent::AsyncEnd {
+        id: SpanId(2),
+        ts: Duration::from_millis(5),
+        outcome: AsyncOutcome::Error("no such file: /home/alice/notes".to_string()),
+    });
+
+    let events = recording.events();
+    let sessions: Vec<_> = events[..3].iter()
+        .map(|e| match *e {
+            TraceEvent::AsyncStart { ref name, ref metadata, .. } => {
+                assert_eq!(name, "open /home/someone/notes");
+                assert_eq!(metadata["args"], json!([{ "path": "/home/someone" }]));
+                metadata["session"].as_str().unwrap().to_string()
+            },
+            ref e => panic!("unexpected {:?}", e),
+        })
+        .collect();
+    assert_eq!(sessions[0], sessions[1]);
+    assert_ne!(sessions[0], sessions[2]);
+    assert!(!sessions[0].contains("s1"));
+    assert_eq!(events[3], TraceEvent::AsyncEnd {
+        id: SpanId(2),
+        ts: Duration::from_millis(5),
+        outcome: AsyncOutcome::Error("no such file: /home/someone/notes".to_string()),
+    });
+
+    // A different salt gives different hashes.
+    let other = Redactor::new().hash_key("session").salt("other");
+    match other.redact(start(2, "s1")) {
+        TraceEvent::AsyncStart { ref metadata, .. } => assert_ne!(metadata["session"], json!(sessions[0])),
+        ref e => panic!("unexpected {:?}", e),
+    }
+
+    let error = Redactor::parse("drop a\nreplace [ -> x").unwrap_err();
+    assert_eq!(error.line, 2);
+    assert!(Redactor::parse("frobnicate x").is_err());
+}
+
+#[test]
+fn test_json_string_ids() {
+    let path = "/tmp/test_json_string_ids.log";
+    let mut logger = JsonWriter::with_format(File::create(path).unwrap(), JsonFormat::version(2).unwrap());
+    let id = SpanId(u64::MAX);
+    logger.write(TraceEvent::ThreadStart { name: "t".to_string(), id, ts: Duration::from_millis(1) });
+    logger.write(TraceEvent::ThreadEnd { id, ts: Duration::from_millis(2) });
+    logger.flush();
+
+    let written = ::std::fs::read_to_string(path).unwrap();
+    assert!(written.contains(&format!("\"id\":\"{}\"", u64::MAX)), "{}", written);
+    assert!(validate::validate(written.as_bytes()).unwrap().is_empty());
+}
+
+#[test]
+fn test_spawned_by_string() {
+    let logger = RecordingLogger::new();
+    let spawner = SpanId((1 << 53) + 1);
+    {
+        let _thread = TracedThread::new("test_spawned_by_string", Box::new(logger.clone()));
+        TracedFuture::spawned(future::ok::<(), ()>(()), "task", Some(spawner)).wait().unwrap();
+    }
+    if cfg!(feature = "disabled") {
+        return;
+    }
+
+    let start = logger.events().into_iter()
+        .find(|e| matches!(*e, TraceEvent::AsyncStart { .. }))
+        .unwrap();
+    let line = serde_json::to_string(&start).unwrap();
+    let metadata = match serde_json::from_str(&line).unwrap() {
+        TraceEvent::AsyncStart { metadata, .. } => metadata,
+        e => panic!("unexpected event {:?}", e),
+    };
+    assert_eq!(metadata["spawned_by"], json!("9007199254740993"));
+    let id: SpanId = serde_json::from_value(metadata["spawned_by"].clone()).unwrap();
+    assert_eq!(id, spawner);
+}
@@ -0,0 +1,323 @@
+//! Streaming well-formedness checks for traces.  Feed events to a
+//! `Validator` one at a time, draining violations as they're found, then call
+//! `finish` to report anything left dangling at the end of the trace.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{self, BufRead};
+use std::time::Duration;
+use std::vec;
+use serde_json;
+use event::{SpanId, TraceEvent};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Kind {
+    Thread,
+    Async,
+    Sync,
+    Idle,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ViolationKind {
+    /// The line isn't a valid event.
+    Parse(String),
+    /// A span was started under a parent that was never started.
+    UnknownParent { id: SpanId, parent_id: SpanId },
+    /// An event refers to a span that was never started.
+    UnknownSpan(SpanId),
+    DoubleStart(SpanId),
+    DoubleEnd(SpanId),
+    /// An event for a span that has already ended.
+    AfterEnd(SpanId),
+    /// The end event doesn't match how the span was started.
+    WrongKind(SpanId),
+    /// `AsyncOnCPU` for a span that is already on-CPU.
+    DoubleOnCPU(SpanId),
+    /// `AsyncOffCPU` for a span that isn't on-CPU.
+    OffCPUWithoutOnCPU(SpanId),
+    /// `AsyncEnd` for a span that is still on-CPU.
+    EndOnCPU(SpanId),
+    /// A sync span can only be scheduled once, when it starts.
+    SyncRescheduled(SpanId),
+    /// An event is timestamped before the previous event on its thread.
+    NonMonotonic { thread: String, previous: Duration, ts: Duration },
+    DuplicateThreadName(String),
+    /// A span never ended.  Reported against the line that started it.
+    LeftOpen(SpanId),
+}
+
+impl fmt::Display for ViolationKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::ViolationKind::*;
+        match *self {
+            Parse(ref e) => write!(f, "parse error: {}", e),
+            UnknownParent { id, parent_id } => write!(f, "span {} has unknown parent {}", id.0, parent_id.0),
+            UnknownSpan(id) => write!(f, "unknown span {}", id.0),
+            DoubleStart(id) => write!(f, "span {} started twice", id.0),
+            DoubleEnd(id) => write!(f, "span {} ended twice", id.0),
+            AfterEnd(id) => write!(f, "event for span {} after it ended", id.0),
+            WrongKind(id) => write!(f, "span {} ended with the wrong kind of event", id.0),
+            DoubleOnCPU(id) => write!(f, "span {} went on-CPU while already on-CPU", id.0),
+            OffCPUWithoutOnCPU(id) => write!(f, "span {} went off-CPU without being on-CPU", id.0),
+            EndOnCPU(id) => write!(f, "span {} ended while on-CPU", id.0),
+            SyncRescheduled(id) => write!(f, "sync span {} scheduled more than once", id.0),
+            NonMonotonic { ref thread, previous, ts } => {
+                write!(f, "timestamp {:?} on thread {:?} is before previous {:?}", ts, thread, previous)
+            },
+            DuplicateThreadName(ref name) => write!(f, "duplicate thread name {:?}", name),
+            LeftOpen(id) => write!(f, "span {} never ended", id.0),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Violation {
+    /// 1-based line number of the offending event.
+    pub line: usize,
+    pub kind: ViolationKind,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
+
+struct SpanState {
+    kind: Kind,
+    thread: SpanId,
+    line: usize,
+    on_cpu: bool,
+    ended: bool,
+}
+
+struct ThreadState {
+    name: String,
+    last_ts: Duration,
+}
+
+#[derive(Default)]
+pub struct Validator {
+    spans: HashMap<SpanId, SpanState>,
+    threads: HashMap<SpanId, ThreadState>,
+    thread_names: HashSet<String>,
+    violations: Vec<Violation>,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn report(&mut self, line: usize, kind: ViolationKind) {
+        self.violations.push(Violation { line, kind });
+    }
+
+    /// Violations found so far that haven't been drained yet.
+    pub fn drain(&mut self) -> vec::Drain<'_, Violation> {
+        self.violations.drain(..)
+    }
+
+    pub fn parse_error<E: fmt::Display>(&mut self, line: usize, error: &E) {
+        self.report(line, ViolationKind::Parse(error.to_string()));
+    }
+
+    fn tick(&mut self, line: usize, thread: SpanId, ts: Duration) {
+        let violation = match self.threads.get_mut(&thread) {
+            Some(ref mut t) if ts < t.last_ts => {
+                Some(ViolationKind::NonMonotonic { thread: t.name.clone(), previous: t.last_ts, ts })
+            },
+            Some(ref mut t) => {
+                t.last_ts = ts;
+                None
+            },
+            None => None,
+        };
+        if let Some(kind) = violation {
+            self.report(line, kind);
+        }
+    }
+
+    fn start(&mut self, line: usize, id: SpanId, kind: Kind, parent_id: SpanId, ts: Duration) {
+        let thread = match self.spans.get(&parent_id).map(|p| (p.thread, p.ended)) {
+            Some((thread, ended)) => {
+                if ended {
+                    self.report(line, ViolationKind::AfterEnd(parent_id));
+                }
+                thread
+            },
+            None => {
+                self.report(line, ViolationKind::UnknownParent { id, parent_id });
+                parent_id
+            },
+        };
+        if self.spans.contains_key(&id) {
+            self.report(line, ViolationKind::DoubleStart(id));
+            return;
+        }
+        self.tick(line, thread, ts);
+        self.spans.insert(id, SpanState {
+            kind,
+            thread,
+            line,
+            on_cpu: kind == Kind::Sync,
+            ended: false,
+        });
+    }
+
+    // Look up a live span, reporting if it's unknown or already ended.
+    fn live(&mut self, line: usize, id: SpanId) -> Option<&mut SpanState> {
+        let violation = match self.spans.get(&id) {
+            None => ViolationKind::UnknownSpan(id),
+            Some(span) if span.ended => ViolationKind::AfterEnd(id),
+            Some(_) => return self.spans.get_mut(&id),
+        };
+        self.report(line, violation);
+        None
+    }
+
+    fn end(&mut self, line: usize, id: SpanId, kind: Kind, ts: Duration) {
+        let violation = match self.spans.get_mut(&id) {
+            None => Some(ViolationKind::UnknownSpan(id)),
+            Some(ref span) if span.ended => Some(ViolationKind::DoubleEnd(id)),
+            Some(ref mut span) => {
+                span.ended = true;
+                if span.kind != kind {
+                    Some(ViolationKind::WrongKind(id))
+                } else if span.kind == Kind::Async && span.on_cpu {
+                    Some(ViolationKind::EndOnCPU(id))
+                } else {
+                    None
+                }
+            },
+        };
+        if let Some(kind) = violation {
+            self.report(line, kind);
+        }
+        if let Some(thread) = self.spans.get(&id).map(|s| s.thread) {
+            self.tick(line, thread, ts);
+        }
+    }
+
+    /// Check the event on (1-based) line `line`.
+    pub fn event(&mut self, line: usize, event: &TraceEvent) {
+        match *event {
+            TraceEvent::ThreadStart { ref name, id, ts } => {
+                if !self.thread_names.insert(name.clone()) {
+                    self.report(line, ViolationKind::DuplicateThreadName(name.clone()));
+                }
+                if self.spans.contains_key(&id) {
+                    self.report(line, ViolationKind::DoubleStart(id));
+                    return;
+                }
+                self.spans.insert(id, SpanState {
+                    kind: Kind::Thread,
+                    thread: id,
+                    line,
+                    on_cpu: false,
+                    ended: false,
+                });
+                self.threads.insert(id, ThreadState { name: name.clone(), last_ts: ts });
+            },
+            TraceEvent::AsyncStart { id, parent_id, ts, .. } => {
+                self.start(line, id, Kind::Async, parent_id, ts);
+            },
+            TraceEvent::SyncStart { id, parent_id, ts, .. } => {
+                self.start(line, id, Kind::Sync, parent_id, ts);
+            },
+            TraceEvent::IdleStart { id, parent_id, ts } => {
+                self.start(line, id, Kind::Idle, parent_id, ts);
+            },
+            TraceEvent::AsyncOnCPU { id, ts } => {
+                let (violation, thread) = match self.live(line, id) {
+                    Some(span) => {
+                        let violation = if span.kind == Kind::Sync {
+                            Some(ViolationKind::SyncRescheduled(id))
+                        } else if span.on_cpu {
+                            Some(ViolationKind::DoubleOnCPU(id))
+                        } else {
+                            None
+                        };
+                        span.on_cpu = true;
+                        (violation, span.thread)
+                    },
+                    None => return,
+                };
+                if let Some(kind) = violation {
+                    self.report(line, kind);
+                }
+                self.tick(line, thread, ts);
+            },
+            TraceEvent::AsyncOffCPU { id, ts } => {
+                let (violation, thread) = match self.live(line, id) {
+                    Some(span) => {
+                        let violation = if span.kind == Kind::Sync {
+                            Some(ViolationKind::SyncRescheduled(id))
+                        } else if !span.on_cpu {
+                            Some(ViolationKind::OffCPUWithoutOnCPU(id))
+                        } else {
+                            None
+                        };
+                        span.on_cpu = false;
+                        (violation, span.thread)
+                    },
+                    None => return,
+                };
+                if let Some(kind) = violation {
+                    self.report(line, kind);
+                }
+                self.tick(line, thread, ts);
+            },
+            TraceEvent::AsyncEnd { id, ts, .. } => self.end(line, id, Kind::Async, ts),
+            TraceEvent::SyncEnd { id, ts } => self.end(line, id, Kind::Sync, ts),
+            TraceEvent::ThreadEnd { id, ts } => self.end(line, id, Kind::Thread, ts),
+            TraceEvent::IdleEnd { id, ts } => self.end(line, id, Kind::Idle, ts),
+            TraceEvent::Wakeup { waking_span, parked_span, .. } => {
+                // Wakeups may be logged from either the waking or the parked
+                // thread, so their timestamps aren't checked.
+                if !self.spans.contains_key(&waking_span) {
+                    self.report(line, ViolationKind::UnknownSpan(waking_span));
+                }
+                if !self.spans.contains_key(&parked_span) {
+                    self.report(line, ViolationKind::UnknownSpan(parked_span));
+                }
+            },
+            TraceEvent::Mark { id, ts, .. } => {
+                if let Some(thread) = self.live(line, id).map(|s| s.thread) {
+                    self.tick(line, thread, ts);
+                }
+            },
+        }
+    }
+
+    /// Report spans that were never closed, and return all outstanding
+    /// violations.
+    pub fn finish(mut self) -> Vec<Violation> {
+        let mut open: Vec<_> = self.spans.iter()
+            .filter(|&(_, s)| !s.ended)
+            .map(|(&id, s)| (s.line, id))
+            .collect();
+        open.sort();
+        for (line, id) in open {
+            self.report(line, ViolationKind::LeftOpen(id));
+        }
+        self.violations
+    }
+}
+
+/// Validate a newline-delimited JSON trace, returning every violation found.
+pub fn validate<R: BufRead>(reader: R) -> io::Result<Vec<Violation>> {
+    let mut validator = Validator::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(event) => validator.event(i + 1, &event),
+            Err(e) => validator.parse_error(i + 1, &e),
+        }
+    }
+    Ok(validator.finish())
+}
@@ -0,0 +1,41 @@
+//! `set_enabled` switches recording off for the whole process, so it gets a
+//! test binary of its own rather than racing the tests in `src/tests.rs`.
+
+extern crate cyclotron_backend;
+extern crate futures;
+
+use futures::{future, Future};
+use cyclotron_backend::{filter, SyncSpan, TraceFuture, TracedThread};
+use cyclotron_backend::recording::RecordingLogger;
+
+#[test]
+fn test_set_enabled() {
+    let logger = RecordingLogger::new();
+    {
+        let _thread = TracedThread::new("test_set_enabled", Box::new(logger.clone()));
+        {
+            let open = SyncSpan::new("open");
+            filter::set_enabled(false);
+            assert!(!filter::is_enabled());
+            assert!(SyncSpan::new("off").id().is_none());
+            assert_eq!(future::ok::<_, ()>(1).traced("off async").wait(), Ok(1));
+            assert_eq!(open.id().is_some(), !cfg!(feature = "disabled"));
+        }
+        filter::set_enabled(true);
+        let _on = SyncSpan::new("on");
+    }
+    if cfg!(feature = "disabled") {
+        return;
+    }
+
+    // Spans open at the switch are still closed, and the thread's recorded
+    // throughout.
+    let tree = logger.tree();
+    tree.span("test_set_enabled")
+        .closed()
+        .lacks_child("off")
+        .lacks_child("off async")
+        .child_count(2);
+    tree.span("open").closed();
+    tree.span("on").closed();
+}
@@ -0,0 +1,63 @@
+use std::io::{self, Write};
+use cyclotron_model::diff::{PathDiff, Summary};
+use ms;
+use table;
+
+// "before -> after (+x%)", or just one side if the path is new or gone.
+fn change(before: Option<f64>, after: Option<f64>, precision: usize) -> String {
+    match (before, after) {
+        (Some(b), Some(a)) if b > 0.0 && a != b => {
+            format!("{:.*} -> {:.*} ({:+.0}%)", precision, b, precision, a, 100.0 * (a - b) / b)
+        },
+        (Some(b), Some(a)) if a != b => format!("{:.*} -> {:.*}", precision, b, precision, a),
+        (Some(b), Some(_)) => format!("{:.*}", precision, b),
+        (Some(b), None) => format!("{:.*} -> -", precision, b),
+        (None, Some(a)) => format!("- -> {:.*}", precision, a),
+        (None, None) => String::new(),
+    }
+}
+
+// The numbers compared for each path: count, p50 and p99 latency, and CPU
+// time, in milliseconds.
+fn numbers(summary: Summary) -> [f64; 4] {
+    [summary.count as f64, ms(summary.latency.p50), ms(summary.latency.p99), ms(summary.cpu_time)]
+}
+
+// Largest relative change in any of the compared numbers, in percent.
+fn largest_change(diff: &PathDiff) -> f64 {
+    let (b, a) = match (diff.baseline, diff.candidate) {
+        (Some(b), Some(a)) => (numbers(b), numbers(a)),
+        _ => return f64::INFINITY,
+    };
+    b.iter().zip(&a)
+        .map(|(&b, &a)| if b > 0.0 {
+            100.0 * (a - b).abs() / b
+        } else if a > 0.0 {
+            f64::INFINITY
+        } else {
+            0.0
+        })
+        .fold(0.0, f64::max)
+}
+
+/// Print paths whose numbers changed by at least `threshold` percent, and
+/// every path that is new or gone.
+pub fn print<W: Write>(out: &mut W, diffs: &[PathDiff], threshold: f64) -> io::Result<()> {
+    let mut rows = vec![
+        ["", "path", "count", "p50 (ms)", "p99 (ms)", "cpu (ms)"].iter().map(|h| h.to_string()).collect(),
+    ];
+    for diff in diffs {
+        if largest_change(diff) < threshold {
+            continue;
+        }
+        let marker = if diff.is_new() { "+" } else if diff.is_gone() { "-" } else { " " };
+        let (b, a) = (diff.baseline.map(numbers), diff.candidate.map(numbers));
+        let mut row = vec![marker.to_string(), diff.path.clone()];
+        for i in 0..4 {
+            let precision = if i == 0 { 0 } else { 3 };
+            row.push(change(b.map(|b| b[i]), a.map(|a| a[i]), precision));
+        }
+        rows.push(row);
+    }
+    table::print(out, &rows, 2)
+}
@@ -0,0 +1,161 @@
+use std::io::{
+    self,
+    BufRead,
+    Read,
+    Write,
+};
+use std::thread;
+use std::time::Duration;
+use cyclotron_model::TraceEvent;
+use cyclotron_model::binary::{self, MAGIC};
+use cyclotron_model::json::JsonFormat;
+use serde_json;
+
+#[derive(Debug, Deserialize)]
+pub enum Output {
+    Json,
+    Binary,
+    Chrome,
+}
+
+const POLL_INTERVAL_MS: u64 = 200;
+
+/// Reads events from a JSON-lines or binary trace, telling them apart by the
+/// binary header.  Each item is the line (or record) number and the event, or
+/// why it couldn't be parsed.
+pub struct Events {
+    reader: Box<dyn BufRead>,
+    binary: bool,
+    // Wait for more to be written at the end of the input, like `tail -f`.
+    follow: bool,
+    record: usize,
+    buf: Vec<u8>,
+}
+
+impl Events {
+    pub fn new(mut reader: Box<dyn BufRead>, follow: bool) -> io::Result<Self> {
+        // When following, an empty (or partly written) header could still
+        // be either format, so wait for enough of it to tell.
+        let mut header = vec![];
+        while header.len() < MAGIC.len() {
+            let n = reader.by_ref().take((MAGIC.len() - header.len()) as u64).read_to_end(&mut header)?;
+            if !MAGIC.starts_with(&header) {
+                break;
+            }
+            if n == 0 {
+                if !follow {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+            }
+        }
+        let binary = header == MAGIC;
+        let buf = if binary { vec![] } else { header };
+        Ok(Events { reader, binary, follow, record: 0, buf })
+    }
+
+    // Read until `buf` ends with a newline (JSON) or holds a whole record
+    // (binary).  Returns false at the end of the input.
+    fn fill(&mut self) -> io::Result<bool> {
+        loop {
+            let n = if self.binary {
+                let want = self.record_len().unwrap_or(4) - self.buf.len();
+                self.reader.by_ref().take(want as u64).read_to_end(&mut self.buf)?
+            } else {
+                self.reader.read_until(b'\n', &mut self.buf)?
+            };
+            let complete = if self.binary {
+                self.record_len().is_some_and(|len| self.buf.len() >= len)
+            } else {
+                self.buf.ends_with(b"\n")
+            };
+            if complete {
+                return Ok(true);
+            }
+            if n > 0 {
+                continue;
+            }
+            if !self.follow {
+                return Ok(!self.buf.is_empty());
+            }
+            thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+        }
+    }
+
+    fn record_len(&self) -> Option<usize> {
+        if self.buf.len() < 4 {
+            return None;
+        }
+        let len = self.buf[..4].iter().rev().fold(0, |n, &b| (n << 8) | b as usize);
+        Some(4 + len)
+    }
+
+    fn parse(&self) -> Result<TraceEvent, String> {
+        if self.binary {
+            match binary::decode(&self.buf) {
+                Some((event, _)) => event.map_err(|e| e.to_string()),
+                None => Err("truncated record".to_string()),
+            }
+        } else {
+            serde_json::from_slice(&self.buf).map_err(|e| e.to_string())
+        }
+    }
+}
+
+impl Iterator for Events {
+    type Item = io::Result<(usize, Result<TraceEvent, String>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.fill() {
+                Ok(true) => (),
+                Ok(false) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+            self.record += 1;
+            let blank = !self.binary && self.buf.iter().all(|b| b.is_ascii_whitespace());
+            let event = if blank { None } else { Some(self.parse()) };
+            self.buf.clear();
+            if let Some(event) = event {
+                return Some(Ok((self.record, event)));
+            }
+        }
+    }
+}
+
+/// Writes a stream of events as JSON lines in the given format, or in the
+/// binary format if there isn't one.
+pub struct Writer<W: Write> {
+    out: W,
+    json: Option<JsonFormat>,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(mut out: W, json: Option<JsonFormat>) -> io::Result<Self> {
+        if json.is_none() {
+            out.write_all(MAGIC)?;
+        }
+        Ok(Writer { out, json, buf: vec![] })
+    }
+
+    pub fn write(&mut self, event: &TraceEvent) -> io::Result<()> {
+        self.buf.clear();
+        match self.json {
+            Some(ref format) => {
+                if *format == JsonFormat::default() {
+                    serde_json::to_writer(&mut self.buf, event)?;
+                } else {
+                    serde_json::to_writer(&mut self.buf, &format.to_value(event))?;
+                }
+                self.buf.push(b'\n');
+            },
+            None => binary::encode(event, &mut self.buf),
+        }
+        self.out.write_all(&self.buf)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
@@ -0,0 +1,441 @@
+extern crate cyclotron_backend;
+extern crate cyclotron_model;
+extern crate docopt;
+#[macro_use]
+extern crate serde_derive;
+extern crate failure;
+#[macro_use]
+extern crate serde_json;
+
+mod diff;
+mod format;
+mod stats;
+mod table;
+mod tail;
+#[cfg(test)]
+mod tests;
+
+use std::fs::{self, File};
+use std::io::{
+    self,
+    BufRead,
+    BufReader,
+    BufWriter,
+    Write,
+};
+use std::process;
+use std::time::Duration;
+use cyclotron_backend::redact::Redactor;
+use cyclotron_backend::validate::Validator;
+use cyclotron_model::{SpanId, Trace, TraceEvent};
+use cyclotron_model::chrome::chrome;
+use cyclotron_model::critical_path::critical_path;
+use cyclotron_model::diff::diff;
+use cyclotron_model::folded::{folded, Weight};
+use cyclotron_model::json::JsonFormat;
+use cyclotron_model::slice::{slice, Selection};
+use cyclotron_model::stats::stats;
+use docopt::Docopt;
+use failure::Error;
+use failure::err_msg;
+use format::{Events, Output, Writer};
+
+const USAGE: &str = "
+Cyclotron trace tools.
+
+Usage:
+   cyclotron validate [<trace>]
+   cyclotron critical-path (--span=<id> | --name=<name>) [<trace>]
+   cyclotron stats [--group-by=<key>] [--format=<fmt>] [<trace>]
+   cyclotron folded [--wall] [<trace>]
+   cyclotron diff [--threshold=<pct>] <baseline> <candidate>
+   cyclotron convert [--to=<fmt>] [--format-version=<n>] [<trace>]
+   cyclotron slice (--span=<id> | --start=<ms> --end=<ms>) [--to=<fmt>] [--format-version=<n>] [<trace>]
+   cyclotron merge [--to=<fmt>] [--format-version=<n>] <traces>...
+   cyclotron redact --rules=<file> [--to=<fmt>] [--format-version=<n>] [<trace>]
+   cyclotron tail [--follow] [<trace>]
+   cyclotron (-h | --help)
+
+Commands:
+   validate        Check that a trace is well-formed, printing each violation.
+   critical-path   Show the chain of work that determined a span's latency.
+   stats           Latency and CPU statistics per span name.
+   folded          Collapsed stacks for flame graphs, weighted by on-CPU
+                   microseconds.
+   diff            Compare spans between two runs, matched by thread and
+                   ancestor names.  New paths are marked +, gone ones -.
+   convert         Rewrite a trace as JSON lines, in the compact binary
+                   format, or as Chrome trace events for chrome://tracing
+                   and Perfetto.
+   slice           Cut out the subtree under a span, or a window of time,
+                   as a trace of its own.  Spans still running at the end are
+                   closed there.
+   merge           Interleave several traces by timestamp, such as the logs
+                   of processes that ran together.  The backend's timestamps
+                   count from when each process started tracing, so traces
+                   from different processes only line up if they started
+                   together.
+   redact          Drop, hash or rewrite sensitive names and metadata, as
+                   set out in a rules file (see `Redactor::parse` in the
+                   backend).
+   tail            Pretty-print a trace's events as they happen.
+
+Options:
+  -h --help          Show this screen.
+  --span=<id>        Span to analyze or slice, by id in decimal or 0x hex.
+  --start=<ms>       Start of the window, in milliseconds of trace time.
+  --end=<ms>         End of the window.
+  --name=<name>      Analyze the slowest span with this name.
+  --group-by=<key>   Split statistics by the value of a metadata key.
+  --format=<fmt>     Output as table, csv or json [default: table].
+  --wall             Weight stacks by wall-clock time instead.
+  --threshold=<pct>  Hide paths that changed by less than this [default: 0].
+  --to=<fmt>         Write json, binary or chrome [default: json].
+  --format-version=<n>  JSON version to write: 1 has numeric ids, 2 has ids
+                     as strings, for JavaScript, and 3 also has timestamps
+                     in integer nanoseconds [default: 1].
+  --rules=<file>     Redaction rules, one per line.
+  -f --follow        Keep waiting for events appended to the trace.
+
+Traces are read from stdin if no path is given, and may be JSON lines or
+binary.
+";
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    cmd_validate: bool,
+    cmd_critical_path: bool,
+    arg_trace: Option<String>,
+    flag_span: Option<String>,
+    flag_name: Option<String>,
+    cmd_stats: bool,
+    flag_group_by: Option<String>,
+    flag_format: stats::Format,
+    cmd_folded: bool,
+    flag_wall: bool,
+    cmd_diff: bool,
+    arg_baseline: String,
+    arg_candidate: String,
+    flag_threshold: f64,
+    cmd_convert: bool,
+    flag_to: Output,
+    flag_format_version: u32,
+    cmd_slice: bool,
+    flag_start: Option<f64>,
+    flag_end: Option<f64>,
+    cmd_merge: bool,
+    arg_traces: Vec<String>,
+    cmd_redact: bool,
+    flag_rules: String,
+    cmd_tail: bool,
+    flag_follow: bool,
+}
+
+fn open(path: &Option<String>) -> Result<Box<dyn BufRead>, Error> {
+    Ok(match *path {
+        Some(ref path) => Box::new(BufReader::new(File::open(path)?)),
+        None => Box::new(BufReader::new(io::stdin())),
+    })
+}
+
+fn events(path: &Option<String>, follow: bool) -> Result<Events, Error> {
+    Ok(Events::new(open(path)?, follow)?)
+}
+
+// Span ids are read the way traces give them: as a number, or a decimal or
+// `0x`-prefixed hex string.
+fn span_id(id: &str) -> Result<SpanId, Error> {
+    serde_json::from_str(id)
+        .or_else(|_| serde_json::from_value(serde_json::Value::String(id.to_string())))
+        .map_err(|e| err_msg(format!("bad span id {:?}: {}", id, e)))
+}
+
+// Load a whole trace, skipping (and warning about) events that don't parse.
+fn read_trace(path: &Option<String>) -> Result<Trace, Error> {
+    let mut trace = Trace::new();
+    for item in events(path, false)? {
+        let (record, event) = item?;
+        match event {
+            Ok(event) => {
+                if let Err(e) = trace.add_event(event) {
+                    eprintln!("record {}: {}", record, e);
+                }
+            },
+            Err(e) => eprintln!("record {}: {}", record, e),
+        }
+    }
+    Ok(trace)
+}
+
+fn ms(d: Duration) -> f64 {
+    d.as_secs() as f64 * 1e3 + f64::from(d.subsec_nanos()) * 1e-6
+}
+
+// Returns the number of violations found.
+fn validate(args: &Args) -> Result<usize, Error> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut validator = Validator::new();
+    let mut count = 0;
+    for item in events(&args.arg_trace, false)? {
+        match item? {
+            (record, Ok(event)) => validator.event(record, &event),
+            (record, Err(e)) => validator.parse_error(record, &e),
+        }
+        for violation in validator.drain() {
+            writeln!(out, "{}", violation)?;
+            count += 1;
+        }
+    }
+    for violation in validator.finish() {
+        writeln!(out, "{}", violation)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn print_critical_path(args: &Args) -> Result<(), Error> {
+    let trace = read_trace(&args.arg_trace)?;
+    let now = trace.end_time();
+    let span = args.flag_span.as_ref().map(|id| span_id(id)).transpose()?;
+    let root = match (span, args.flag_name.as_ref()) {
+        (Some(id), _) => id,
+        (None, Some(name)) => {
+            trace.find(name).into_iter()
+                .max_by_key(|s| s.end_or(now) - s.start)
+                .map(|s| s.id)
+                .ok_or_else(|| err_msg(format!("no span named {:?}", name)))?
+        },
+        (None, None) => unreachable!(),
+    };
+    let path = critical_path(&trace, root).ok_or_else(|| err_msg(format!("no span {}", root.0)))?;
+    let name = |id: SpanId| trace.get(id).map(|s| s.name.as_str()).unwrap_or("?");
+
+    let start = trace.get(root).unwrap().start;
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    writeln!(out, "Critical path of {:?} ({}), {:.3}ms:", name(root), root.0, ms(path.duration()))?;
+    writeln!(out, "{:>12} {:>12}  {:<8}  span", "start (ms)", "time (ms)", "state")?;
+    for segment in &path.segments {
+        let state = format!("{:?}", segment.kind);
+        writeln!(out, "{:>12.3} {:>12.3}  {:<8}  {} ({})",
+                 ms(segment.start - start), ms(segment.duration()), state, name(segment.span), segment.span.0)?;
+    }
+    writeln!(out)?;
+    writeln!(out, "{:>12} {:>6}  span", "time (ms)", "%")?;
+    let total = ms(path.duration());
+    for (id, time) in path.by_span() {
+        let percent = if total > 0.0 { 100.0 * ms(time) / total } else { 0.0 };
+        writeln!(out, "{:>12.3} {:>6.1}  {} ({})", ms(time), percent, name(id), id.0)?;
+    }
+    Ok(())
+}
+
+fn print_stats(args: &Args) -> Result<(), Error> {
+    let trace = read_trace(&args.arg_trace)?;
+    let group_by = args.flag_group_by.as_deref();
+    stats::print(&mut io::stdout().lock(), &stats(&trace, group_by), group_by.is_some(), &args.flag_format)?;
+    Ok(())
+}
+
+fn print_folded(args: &Args) -> Result<(), Error> {
+    let trace = read_trace(&args.arg_trace)?;
+    let weight = if args.flag_wall { Weight::Wall } else { Weight::Cpu };
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for (stack, micros) in folded(&trace, weight) {
+        writeln!(out, "{} {}", stack, micros)?;
+    }
+    Ok(())
+}
+
+fn print_diff(args: &Args) -> Result<(), Error> {
+    let baseline = read_trace(&Some(args.arg_baseline.clone()))?;
+    let candidate = read_trace(&Some(args.arg_candidate.clone()))?;
+    diff::print(&mut io::stdout().lock(), &diff(&baseline, &candidate), args.flag_threshold)?;
+    Ok(())
+}
+
+// Where commands write the traces they produce.  Chrome traces are built from
+// a whole trace, so commands that can write them handle that themselves.
+fn output(args: &Args) -> Result<Writer<BufWriter<io::StdoutLock<'static>>>, Error> {
+    let json = match args.flag_to {
+        Output::Json => {
+            let version = args.flag_format_version;
+            Some(JsonFormat::version(version).ok_or_else(|| err_msg(format!("no JSON format version {}", version)))?)
+        },
+        Output::Binary => None,
+        Output::Chrome => return Err(err_msg("only convert and slice write chrome traces")),
+    };
+    Ok(Writer::new(BufWriter::new(io::stdout().lock()), json)?)
+}
+
+fn print_chrome(trace: &Trace) -> Result<(), Error> {
+    // Written whole, so that a closed pipe shows up as an `io::Error`.
+    let json = serde_json::to_string(&chrome(trace))?;
+    writeln!(io::stdout().lock(), "{}", json)?;
+    Ok(())
+}
+
+fn convert(args: &Args) -> Result<(), Error> {
+    if let Output::Chrome = args.flag_to {
+        return print_chrome(&read_trace(&args.arg_trace)?);
+    }
+    copy(events(&args.arg_trace, false)?, &mut output(args)?)
+}
+
+fn copy<W: Write>(events: Events, out: &mut Writer<W>) -> Result<(), Error> {
+    for item in events {
+        match item? {
+            (_, Ok(event)) => out.write(&event)?,
+            (record, Err(e)) => eprintln!("record {}: {}", record, e),
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn millis(ms: f64) -> Duration {
+    let nanos = (ms.max(0.0) * 1e6) as u64;
+    Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+}
+
+fn print_slice(args: &Args) -> Result<(), Error> {
+    let trace = read_trace(&args.arg_trace)?;
+    let span = args.flag_span.as_ref().map(|id| span_id(id)).transpose()?;
+    let selection = match (span, args.flag_start, args.flag_end) {
+        (Some(id), _, _) => {
+            if trace.get(id).is_none() {
+                return Err(err_msg(format!("no span {}", id.0)));
+            }
+            Selection::Subtree(id)
+        },
+        (None, Some(start), Some(end)) => Selection::Window { start: millis(start), end: millis(end) },
+        _ => unreachable!(),
+    };
+    let events = slice(&trace, selection);
+
+    if let Output::Chrome = args.flag_to {
+        return print_chrome(&Trace::from_events(events));
+    }
+    let mut out = output(args)?;
+    for event in &events {
+        out.write(event)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn merge(args: &Args) -> Result<(), Error> {
+    let mut out = output(args)?;
+    let mut inputs = vec![];
+    for path in &args.arg_traces {
+        inputs.push((path.as_str(), events(&Some(path.clone()), false)?));
+    }
+    merge_into(inputs, &mut out)
+}
+
+// Write the events of each named input in timestamp order, taking them from
+// the earliest input on ties.
+fn merge_into<W: Write>(mut inputs: Vec<(&str, Events)>, out: &mut Writer<W>) -> Result<(), Error> {
+    // The next good event from each input.
+    let next = |path: &str, input: &mut Events| -> Result<Option<TraceEvent>, Error> {
+        for item in input {
+            match item? {
+                (_, Ok(event)) => return Ok(Some(event)),
+                (record, Err(e)) => eprintln!("{}: record {}: {}", path, record, e),
+            }
+        }
+        Ok(None)
+    };
+    let mut heads = vec![];
+    for &mut (path, ref mut input) in &mut inputs {
+        heads.push(next(path, input)?);
+    }
+
+    loop {
+        let earliest = heads.iter().enumerate()
+            .filter_map(|(i, head)| head.as_ref().map(|e| (e.ts(), i)))
+            .min();
+        let i = match earliest {
+            Some((_, i)) => i,
+            None => break,
+        };
+        out.write(heads[i].as_ref().unwrap())?;
+        let (path, ref mut input) = inputs[i];
+        heads[i] = next(path, input)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn redact(args: &Args) -> Result<(), Error> {
+    let rules = fs::read_to_string(&args.flag_rules)?;
+    let redactor = Redactor::parse(&rules).map_err(|e| err_msg(format!("{}: {}", args.flag_rules, e)))?;
+    let mut out = output(args)?;
+    for item in events(&args.arg_trace, false)? {
+        match item? {
+            (_, Ok(event)) => out.write(&redactor.redact(event))?,
+            (record, Err(e)) => eprintln!("record {}: {}", record, e),
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn tail(args: &Args) -> Result<(), Error> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut printer = tail::Printer::new();
+    for item in events(&args.arg_trace, args.flag_follow)? {
+        match item? {
+            (_, Ok(event)) => printer.print(&mut out, &event)?,
+            (record, Err(e)) => eprintln!("record {}: {}", record, e),
+        }
+    }
+    Ok(())
+}
+
+fn is_broken_pipe(e: &Error) -> bool {
+    e.downcast_ref::<io::Error>().map(|e| e.kind() == io::ErrorKind::BrokenPipe).unwrap_or(false)
+}
+
+fn main() {
+    let args: Args = Docopt::new(USAGE)
+        .and_then(|d| d.deserialize())
+        .unwrap_or_else(|e| e.exit());
+
+    let result = if args.cmd_validate {
+        validate(&args).map(|count| if count > 0 { 1 } else { 0 })
+    } else if args.cmd_critical_path {
+        print_critical_path(&args).map(|()| 0)
+    } else if args.cmd_stats {
+        print_stats(&args).map(|()| 0)
+    } else if args.cmd_folded {
+        print_folded(&args).map(|()| 0)
+    } else if args.cmd_diff {
+        print_diff(&args).map(|()| 0)
+    } else if args.cmd_convert {
+        convert(&args).map(|()| 0)
+    } else if args.cmd_slice {
+        print_slice(&args).map(|()| 0)
+    } else if args.cmd_merge {
+        merge(&args).map(|()| 0)
+    } else if args.cmd_redact {
+        redact(&args).map(|()| 0)
+    } else if args.cmd_tail {
+        tail(&args).map(|()| 0)
+    } else {
+        unreachable!()
+    };
+    match result {
+        Ok(code) => process::exit(code),
+        // Whatever was reading the output has gone away, as when piped into
+        // `head`, so there's no one left to tell.
+        Err(ref e) if is_broken_pipe(e) => process::exit(0),
+        Err(e) => {
+            eprintln!("cyclotron: {}", e);
+            process::exit(2);
+        },
+    }
+}
@@ -0,0 +1,92 @@
+use std::io::{self, Write};
+use std::time::Duration;
+use cyclotron_model::stats::NameStats;
+use serde_json;
+use ms;
+use table;
+
+#[derive(Debug, Deserialize)]
+pub enum Format {
+    Table,
+    Csv,
+    Json,
+}
+
+const COLUMNS: &[&str] = &[
+    "name", "group", "count", "open", "errors", "cancelled",
+    "p50_ms", "p90_ms", "p99_ms", "max_ms", "cpu_ms", "polls_per_span", "off_cpu_ms",
+];
+
+fn millis(d: Duration) -> String {
+    format!("{:.3}", ms(d))
+}
+
+fn row(stats: &NameStats) -> Vec<String> {
+    vec![
+        stats.name.clone(),
+        stats.group.clone().unwrap_or_default(),
+        stats.count.to_string(),
+        stats.open.to_string(),
+        stats.errors.to_string(),
+        stats.cancelled.to_string(),
+        millis(stats.latency.p50),
+        millis(stats.latency.p90),
+        millis(stats.latency.p99),
+        millis(stats.latency.max),
+        millis(stats.cpu_time),
+        format!("{:.2}", stats.polls_per_span()),
+        millis(stats.off_cpu_time),
+    ]
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+pub fn print<W: Write>(out: &mut W, stats: &[NameStats], grouped: bool, format: &Format) -> io::Result<()> {
+    // Without grouping the group column is always empty, so leave it out.
+    let keep = |i: usize| grouped || COLUMNS[i] != "group";
+    let header: Vec<String> = COLUMNS.iter().map(|c| c.to_string()).collect();
+    let rows: Vec<Vec<String>> = stats.iter().map(row).collect();
+    let select = |row: &[String]| -> Vec<String> {
+        row.iter().enumerate().filter(|&(i, _)| keep(i)).map(|(_, f)| f.clone()).collect()
+    };
+
+    match *format {
+        Format::Table => {
+            let rows: Vec<Vec<String>> = Some(&header).into_iter().chain(&rows).map(|r| select(r)).collect();
+            table::print(out, &rows, if grouped { 2 } else { 1 })?;
+        },
+        Format::Csv => {
+            for row in Some(&header).into_iter().chain(&rows) {
+                let fields: Vec<String> = select(row).iter().map(|f| csv_field(f)).collect();
+                writeln!(out, "{}", fields.join(","))?;
+            }
+        },
+        Format::Json => {
+            let objects: Vec<serde_json::Value> = stats.iter()
+                .map(|s| json!({
+                    "name": s.name,
+                    "group": s.group,
+                    "count": s.count,
+                    "open": s.open,
+                    "errors": s.errors,
+                    "cancelled": s.cancelled,
+                    "p50_ms": ms(s.latency.p50),
+                    "p90_ms": ms(s.latency.p90),
+                    "p99_ms": ms(s.latency.p99),
+                    "max_ms": ms(s.latency.max),
+                    "cpu_ms": ms(s.cpu_time),
+                    "polls_per_span": s.polls_per_span(),
+                    "off_cpu_ms": ms(s.off_cpu_time),
+                }))
+                .collect();
+            writeln!(out, "{}", serde_json::to_string_pretty(&objects).unwrap())?;
+        },
+    }
+    Ok(())
+}
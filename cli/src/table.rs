@@ -0,0 +1,24 @@
+use std::io::{self, Write};
+
+/// Print rows as aligned columns, left-aligning the first `left` columns
+/// and right-aligning the rest.
+pub fn print<W: Write>(out: &mut W, rows: &[Vec<String>], left: usize) -> io::Result<()> {
+    let mut widths = vec![];
+    for row in rows {
+        widths.resize(widths.len().max(row.len()), 0);
+        for (width, field) in widths.iter_mut().zip(row) {
+            *width = (*width).max(field.chars().count());
+        }
+    }
+    for row in rows {
+        let fields: Vec<String> = row.iter().enumerate()
+            .map(|(i, f)| if i < left {
+                format!("{:<1$}", f, widths[i])
+            } else {
+                format!("{:>1$}", f, widths[i])
+            })
+            .collect();
+        writeln!(out, "{}", fields.join("  ").trim_end())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::time::Duration;
+use cyclotron_model::{AsyncOutcome, SpanId, TraceEvent};
+use serde_json;
+use ms;
+
+struct Known {
+    name: String,
+    thread: String,
+    depth: usize,
+}
+
+/// Prints events one per line as they're read: the time since the first
+/// event, the thread, and what happened, indented by nesting depth.
+pub struct Printer {
+    spans: HashMap<SpanId, Known>,
+    first: Option<Duration>,
+}
+
+fn with_metadata(text: String, metadata: &serde_json::Value) -> String {
+    if metadata.is_null() {
+        text
+    } else {
+        format!("{} {}", text, metadata)
+    }
+}
+
+impl Printer {
+    pub fn new() -> Self {
+        Printer { spans: HashMap::new(), first: None }
+    }
+
+    fn name(&self, id: SpanId) -> String {
+        match self.spans.get(&id) {
+            Some(known) => known.name.clone(),
+            None => format!("#{}", id.0),
+        }
+    }
+
+    fn start(&mut self, id: SpanId, parent_id: Option<SpanId>, name: &str) {
+        let (thread, depth) = match parent_id.and_then(|p| self.spans.get(&p)) {
+            Some(parent) => (parent.thread.clone(), parent.depth + 1),
+            None => (name.to_string(), 0),
+        };
+        self.spans.insert(id, Known { name: name.to_string(), thread, depth });
+    }
+
+    fn text(&mut self, event: &TraceEvent) -> String {
+        match *event {
+            TraceEvent::ThreadStart { ref name, id, .. } => {
+                self.start(id, None, name);
+                "thread started".to_string()
+            },
+            TraceEvent::ThreadEnd { .. } => "thread ended".to_string(),
+            TraceEvent::AsyncStart { ref name, id, parent_id, ref metadata, .. } => {
+                self.start(id, Some(parent_id), name);
+                with_metadata(format!("+ {}", name), metadata)
+            },
+            TraceEvent::AsyncOnCPU { id, .. } => format!("> {}", self.name(id)),
+            TraceEvent::AsyncOffCPU { id, .. } => format!("< {}", self.name(id)),
+            TraceEvent::AsyncEnd { id, ref outcome, .. } => {
+                let outcome = match *outcome {
+                    AsyncOutcome::Success => "".to_string(),
+                    AsyncOutcome::Cancelled => " (cancelled)".to_string(),
+                    AsyncOutcome::Error(ref e) => format!(" (error: {})", e),
+                };
+                format!("- {}{}", self.name(id), outcome)
+            },
+            TraceEvent::SyncStart { ref name, id, parent_id, ref metadata, .. } => {
+                self.start(id, Some(parent_id), name);
+                with_metadata(format!("+ {}", name), metadata)
+            },
+            TraceEvent::SyncEnd { id, .. } => format!("- {}", self.name(id)),
+            TraceEvent::IdleStart { id, parent_id, .. } => {
+                self.start(id, Some(parent_id), "idle");
+                "+ idle".to_string()
+            },
+            TraceEvent::IdleEnd { .. } => "- idle".to_string(),
+            TraceEvent::Wakeup { waking_span, parked_span, .. } => {
+                format!("! {} woke {}", self.name(waking_span), self.name(parked_span))
+            },
+            TraceEvent::Mark { id, ref name, ref metadata, .. } => {
+                with_metadata(format!("* {} in {}", name, self.name(id)), metadata)
+            },
+        }
+    }
+
+    pub fn print<W: Write>(&mut self, out: &mut W, event: &TraceEvent) -> io::Result<()> {
+        writeln!(out, "{}", self.line(event))
+    }
+
+    pub fn line(&mut self, event: &TraceEvent) -> String {
+        // Threads log independently, so events can be slightly out of order
+        // and come before the first one.
+        let first = *self.first.get_or_insert(event.ts());
+        let since = ms(event.ts()) - ms(first);
+        let text = self.text(event);
+        // Wakeups are shown under the span they woke.
+        let id = match *event {
+            TraceEvent::Wakeup { parked_span, .. } => parked_span,
+            _ => event.id().unwrap(),
+        };
+        let (thread, depth) = match self.spans.get(&id) {
+            Some(known) => (known.thread.as_str(), known.depth),
+            None => ("?", 0),
+        };
+        format!("{:>12.3}  {:<16}  {}{}", since, thread, "  ".repeat(depth), text)
+    }
+}
@@ -0,0 +1,162 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Cursor, Write};
+use std::thread;
+use std::time::Duration;
+use cyclotron_model::{AsyncOutcome, SpanId, TraceEvent};
+use cyclotron_model::json::JsonFormat;
+use serde_json::Value;
+use format::{Events, Writer};
+use tail::Printer;
+use {copy, is_broken_pipe, merge_into, span_id};
+
+fn ms(n: u64) -> Duration {
+    Duration::from_millis(n)
+}
+
+fn events() -> Vec<TraceEvent> {
+    vec![
+        TraceEvent::ThreadStart { name: "main".to_string(), id: SpanId(1), ts: ms(0) },
+        TraceEvent::AsyncStart {
+            name: "task".to_string(), id: SpanId(2), parent_id: SpanId(1), ts: ms(1),
+            metadata: json!({ "user": "alice" }),
+        },
+        TraceEvent::AsyncOnCPU { id: SpanId(2), ts: Duration::new(0, 1_500_001) },
+        TraceEvent::SyncStart { name: "work".to_string(), id: SpanId(3), parent_id: SpanId(2), ts: ms(2), metadata: Value::Null },
+        TraceEvent::SyncEnd { id: SpanId(3), ts: ms(3) },
+        TraceEvent::Wakeup { waking_span: SpanId(3), parked_span: SpanId(2), ts: ms(3) },
+        TraceEvent::AsyncOffCPU { id: SpanId(2), ts: ms(4) },
+        TraceEvent::AsyncEnd { id: SpanId(2), ts: ms(5), outcome: AsyncOutcome::Error("oops".to_string()) },
+        TraceEvent::ThreadEnd { id: SpanId(1), ts: ms(6) },
+    ]
+}
+
+fn read(bytes: Vec<u8>) -> Events {
+    Events::new(Box::new(Cursor::new(bytes)), false).unwrap()
+}
+
+fn write(events: &[TraceEvent], json: Option<JsonFormat>) -> Vec<u8> {
+    let mut buf = vec![];
+    {
+        let mut out = Writer::new(&mut buf, json).unwrap();
+        for event in events {
+            out.write(event).unwrap();
+        }
+    }
+    buf
+}
+
+fn parse(events: Events) -> Vec<TraceEvent> {
+    events.map(|item| item.unwrap().1.unwrap()).collect()
+}
+
+#[test]
+fn test_convert() {
+    // Every JSON version and the binary format read back the same, whichever
+    // they're converted from.
+    let events = events();
+    let formats = vec![None, JsonFormat::version(1), JsonFormat::version(2), JsonFormat::version(3)];
+    for from in &formats {
+        for to in &formats {
+            let mut buf = vec![];
+            copy(read(write(&events, *from)), &mut Writer::new(&mut buf, *to).unwrap()).unwrap();
+            assert_eq!(parse(read(buf)), events, "{:?} to {:?}", from, to);
+        }
+    }
+
+    // Lines that don't parse are skipped.
+    let json = JsonFormat::version(1);
+    let input = [write(&events[..1], json), b"not json\n".to_vec(), write(&events[1..2], json)].concat();
+    let mut buf = vec![];
+    copy(read(input), &mut Writer::new(&mut buf, None).unwrap()).unwrap();
+    assert_eq!(parse(read(buf)), &events[..2]);
+}
+
+#[test]
+fn test_merge() {
+    let events = events();
+    // Split between two traces, one of them binary, each in order.
+    let (a, b): (Vec<_>, Vec<_>) = events.iter().cloned().enumerate().partition(|&(i, _)| i % 2 == 1);
+    let a: Vec<_> = a.into_iter().map(|(_, e)| e).collect();
+    let b: Vec<_> = b.into_iter().map(|(_, e)| e).collect();
+    let inputs = vec![
+        ("a.log", read(write(&a, JsonFormat::version(2)))),
+        ("b.bin", read(write(&b, None))),
+    ];
+    let mut buf = vec![];
+    merge_into(inputs, &mut Writer::new(&mut buf, JsonFormat::version(1)).unwrap()).unwrap();
+    let merged = parse(read(buf));
+    assert_eq!(merged.len(), events.len());
+    assert!(merged.windows(2).all(|w| w[0].ts() <= w[1].ts()));
+    // The SyncEnd and Wakeup at 3ms tie, and the first input's Wakeup wins.
+    assert_eq!(merged[4], events[5]);
+    assert_eq!(merged[5], events[4]);
+}
+
+#[test]
+fn test_tail() {
+    let mut printer = Printer::new();
+    let lines: Vec<String> = events().iter().map(|e| printer.line(e)).collect();
+    let expected = vec![
+        "       0.000  main              thread started",
+        "       1.000  main                + task {\"user\":\"alice\"}",
+        "       1.500  main                > task",
+        "       2.000  main                  + work",
+        "       3.000  main                  - work",
+        "       3.000  main                ! work woke task",
+        "       4.000  main                < task",
+        "       5.000  main                - task (error: oops)",
+        "       6.000  main              thread ended",
+    ];
+    assert_eq!(lines, expected);
+}
+
+// Stdout once whatever was reading it has exited.
+struct ClosedPipe;
+
+impl Write for ClosedPipe {
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_broken_pipe() {
+    let mut printer = Printer::new();
+    let e = printer.print(&mut ClosedPipe, &events()[0]).unwrap_err();
+    assert!(is_broken_pipe(&e.into()));
+    let mut out = Writer::new(ClosedPipe, JsonFormat::version(1)).unwrap();
+    let e = copy(read(write(&events(), JsonFormat::version(1))), &mut out).unwrap_err();
+    assert!(is_broken_pipe(&e));
+    assert!(!is_broken_pipe(&io::Error::new(io::ErrorKind::NotFound, "gone").into()));
+}
+
+#[test]
+fn test_span_id() {
+    assert_eq!(span_id("42").unwrap(), SpanId(42));
+    assert_eq!(span_id("0x2a").unwrap(), SpanId(42));
+    assert_eq!(span_id("\"42\"").unwrap(), SpanId(42));
+    assert_eq!(span_id("18446744073709551615").unwrap(), SpanId(u64::MAX));
+    assert!(span_id("-1").is_err());
+    assert!(span_id("x").is_err());
+}
+
+// Following a trace that's still empty waits for its first bytes before
+// deciding it isn't binary.
+#[test]
+fn test_follow_empty() {
+    let path = "/tmp/test_follow_empty.bin";
+    File::create(path).unwrap();
+    let writer = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        let mut file = OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(&write(&events(), None)).unwrap();
+    });
+    let mut follower = Events::new(Box::new(BufReader::new(File::open(path).unwrap())), true).unwrap();
+    writer.join().unwrap();
+    let first = follower.next().unwrap().unwrap().1.unwrap();
+    assert_eq!(first, events()[0]);
+}
@@ -2,7 +2,7 @@ use std::collections::{BTreeSet,BTreeMap};
 use std::time::Duration;
 use smallvec::SmallVec;
 
-use event;
+use cyclotron_model::SpanId;
 use spans;
 
 pub struct LaidSpan<'a> {
@@ -61,7 +61,7 @@ pub fn lay_out<'a>(spans: impl Iterator<Item = spans::Span<'a>>) -> Layout<'a> {
     spans.sort_unstable_by_key(|s| s.span.start);
 
     type Path = SmallVec<[u16; 8]>; // quite possibly the most unnecessary thing
-    let mut allocations: BTreeMap<event::SpanId, Path> = BTreeMap::new();
+    let mut allocations: BTreeMap<SpanId, Path> = BTreeMap::new();
     let mut sweeps: BTreeMap<Path, Sweep> = BTreeMap::new();
     for sp in &mut spans {
         let mut parent_path = if let Some(parent) = sp.span.parent_id {
@@ -3,6 +3,7 @@
 #![feature(conservative_impl_trait)]
 #![feature(universal_impl_trait)]
 
+extern crate cyclotron_model;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
@@ -17,8 +18,8 @@ extern crate font_rs;
 
 pub mod webgl_rendering_context;
 
-mod event;
 mod spans;
+mod remote;
 mod render;
 mod font;
 mod layout;
@@ -50,6 +51,8 @@ pub struct Inner {
     render_cache: RefCell<render::Cache>,
     zoom: Cell<(Duration, Duration)>,
     render_scheduled: Cell<bool>,
+    // Set when viewing a trace on the server.
+    remote: RefCell<Option<remote::Remote>>,
 }
 
 #[derive(Clone)]
@@ -64,20 +67,57 @@ fn read_into(out: &mut spans::State, bytes: &[u8]) -> Result<(), serde_json::Err
     Ok(())
 }
 
+fn secs(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 * 1e-9
+}
+
+fn duration(secs: f64) -> Duration {
+    Duration::new(secs as u64, (secs.fract() * 1e9) as u32)
+}
+
 impl Context {
     fn render(&self, _time: f64) {
         let (start, end) = self.inner.zoom.get();
-        let state = self.inner.spans.borrow();
-        let layout = layout::lay_out(state.select(start, end));
-        render::render(&self.inner.canvas, &layout, &render::Options {
+        let options = render::Options {
             start_ts: start,
             end_ts: end,
             font_size: 120,
-        }, &mut self.inner.render_cache.borrow_mut());
+        };
+        // A bucket every other pixel.
+        let buckets = self.inner.canvas.width() as u64 / 2;
+        if end > start {
+            self.fetch(remote::View { start, end, buckets });
+        }
+        let summary = self.inner.spans.borrow_mut().summarise(start, end, buckets);
+        match summary {
+            Some(summary) => {
+                render::render_summary(&self.inner.canvas, &summary, &options, &mut self.inner.render_cache.borrow_mut());
+            },
+            None => {
+                let state = self.inner.spans.borrow();
+                let layout = layout::lay_out(state.select(start, end));
+                render::render(&self.inner.canvas, &layout, &options, &mut self.inner.render_cache.borrow_mut());
+            },
+        }
 
         self.schedule_render();
     }
 
+    // Zoom in or out, keeping the point `at` of the way across the view
+    // where it is.
+    fn zoom_by(&self, delta: f64, at: f64) {
+        let (start, end) = self.inner.zoom.get();
+        let (start, end) = (secs(start), secs(end));
+        let scale = (delta * 0.002).exp();
+        let centre = start + (end - start) * at;
+        let (start, end) = ((centre - (centre - start) * scale).max(0.0), centre + (end - centre) * scale);
+        if end - start < 1e-9 {
+            return;
+        }
+        self.inner.zoom.set((duration(start), duration(end)));
+        self.schedule_render();
+    }
+
     fn schedule_render(&self) {
         let ctx = self.clone();
         if !self.inner.render_scheduled.get() {
@@ -90,6 +130,7 @@ impl Context {
     }
 
     fn set_file(&self, file: Reference) {
+        *self.inner.remote.borrow_mut() = None;
         let this = self.clone();
         let callback = move |array: ArrayBuffer| {
             let data: Vec<u8> = array.into();
@@ -100,7 +141,7 @@ impl Context {
                     console!(error, format!("JSON deserialization error: {}", e));
                 }
                 console!(log, format!("Loaded in {} spans", spans.len()));
-                this.inner.zoom.set((Duration::default(), spans.end_time));
+                this.inner.zoom.set((Duration::default(), spans.end_time()));
             }
             this.schedule_render();
         };
@@ -112,6 +153,143 @@ impl Context {
             reader.readAsArrayBuffer(@{&file});
         }
     }
+
+    // Send `query` to the server, and once it closes the socket, pass `f`
+    // everything it sent.
+    fn query<F: FnOnce(&Context, Vec<String>) + 'static>(&self, query: String, f: F) {
+        let ctx = self.clone();
+        let mut f = Some(f);
+        let callback = move |messages: Array| {
+            let messages = Vec::<Value>::from(messages).into_iter()
+                .filter_map(|m| m.into_string())
+                .collect();
+            if let Some(f) = f.take() {
+                f(&ctx, messages);
+            }
+        };
+        js!{@(no_return)
+            const url = (location.protocol === "https:" ? "wss:" : "ws:") + "//" + location.host + "/ws/";
+            const socket = new WebSocket(url, "cyclotron-ws");
+            const messages = [];
+            const callback = @{callback};
+            socket.onopen = function() {
+                socket.send(@{query});
+            };
+            socket.onmessage = function(e) {
+                messages.push(e.data);
+            };
+            socket.onclose = function() {
+                callback(messages);
+                callback.drop();
+            };
+        }
+    }
+
+    fn set_trace(&self, trace: String) {
+        let remote = remote::Remote::new(trace);
+        let query = remote.query(None, true);
+        *self.inner.remote.borrow_mut() = Some(remote);
+        *self.inner.spans.borrow_mut() = spans::State::new();
+        self.query(query, |ctx, messages| {
+            let end = messages.iter()
+                .filter_map(|m| match remote::Message::parse(m) {
+                    Ok(remote::Message::Reply(remote::Reply::Header(header))) => header.end,
+                    _ => None,
+                })
+                .next();
+            match end {
+                Some(end) => {
+                    ctx.inner.zoom.set((Duration::default(), end));
+                    ctx.schedule_render();
+                },
+                None => console!(error, "Nothing to show from the server"),
+            }
+        });
+    }
+
+    fn is_fetching(&self, view: remote::View) -> bool {
+        self.inner.remote.borrow().as_ref().map(|r| r.fetching == Some(view)).unwrap_or(false)
+    }
+
+    // Fetch what's needed to draw `view` from the server, unless it's
+    // already drawn.  One view's fetched at a time, then the latest one
+    // wanted meanwhile.
+    fn fetch(&self, view: remote::View) {
+        let query = match *self.inner.remote.borrow_mut() {
+            Some(ref mut remote) => {
+                if remote.shown == Some(view) || remote.fetching == Some(view) {
+                    return;
+                }
+                if remote.fetching.is_some() {
+                    remote.wanted = Some(view);
+                    return;
+                }
+                remote.fetching = Some(view);
+                remote.query(Some(view), true)
+            },
+            None => return,
+        };
+        self.query(query, move |ctx, messages| ctx.fetched_summary(view, messages));
+    }
+
+    fn fetched_summary(&self, view: remote::View, messages: Vec<String>) {
+        if !self.is_fetching(view) {
+            return;
+        }
+        let summary = messages.iter()
+            .filter_map(|m| match remote::Message::parse(m) {
+                Ok(remote::Message::Reply(remote::Reply::Summary(summary))) => Some(summary),
+                _ => None,
+            })
+            .next();
+        match summary {
+            Some(ref summary) if !remote::too_many(summary) => {
+                let query = self.inner.remote.borrow().as_ref().unwrap().query(Some(view), false);
+                self.query(query, move |ctx, messages| ctx.fetched_events(view, messages));
+                return;
+            },
+            Some(summary) => self.inner.spans.borrow_mut().show_summary(summary),
+            None => console!(error, "No summary from the server"),
+        }
+        self.fetched(view);
+    }
+
+    fn fetched_events(&self, view: remote::View, messages: Vec<String>) {
+        if !self.is_fetching(view) {
+            return;
+        }
+        let mut state = spans::State::new();
+        for message in &messages {
+            match remote::Message::parse(message) {
+                Ok(remote::Message::Event(event)) => state.add_event(event),
+                Ok(remote::Message::Reply(_)) => (),
+                Err(e) => console!(error, format!("JSON deserialization error: {}", e)),
+            }
+        }
+        *self.inner.spans.borrow_mut() = state;
+        self.fetched(view);
+    }
+
+    fn fetched(&self, view: remote::View) {
+        let wanted = match *self.inner.remote.borrow_mut() {
+            Some(ref mut remote) => {
+                remote.shown = Some(view);
+                remote.fetching = None;
+                remote.wanted.take()
+            },
+            None => None,
+        };
+        if let Some(wanted) = wanted {
+            self.fetch(wanted);
+        }
+        self.schedule_render();
+    }
+
+    fn set_critical_path(&self, name: &str) {
+        let count = self.inner.spans.borrow_mut().highlight_critical_path(name);
+        console!(log, format!("Highlighted {} spans on the critical path of {:?}", count, name));
+        self.schedule_render();
+    }
 }
 
 pub fn main() {
@@ -128,11 +306,23 @@ pub fn main() {
             spans: RefCell::new(spans::State::new()),
             render_cache: Default::default(),
             render_scheduled: Cell::new(false),
+            remote: RefCell::new(None),
         }),
     };
     window().add_event_listener(enclose!((ctx) move |_e: ResizeEvent| {
         ctx.schedule_render();
     }));
+    let on_wheel = enclose!((ctx) move |delta: f64, at: f64| {
+        ctx.zoom_by(delta, at);
+    });
+    js!{@(no_return)
+        const canvas = @{&ctx.inner.canvas};
+        const callback = @{on_wheel};
+        canvas.addEventListener("wheel", function(e) {
+            e.preventDefault();
+            callback(e.deltaY, e.offsetX / canvas.clientWidth);
+        });
+    }
     document()
         .get_element_by_id("file")
         .unwrap()
@@ -145,6 +335,23 @@ pub fn main() {
             }
             e.prevent_default();
         }));
+    document()
+        .get_element_by_id("critical")
+        .unwrap()
+        .add_event_listener(enclose!((ctx) move |e: ChangeEvent| {
+            let name: String = js! {
+                return document.getElementById("critical").value;
+            }.try_into().unwrap();
+            ctx.set_critical_path(name.trim());
+            e.prevent_default();
+        }));
+    // `?trace=<name>` views a trace on the server this is served from.
+    let trace = js! {
+        return new URLSearchParams(location.search).get("trace");
+    };
+    if let Value::String(trace) = trace {
+        ctx.set_trace(trace);
+    }
     ctx.schedule_render();
     stdweb::event_loop();
 }
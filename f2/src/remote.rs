@@ -0,0 +1,97 @@
+//! Viewing a trace on the server, for traces too big to load whole.  For each
+//! view, the server's asked for a summary first, and if that shows few enough
+//! spans to draw one by one, for the view's events.
+
+use std::time::Duration;
+use serde_json;
+use cyclotron_model::TraceEvent;
+use cyclotron_model::lod::Summary;
+use spans;
+
+#[derive(Debug, Deserialize)]
+pub struct Header {
+    pub start: Option<Duration>,
+    pub end: Option<Duration>,
+}
+
+/// Messages from the server that aren't trace events.
+#[derive(Debug, Deserialize)]
+pub enum Reply {
+    Header(Header),
+    Summary(Summary),
+}
+
+pub enum Message {
+    Reply(Reply),
+    Event(TraceEvent),
+}
+
+impl Message {
+    pub fn parse(text: &str) -> Result<Self, serde_json::Error> {
+        match serde_json::from_str(text) {
+            Ok(reply) => Ok(Message::Reply(reply)),
+            Err(_) => serde_json::from_str(text).map(Message::Event),
+        }
+    }
+}
+
+/// Whether a summary has too many spans starting in it to draw them one by
+/// one.  Spans that started before it are left out, so this is a guess.
+pub fn too_many(summary: &Summary) -> bool {
+    let count: usize = summary.rows.iter()
+        .flat_map(|r| r.buckets.iter())
+        .map(|b| b.count as usize)
+        .sum();
+    count > spans::MAX_SPANS
+}
+
+/// A view of the trace, and how many buckets across it summaries need.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct View {
+    pub start: Duration,
+    pub end: Duration,
+    pub buckets: u64,
+}
+
+// See the server's `query` module.
+#[derive(Serialize)]
+struct Query<'a> {
+    version: u32,
+    trace: &'a str,
+    start: Option<Duration>,
+    end: Option<Duration>,
+    resolution: Option<u64>,
+}
+
+pub struct Remote {
+    pub trace: String,
+    // The view drawn, the one being fetched, and the latest one wanted
+    // meanwhile, to fetch next.
+    pub shown: Option<View>,
+    pub fetching: Option<View>,
+    pub wanted: Option<View>,
+}
+
+impl Remote {
+    pub fn new(trace: String) -> Self {
+        Remote {
+            trace,
+            shown: None,
+            fetching: None,
+            wanted: None,
+        }
+    }
+
+    /// A query for the summary of `view`, or of the whole trace, or for the
+    /// events in `view`.
+    pub fn query(&self, view: Option<View>, summary: bool) -> String {
+        let query = Query {
+            version: 2,
+            trace: &self.trace,
+            start: view.map(|v| v.start),
+            end: view.map(|v| v.end),
+            resolution: if summary { Some(view.map(|v| v.buckets).unwrap_or(1)) } else { None },
+        };
+        serde_json::to_string(&query).unwrap()
+    }
+}
@@ -3,6 +3,7 @@ use stdweb::web::html_element::CanvasElement;
 use stdweb::UnsafeTypedArray;
 use std::time::Duration;
 
+use cyclotron_model::lod::Summary;
 use webgl_rendering_context::{GLenum, GLfloat, WebGLBuffer, WebGLProgram,
                               WebGLRenderingContext as GL, WebGLShader, WebGLTexture,
                               WebGLUniformLocation};
@@ -69,11 +70,11 @@ fn d(d: Duration) -> GLfloat {
     d.as_secs() as GLfloat + d.subsec_nanos() as GLfloat * 1e-9
 }
 
-fn render_boxes<'a, 'b: 'a>(
+fn render_boxes(
     gl: &GL,
     once: &Once,
     options: &Options,
-    spans: impl Iterator<Item = &'a layout::LaidSpan<'b>>,
+    boxes: impl Iterator<Item = (GLfloat, GLfloat, GLfloat, GLfloat)>,
     col: (f32, f32, f32),
     pos_data: &mut Vec<GLfloat>,
     index_data: &mut Vec<u16>,
@@ -91,7 +92,7 @@ fn render_boxes<'a, 'b: 'a>(
     pos_data.clear();
     index_data.clear();
 
-    for sp in spans {
+    for (x1, y1, x2, y2) in boxes {
         // two triangles make a rectangle
         let ix = (pos_data.len() / 2) as u16;
         index_data.push(ix);
@@ -101,10 +102,6 @@ fn render_boxes<'a, 'b: 'a>(
         index_data.push(ix + 2);
         index_data.push(ix + 3);
 
-        let x1 = d(sp.span.start);
-        let y1 = 2.0 * sp.row as GLfloat;
-        let x2 = d(sp.span.end);
-        let y2 = y1 + 1.5;
         pos_data.push(x1);
         pos_data.push(y1);
         pos_data.push(x1);
@@ -133,15 +130,11 @@ fn render_boxes<'a, 'b: 'a>(
     );
 }
 
-pub fn render(
-    canvas: &CanvasElement,
-    layout: &layout::Layout,
-    options: &Options,
-    cache: &mut Cache,
-) {
+// Set up GL the first time, and clear the canvas.
+fn begin<'c>(canvas: &CanvasElement, once: &'c mut Option<Once>) -> (GL, f64, &'c Once) {
     let (ratio, width, height) = info(canvas);
     let gl: GL = canvas.get_context().unwrap();
-    if cache.once.is_none() {
+    if once.is_none() {
         let box_frag = load_shader(&gl, GL::FRAGMENT_SHADER, include_str!("./shaders/box.frag"));
         let box_vert = load_shader(&gl, GL::VERTEX_SHADER, include_str!("./shaders/box.vert"));
         let box_program = gl.create_program().unwrap();
@@ -163,20 +156,28 @@ pub fn render(
         }
         let pos_buffer = mk_buffer!(pos);
         let index_buffer = gl.create_buffer().unwrap();
-        let once = Once {
+        *once = Some(Once {
             box_program,
             view_uniform,
             color_uniform,
             pos_buffer,
             index_buffer,
-        };
-        cache.once = Some(once);
+        });
     }
-    let font_size = (options.font_size as f64 * ratio) as u32;
-    let once = cache.once.as_ref().unwrap();
     gl.viewport(0, 0, width as i32, height as i32);
     gl.clear_color(0.0, 0.0, 0.0, 0.0);
     gl.clear(GL::COLOR_BUFFER_BIT);
+    (gl, ratio, once.as_ref().unwrap())
+}
+
+pub fn render(
+    canvas: &CanvasElement,
+    layout: &layout::Layout,
+    options: &Options,
+    cache: &mut Cache,
+) {
+    let (gl, ratio, once) = begin(canvas, &mut cache.once);
+    let font_size = (options.font_size as f64 * ratio) as u32;
 
     // draw boxes
     let mut pos_data: Vec<GLfloat> = Vec::with_capacity(layout.spans.len() * 8);
@@ -191,12 +192,18 @@ pub fn render(
         (spans::SpanStyle::SyncInProgress, (0.6, 0.6, 0.0)),
         (spans::SpanStyle::ThreadFinished, (0.2, 0.8, 0.0)),
         (spans::SpanStyle::ThreadInProgress, (0.1, 0.7, 0.0)),
+        (spans::SpanStyle::IdleFinished, (0.8, 0.8, 0.8)),
+        (spans::SpanStyle::IdleInProgress, (0.7, 0.7, 0.7)),
+        (spans::SpanStyle::CriticalPath, (1.0, 0.4, 0.0)),
     ] {
         render_boxes(
             &gl,
             once,
             options,
-            layout.spans.iter().filter(|sp| sp.span.style == style),
+            layout.spans.iter().filter(|sp| sp.span.style == style).map(|sp| {
+                let y1 = 2.0 * sp.row as GLfloat;
+                (d(sp.span.start), y1, d(sp.span.end), y1 + 1.5)
+            }),
             col,
             &mut pos_data,
             &mut index_data,
@@ -212,3 +219,41 @@ pub fn render(
         (b'c', (160.0, 0.0)),
     ].iter().cloned(), (1.0, 0.5, 0.0));
 }
+
+// Summary bars are coloured by the name of the span they're busiest in.
+const PALETTE: [(f32, f32, f32); 6] = [
+    (0.0, 0.0, 0.9),
+    (0.8, 0.8, 0.0),
+    (0.2, 0.8, 0.0),
+    (0.9, 0.3, 0.3),
+    (0.0, 0.7, 0.7),
+    (0.6, 0.2, 0.8),
+];
+
+fn colour(name: Option<&String>) -> usize {
+    name.map(|n| n.bytes().fold(0usize, |h, c| h.wrapping_mul(31).wrapping_add(c as usize)))
+        .unwrap_or(0) % PALETTE.len()
+}
+
+/// Draw a view too zoomed out for its spans: a row per thread, with a bar
+/// for each bucket of time as tall as the thread was busy in it.
+pub fn render_summary(
+    canvas: &CanvasElement,
+    summary: &Summary,
+    options: &Options,
+    cache: &mut Cache,
+) {
+    let (gl, _, once) = begin(canvas, &mut cache.once);
+    let width = d(summary.width);
+    let mut pos_data = vec![];
+    let mut index_data = vec![];
+    for (i, &col) in PALETTE.iter().enumerate() {
+        let bars = summary.rows.iter().enumerate().flat_map(|(row, r)| {
+            let bottom = 2.0 * row as GLfloat + 1.5;
+            r.buckets.iter()
+                .filter(move |b| colour(b.name.as_ref()) == i)
+                .map(move |b| (d(b.start), bottom - 1.5 * b.busy as GLfloat, d(b.start) + width, bottom))
+        });
+        render_boxes(&gl, once, options, bars, col, &mut pos_data, &mut index_data);
+    }
+}
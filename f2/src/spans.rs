@@ -1,48 +1,24 @@
-use std::collections::HashMap;
+use std::collections::HashSet;
 use std::time::Duration;
-use std::borrow::Cow;
 
-use event::*;
+use cyclotron_model::{AsyncOutcome, SpanId, SpanKind, Trace, TraceEvent};
+use cyclotron_model::critical_path::critical_path;
+use cyclotron_model::lod::{self, Summary};
+use cyclotron_model::spans;
 
-#[derive(Debug)]
-pub struct State {
-    pub active_spans: HashMap<SpanId, ActiveSpan>,
-
-    pub finished_spans: Vec<Span<'static>>,
-
-    pub end_time: Duration,
-}
+// Views with more spans than this are drawn from summaries instead.
+pub const MAX_SPANS: usize = 20_000;
 
 #[derive(Debug)]
-pub struct ActiveSpan {
-    pub event: TraceEvent,
-    pub message: Vec<u8>,
-    pub wakeups: Vec<Wakeup>,
-}
-
-impl ActiveSpan {
-    fn in_progress<'a>(&'a self, ts: Duration) -> Span<'a> {
-        Span {
-            id: self.event.id().unwrap(),
-            parent_id: self.event.parent_id(),
-            start: self.event.ts(),
-            end: ts,
-            style: match self.event {
-                TraceEvent::AsyncStart { .. } => SpanStyle::AsyncInProgress,
-                TraceEvent::SyncStart { .. } => SpanStyle::SyncInProgress,
-                TraceEvent::ThreadStart { .. } => SpanStyle::ThreadInProgress,
-                _ => panic!("wrong kind of start event"),
-            },
-            message: Cow::Borrowed(&self.message),
-            wakeups: Cow::Borrowed(&self.wakeups),
-        }
-    }
-}
-
-#[derive(Debug, Clone)]
-pub struct Wakeup {
-    target: SpanId,
-    ts: Duration,
+pub struct State {
+    trace: Trace,
+    // Spans on the highlighted critical path.
+    critical: HashSet<SpanId>,
+    lod: lod::Builder,
+    // The last summary asked for, by view, bucket count and span count.
+    summary: Option<((Duration, Duration, u64, usize), Option<Summary>)>,
+    // A summary from the server, drawn instead of any spans.
+    remote_summary: Option<Summary>,
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -55,31 +31,48 @@ pub enum SpanStyle {
     AsyncSuccess,
     AsyncCancel,
     AsyncError,
+    IdleInProgress,
+    IdleFinished,
+    CriticalPath,
 }
 
+impl SpanStyle {
+    fn of(span: &spans::Span) -> Self {
+        match (span.kind, &span.outcome, span.is_finished()) {
+            (SpanKind::Thread, _, false) => SpanStyle::ThreadInProgress,
+            (SpanKind::Thread, _, true) => SpanStyle::ThreadFinished,
+            (SpanKind::Sync, _, false) => SpanStyle::SyncInProgress,
+            (SpanKind::Sync, _, true) => SpanStyle::SyncFinished,
+            (SpanKind::Idle, _, false) => SpanStyle::IdleInProgress,
+            (SpanKind::Idle, _, true) => SpanStyle::IdleFinished,
+            (SpanKind::Async, &Some(AsyncOutcome::Success), _) => SpanStyle::AsyncSuccess,
+            (SpanKind::Async, &Some(AsyncOutcome::Cancelled), _) => SpanStyle::AsyncCancel,
+            (SpanKind::Async, &Some(AsyncOutcome::Error(_)), _) => SpanStyle::AsyncError,
+            (SpanKind::Async, &None, _) => SpanStyle::AsyncInProgress,
+        }
+    }
+}
+
+/// A span as drawn: in-progress spans are cut off at the end of the view.
 #[derive(Debug)]
 pub struct Span<'a> {
     pub id: SpanId,
     pub parent_id: Option<SpanId>,
     pub start: Duration,
     pub end: Duration,
-    pub message: Cow<'a, [u8]>,
-    pub wakeups: Cow<'a, [Wakeup]>,
-
     pub style: SpanStyle,
-    // TODO: more complicated stuff goes here
+    pub inner: &'a spans::Span,
 }
 
 impl<'a> Span<'a> {
-    pub fn borrow<'b>(&'b self) -> Span<'b> {
+    fn new(span: &'a spans::Span, now: Duration, critical: bool) -> Self {
         Span {
-            id: self.id,
-            parent_id: self.parent_id,
-            start: self.start,
-            end: self.end,
-            message: Cow::from(&self.message[..]),
-            wakeups: Cow::from(&self.wakeups[..]),
-            style: self.style,
+            id: span.id,
+            parent_id: span.parent_id,
+            start: span.start,
+            end: span.end_or(now),
+            style: if critical { SpanStyle::CriticalPath } else { SpanStyle::of(span) },
+            inner: span,
         }
     }
 }
@@ -87,110 +80,64 @@ impl<'a> Span<'a> {
 impl State {
     pub fn new() -> Self {
         State {
-            active_spans: HashMap::new(),
-            finished_spans: Vec::new(),
-            end_time: Duration::default(),
+            trace: Trace::new(),
+            critical: HashSet::new(),
+            lod: lod::Builder::new(),
+            summary: None,
+            remote_summary: None,
         }
     }
 
-    pub fn bump_time(&mut self, ts: Duration) {
-        if ts > self.end_time {
-            self.end_time = ts;
-        }
+    pub fn end_time(&self) -> Duration {
+        self.trace.end_time()
     }
 
     pub fn add_event(&mut self, event: TraceEvent) {
-        self.bump_time(event.ts());
-        match event {
-            TraceEvent::AsyncStart { id, .. }
-            | TraceEvent::SyncStart { id, .. }
-            | TraceEvent::ThreadStart { id, .. } => {
-                self.active_spans.insert(
-                    id,
-                    ActiveSpan {
-                        wakeups: vec![],
-                        message: match event {
-                            TraceEvent::AsyncStart {
-                                ref name,
-                                ref metadata,
-                                ..
-                            } => format!("{} {}", name, metadata),
-                            TraceEvent::SyncStart {
-                                ref name,
-                                ref metadata,
-                                ..
-                            } => format!("{} {}", name, metadata),
-                            TraceEvent::ThreadStart { ref name, .. } => name.clone(),
-                            _ => unreachable!(),
-                        }.into_bytes(),
-                        event,
-                    },
-                );
-            }
-            TraceEvent::AsyncOnCPU { .. } | TraceEvent::AsyncOffCPU { .. } => {
-                // TODO
-            }
-            TraceEvent::AsyncEnd { id, ts, .. }
-            | TraceEvent::SyncEnd { id, ts }
-            | TraceEvent::ThreadEnd { id, ts } => {
-                if let Some(mut start) = self.active_spans.remove(&id) {
-                    self.finished_spans.push(Span {
-                        id,
-                        parent_id: start.event.parent_id(),
-                        start: start.event.ts(),
-                        end: ts,
-                        style: match start.event {
-                            TraceEvent::AsyncStart { .. } => match event {
-                                TraceEvent::AsyncEnd {
-                                    outcome: AsyncOutcome::Success,
-                                    ..
-                                } => SpanStyle::AsyncSuccess,
-                                TraceEvent::AsyncEnd {
-                                    outcome: AsyncOutcome::Cancelled,
-                                    ..
-                                } => SpanStyle::AsyncCancel,
-                                TraceEvent::AsyncEnd {
-                                    outcome: AsyncOutcome::Error(_),
-                                    ..
-                                } => SpanStyle::AsyncError,
-                                _ => {
-                                    eprintln!("wrong kind of start event");
-                                    return;
-                                }
-                            },
-                            TraceEvent::SyncStart { .. } => SpanStyle::SyncFinished,
-                            TraceEvent::ThreadStart { .. } => SpanStyle::ThreadFinished,
-                            _ => {
-                                eprintln!("wrong kind of start event");
-                                return;
-                            }
-                        },
-                        message: start.message.into(),
-                        wakeups: start.wakeups.into(),
-                    });
-                } else {
-                    eprintln!("unknown span id: {:?}", id);
-                }
-            }
-            TraceEvent::Wakeup {
-                waking_span,
-                parked_span,
-                ts,
-            } => {
-                if let Some(sp) = self.active_spans.get_mut(&waking_span) {
-                    sp.wakeups.push(Wakeup {
-                        target: parked_span,
-                        ts,
-                    });
-                } else {
-                    eprintln!("unknown waking span id: {:?}", waking_span);
-                }
-            }
+        self.lod.add_event(&event);
+        if let Err(e) = self.trace.add_event(event) {
+            eprintln!("{}", e);
         }
     }
 
     pub fn len(&self) -> usize {
-        self.active_spans.len() + self.finished_spans.len()
+        self.trace.len()
+    }
+
+    /// Highlight the critical path of the slowest span named `name`, or
+    /// clear the highlight if there isn't one.  Returns the number of spans
+    /// highlighted.
+    pub fn highlight_critical_path(&mut self, name: &str) -> usize {
+        let now = self.trace.end_time();
+        let root = self.trace.find(name).into_iter()
+            .max_by_key(|s| s.end_or(now) - s.start)
+            .map(|s| s.id);
+        self.critical = root.and_then(|root| critical_path(&self.trace, root))
+            .map(|path| path.segments.iter().map(|s| s.span).collect())
+            .unwrap_or_default();
+        self.critical.len()
+    }
+
+    /// Draw `summary`, from the server, instead of any spans.
+    pub fn show_summary(&mut self, summary: Summary) {
+        self.remote_summary = Some(summary);
+    }
+
+    /// A summary of `[start, end]` in about `buckets` buckets, if it has
+    /// too many spans to draw one by one.
+    pub fn summarise(&mut self, start: Duration, end: Duration, buckets: u64) -> Option<Summary> {
+        if let Some(ref summary) = self.remote_summary {
+            return Some(summary.clone());
+        }
+        let key = (start, end, buckets, self.trace.len());
+        if self.summary.as_ref().map(|s| s.0 != key).unwrap_or(true) {
+            let summary = if self.trace.overlapping(start, end).len() > MAX_SPANS {
+                Some(self.lod.summarise(start, end, buckets))
+            } else {
+                None
+            };
+            self.summary = Some((key, summary));
+        }
+        self.summary.as_ref().and_then(|s| s.1.clone())
     }
 
     pub fn select<'a>(
@@ -198,11 +145,7 @@ impl State {
         start: Duration,
         end: Duration,
     ) -> impl Iterator<Item = Span<'a>> + 'a {
-        // FIXME: make this good and not bad
-        self.active_spans.values().filter(move |e| e.event.ts() < end)
-            .map(move |e| e.in_progress(end)) // FIXME
-            .chain(self.finished_spans.iter()
-                   .filter(move |s| s.start < end && s.end > start)
-                   .map(|s| s.borrow()))
+        self.trace.overlapping(start, end).into_iter()
+            .map(move |s| Span::new(s, end, self.critical.contains(&s.id)))
     }
 }
@@ -35,7 +35,12 @@ export class Cyclotron {
 
         this.spanManager = new SpanManager();
         // TODO: Print that we're waiting for data or something here.
-        var socket = new WebSocket("ws://127.0.0.1:3001", "cyclotron-ws");
+        // The trace comes from the page's `?trace=` parameter, and streams
+        // from the same host and port as the page.
+        var trace = new URLSearchParams(window.location.search).get("trace") || "empty_file_release.log";
+        var scheme = window.location.protocol === "https:" ? "wss:" : "ws:";
+        var url = `${scheme}//${window.location.host}/ws/${encodeURIComponent(trace)}`;
+        var socket = new WebSocket(url, "cyclotron-ws");
         this.bufferedMessages = [];
         var i = 0;
         socket.onmessage = event => {
@@ -43,7 +48,6 @@ export class Cyclotron {
             // this.bufferedMessages.push(JSON.parse(event.data));
             this.addEvent(JSON.parse(event.data));
         };
-        socket.onopen = event => { socket.send("empty_file_release.log"); };
         socket.onerror = event => { alert(`Socket error ${event}`); };
         socket.onclose = event => { alert(`Socket closed ${event}`); };
 
@@ -129,7 +133,7 @@ export class Cyclotron {
                         this.rectangles[span.id] = rect;
                     }
                     rect.clear();
-                    rect.beginFill(0x484848);
+                    rect.beginFill(span.idle ? 0xd0d0d0 : 0x484848);
                     rect.drawRect(
                         span.start,
                         lane.index,
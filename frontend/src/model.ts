@@ -1,5 +1,13 @@
 import { Lane } from "./lane";
 
+// Matches `cyclotron_model::spans::IDLE_SPAN_NAME`.
+const IDLE_SPAN_NAME = "idle";
+
+// Span ids are strings in traces written with JSON format version 2, since
+// ids above 2^53 don't survive `JSON.parse` as numbers.  Both forms work as
+// keys.
+export type SpanID = number | string;
+
 class OnCPU {
     public end;
 
@@ -26,6 +34,7 @@ export class Span {
     public end;
     public scheduled;
     public outcome;
+    public idle;
 
     public laneID;
     public freeLanes;
@@ -36,8 +45,8 @@ export class Span {
 
     constructor(
         readonly name: string,
-        readonly id: number,
-        readonly parent_id: number,
+        readonly id: SpanID,
+        readonly parent_id: SpanID,
         readonly start: number,
         readonly metadata,
         readonly threadName
@@ -45,6 +54,7 @@ export class Span {
         this.end = null;
         this.scheduled = [];
         this.outcome = null;
+        this.idle = false;
 
         this.expanded = true;
         this.laneID = null;
@@ -349,6 +359,19 @@ export class SpanManager {
         } else if (event.ThreadEnd) {
             let span = this.getSpan(event.ThreadEnd.id);
             this.closeSpan(span, this.convertTs(event.ThreadEnd.ts));
+        } else if (event.IdleStart) {
+            let start = event.IdleStart;
+            let span = this.addSpanWithParent({
+                name: IDLE_SPAN_NAME,
+                id: start.id,
+                parent_id: start.parent_id,
+                ts: start.ts,
+                metadata: null,
+            });
+            span.idle = true;
+        } else if (event.IdleEnd) {
+            let span = this.getSpan(event.IdleEnd.id);
+            this.closeSpan(span, this.convertTs(event.IdleEnd.ts));
         } else if (event.Wakeup) {
             if (event.Wakeup.parked_span == event.Wakeup.waking_span) {
                 // We don't track self-wakeups.
@@ -365,6 +388,9 @@ export class SpanManager {
             }
             this.openWakeups[event.Wakeup.parked_span].push(wakeup);
             this.wakeups.push(wakeup);
+        } else if (event.Mark) {
+            // Point events aren't drawn yet.
+            this.convertTs(event.Mark.ts);
         } else {
             throw new Error("Unexpected event: " + event);
         }
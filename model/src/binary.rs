@@ -0,0 +1,295 @@
+//! A compact binary encoding of trace events, about a third the size of the
+//! JSON one and much cheaper to parse.
+//!
+//! A file starts with `MAGIC`, followed by one record per event: a
+//! little-endian `u32` length and then that many bytes of payload.  The
+//! payload is a tag byte for the event type followed by its fields in
+//! declaration order, where span ids are `u64`s, timestamps are a `u64` of
+//! seconds and a `u32` of nanoseconds, and strings (including metadata, as
+//! JSON) are a `u32` length and UTF-8 bytes.  All integers are little-endian.
+
+use std::fmt;
+use std::str;
+use std::time::Duration;
+use serde_json;
+use event::{AsyncOutcome, SpanId, TraceEvent};
+
+pub const MAGIC: &[u8] = b"CYCLOTRON\x00\x00\x01";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DecodeError {
+    UnknownTag(u8),
+    /// The record is shorter than its fields need.
+    Truncated,
+    InvalidUtf8,
+    InvalidMetadata(String),
+    /// Nanoseconds of a second or more.
+    InvalidTimestamp(u32),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::UnknownTag(tag) => write!(f, "unknown event tag {}", tag),
+            DecodeError::Truncated => write!(f, "truncated record"),
+            DecodeError::InvalidUtf8 => write!(f, "invalid UTF-8 in string"),
+            DecodeError::InvalidMetadata(ref e) => write!(f, "invalid metadata: {}", e),
+            DecodeError::InvalidTimestamp(nanos) => write!(f, "invalid timestamp: {} nanos", nanos),
+        }
+    }
+}
+
+const ASYNC_START: u8 = 0;
+const ASYNC_ON_CPU: u8 = 1;
+const ASYNC_OFF_CPU: u8 = 2;
+const ASYNC_END: u8 = 3;
+const SYNC_START: u8 = 4;
+const SYNC_END: u8 = 5;
+const THREAD_START: u8 = 6;
+const THREAD_END: u8 = 7;
+const IDLE_START: u8 = 8;
+const IDLE_END: u8 = 9;
+const WAKEUP: u8 = 10;
+const MARK: u8 = 11;
+
+const SUCCESS: u8 = 0;
+const CANCELLED: u8 = 1;
+const ERROR: u8 = 2;
+
+struct Writer<'a> {
+    out: &'a mut Vec<u8>,
+}
+
+impl<'a> Writer<'a> {
+    fn u8(&mut self, n: u8) {
+        self.out.push(n);
+    }
+
+    fn u32(&mut self, n: u32) {
+        for i in 0..4 {
+            self.out.push((n >> (8 * i)) as u8);
+        }
+    }
+
+    fn u64(&mut self, n: u64) {
+        for i in 0..8 {
+            self.out.push((n >> (8 * i)) as u8);
+        }
+    }
+
+    fn id(&mut self, id: SpanId) {
+        self.u64(id.0);
+    }
+
+    fn ts(&mut self, ts: Duration) {
+        self.u64(ts.as_secs());
+        self.u32(ts.subsec_nanos());
+    }
+
+    fn str(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.out.extend_from_slice(s.as_bytes());
+    }
+
+    fn metadata(&mut self, metadata: &serde_json::Value) {
+        self.str(&metadata.to_string());
+    }
+}
+
+/// Append one record for `event` to `out`.
+pub fn encode(event: &TraceEvent, out: &mut Vec<u8>) {
+    let start = out.len();
+    let mut w = Writer { out };
+    w.u32(0);
+    match *event {
+        TraceEvent::AsyncStart { ref name, id, parent_id, ts, ref metadata } => {
+            w.u8(ASYNC_START);
+            w.str(name);
+            w.id(id);
+            w.id(parent_id);
+            w.ts(ts);
+            w.metadata(metadata);
+        },
+        TraceEvent::AsyncOnCPU { id, ts } => {
+            w.u8(ASYNC_ON_CPU);
+            w.id(id);
+            w.ts(ts);
+        },
+        TraceEvent::AsyncOffCPU { id, ts } => {
+            w.u8(ASYNC_OFF_CPU);
+            w.id(id);
+            w.ts(ts);
+        },
+        TraceEvent::AsyncEnd { id, ts, ref outcome } => {
+            w.u8(ASYNC_END);
+            w.id(id);
+            w.ts(ts);
+            match *outcome {
+                AsyncOutcome::Success => w.u8(SUCCESS),
+                AsyncOutcome::Cancelled => w.u8(CANCELLED),
+                AsyncOutcome::Error(ref e) => {
+                    w.u8(ERROR);
+                    w.str(e);
+                },
+            }
+        },
+        TraceEvent::SyncStart { ref name, id, parent_id, ts, ref metadata } => {
+            w.u8(SYNC_START);
+            w.str(name);
+            w.id(id);
+            w.id(parent_id);
+            w.ts(ts);
+            w.metadata(metadata);
+        },
+        TraceEvent::SyncEnd { id, ts } => {
+            w.u8(SYNC_END);
+            w.id(id);
+            w.ts(ts);
+        },
+        TraceEvent::ThreadStart { ref name, id, ts } => {
+            w.u8(THREAD_START);
+            w.str(name);
+            w.id(id);
+            w.ts(ts);
+        },
+        TraceEvent::ThreadEnd { id, ts } => {
+            w.u8(THREAD_END);
+            w.id(id);
+            w.ts(ts);
+        },
+        TraceEvent::IdleStart { id, parent_id, ts } => {
+            w.u8(IDLE_START);
+            w.id(id);
+            w.id(parent_id);
+            w.ts(ts);
+        },
+        TraceEvent::IdleEnd { id, ts } => {
+            w.u8(IDLE_END);
+            w.id(id);
+            w.ts(ts);
+        },
+        TraceEvent::Wakeup { waking_span, parked_span, ts } => {
+            w.u8(WAKEUP);
+            w.id(waking_span);
+            w.id(parked_span);
+            w.ts(ts);
+        },
+        TraceEvent::Mark { id, ref name, ts, ref metadata } => {
+            w.u8(MARK);
+            w.id(id);
+            w.str(name);
+            w.ts(ts);
+            w.metadata(metadata);
+        },
+    }
+    let len = (w.out.len() - start - 4) as u32;
+    for i in 0..4 {
+        w.out[start + i] = (len >> (8 * i)) as u8;
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+        if self.buf.len() < n {
+            return Err(DecodeError::Truncated);
+        }
+        let (bytes, rest) = self.buf.split_at(n);
+        self.buf = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, DecodeError> {
+        let bytes = self.bytes(4)?;
+        Ok(bytes.iter().rev().fold(0, |n, &b| (n << 8) | u32::from(b)))
+    }
+
+    fn u64(&mut self) -> Result<u64, DecodeError> {
+        let bytes = self.bytes(8)?;
+        Ok(bytes.iter().rev().fold(0, |n, &b| (n << 8) | u64::from(b)))
+    }
+
+    fn id(&mut self) -> Result<SpanId, DecodeError> {
+        Ok(SpanId(self.u64()?))
+    }
+
+    fn ts(&mut self) -> Result<Duration, DecodeError> {
+        let secs = self.u64()?;
+        let nanos = self.u32()?;
+        if nanos >= 1_000_000_000 {
+            return Err(DecodeError::InvalidTimestamp(nanos));
+        }
+        Ok(Duration::new(secs, nanos))
+    }
+
+    fn str(&mut self) -> Result<String, DecodeError> {
+        let len = self.u32()? as usize;
+        let bytes = self.bytes(len)?;
+        str::from_utf8(bytes).map(str::to_string).map_err(|_| DecodeError::InvalidUtf8)
+    }
+
+    fn metadata(&mut self) -> Result<serde_json::Value, DecodeError> {
+        serde_json::from_str(&self.str()?).map_err(|e| DecodeError::InvalidMetadata(e.to_string()))
+    }
+}
+
+fn decode_payload(r: &mut Reader) -> Result<TraceEvent, DecodeError> {
+    Ok(match r.u8()? {
+        ASYNC_START => TraceEvent::AsyncStart {
+            name: r.str()?,
+            id: r.id()?,
+            parent_id: r.id()?,
+            ts: r.ts()?,
+            metadata: r.metadata()?,
+        },
+        ASYNC_ON_CPU => TraceEvent::AsyncOnCPU { id: r.id()?, ts: r.ts()? },
+        ASYNC_OFF_CPU => TraceEvent::AsyncOffCPU { id: r.id()?, ts: r.ts()? },
+        ASYNC_END => TraceEvent::AsyncEnd {
+            id: r.id()?,
+            ts: r.ts()?,
+            outcome: match r.u8()? {
+                SUCCESS => AsyncOutcome::Success,
+                CANCELLED => AsyncOutcome::Cancelled,
+                ERROR => AsyncOutcome::Error(r.str()?),
+                tag => return Err(DecodeError::UnknownTag(tag)),
+            },
+        },
+        SYNC_START => TraceEvent::SyncStart {
+            name: r.str()?,
+            id: r.id()?,
+            parent_id: r.id()?,
+            ts: r.ts()?,
+            metadata: r.metadata()?,
+        },
+        SYNC_END => TraceEvent::SyncEnd { id: r.id()?, ts: r.ts()? },
+        THREAD_START => TraceEvent::ThreadStart { name: r.str()?, id: r.id()?, ts: r.ts()? },
+        THREAD_END => TraceEvent::ThreadEnd { id: r.id()?, ts: r.ts()? },
+        IDLE_START => TraceEvent::IdleStart { id: r.id()?, parent_id: r.id()?, ts: r.ts()? },
+        IDLE_END => TraceEvent::IdleEnd { id: r.id()?, ts: r.ts()? },
+        WAKEUP => TraceEvent::Wakeup { waking_span: r.id()?, parked_span: r.id()?, ts: r.ts()? },
+        MARK => TraceEvent::Mark { id: r.id()?, name: r.str()?, ts: r.ts()?, metadata: r.metadata()? },
+        tag => return Err(DecodeError::UnknownTag(tag)),
+    })
+}
+
+/// Decode the record at the start of `buf`.  Returns `None` if `buf` doesn't
+/// hold a whole record yet, and otherwise the number of bytes the record
+/// took, which are consumed even if the record is bad.
+pub fn decode(buf: &[u8]) -> Option<(Result<TraceEvent, DecodeError>, usize)> {
+    if buf.len() < 4 {
+        return None;
+    }
+    let len = Reader { buf }.u32().unwrap() as usize;
+    if buf.len() < 4 + len {
+        return None;
+    }
+    let mut reader = Reader { buf: &buf[4..4 + len] };
+    Some((decode_payload(&mut reader), 4 + len))
+}
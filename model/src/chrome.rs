@@ -0,0 +1,78 @@
+//! Export to the Chrome trace event format, for chrome://tracing, Perfetto
+//! and speedscope.
+//!
+//! Each thread span becomes a thread.  Sync and idle spans are complete
+//! events on their thread, async spans are nestable async events with each
+//! poll as a complete event on the thread that ran it, and marks are
+//! instant events.  Spans still open are cut off at the end of the trace.
+
+use std::collections::HashMap;
+use std::time::Duration;
+use serde_json;
+use spans::{SpanKind, IDLE_SPAN_NAME};
+use trace::Trace;
+
+fn micros(d: Duration) -> f64 {
+    d.as_secs() as f64 * 1e6 + f64::from(d.subsec_nanos()) * 1e-3
+}
+
+pub fn chrome(trace: &Trace) -> serde_json::Value {
+    let now = trace.end_time();
+    let mut spans = trace.spans();
+    spans.sort_by_key(|s| (s.start, s.id.0));
+
+    let mut tids = HashMap::new();
+    let mut events = vec![];
+    for span in spans.iter().filter(|s| s.kind == SpanKind::Thread) {
+        let tid = tids.len() + 1;
+        tids.insert(span.id, tid);
+        events.push(json!({
+            "ph": "M", "name": "thread_name", "pid": 1, "tid": tid,
+            "args": { "name": span.name },
+        }));
+    }
+
+    for span in &spans {
+        let tid = match trace.thread(span.id).and_then(|t| tids.get(&t.id)) {
+            Some(&tid) => tid,
+            None => 0,
+        };
+        let end = span.end_or(now);
+        match span.kind {
+            SpanKind::Thread => (),
+            SpanKind::Sync | SpanKind::Idle => {
+                let name = if span.kind == SpanKind::Idle { IDLE_SPAN_NAME } else { span.name.as_str() };
+                events.push(json!({
+                    "ph": "X", "name": name, "cat": "sync", "pid": 1, "tid": tid,
+                    "ts": micros(span.start), "dur": micros(end - span.start),
+                    "args": span.metadata,
+                }));
+            },
+            SpanKind::Async => {
+                let id = format!("{:x}", span.id.0);
+                events.push(json!({
+                    "ph": "b", "name": span.name, "cat": "async", "id": id, "pid": 1, "tid": tid,
+                    "ts": micros(span.start), "args": span.metadata,
+                }));
+                let outcome = span.outcome.as_ref().map(|o| format!("{:?}", o));
+                events.push(json!({
+                    "ph": "e", "name": span.name, "cat": "async", "id": id, "pid": 1, "tid": tid,
+                    "ts": micros(end), "args": { "outcome": outcome },
+                }));
+                for segment in span.segments(now) {
+                    events.push(json!({
+                        "ph": "X", "name": span.name, "cat": "poll", "pid": 1, "tid": tid,
+                        "ts": micros(segment.start), "dur": micros(segment.end - segment.start),
+                    }));
+                }
+            },
+        }
+        for mark in &span.marks {
+            events.push(json!({
+                "ph": "i", "s": "t", "name": mark.name, "pid": 1, "tid": tid,
+                "ts": micros(mark.ts), "args": mark.metadata,
+            }));
+        }
+    }
+    json!({ "traceEvents": events, "displayTimeUnit": "ms" })
+}
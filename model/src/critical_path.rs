@@ -0,0 +1,204 @@
+//! Which chain of work determined how long a span took.
+//!
+//! The path is found by walking backward from the end of the root span.  At
+//! each point in time we blame, in order of preference:
+//!
+//! 1. if the span was on-CPU, the child it was running, descending into it,
+//!    or otherwise the span itself;
+//! 2. if it was off-CPU, a child that was still open, descending into it;
+//! 3. whoever woke the span up, jumping to the waking span at the time of the
+//!    wakeup;
+//! 4. otherwise nobody: the span was just waiting.
+//!
+//! Every instant of the root span is attributed to exactly one segment.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::time::Duration;
+use event::SpanId;
+use spans::Span;
+use trace::Trace;
+
+// Descending into children and following wakeups recurses, so past this
+// depth, time is blamed on the span we've got to rather than overflowing the
+// stack on deeply nested or cyclic spans.
+const MAX_DEPTH: usize = 1000;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum SegmentKind {
+    /// On-CPU.
+    Running,
+    /// Woken up, but not yet back on-CPU.
+    Runnable,
+    /// Off-CPU with nothing on the path to blame.
+    Waiting,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PathSegment {
+    pub span: SpanId,
+    pub kind: SegmentKind,
+    pub start: Duration,
+    pub end: Duration,
+}
+
+impl PathSegment {
+    pub fn duration(&self) -> Duration {
+        self.end - self.start
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CriticalPath {
+    pub root: SpanId,
+    /// In time order, covering the root span from start to end.
+    pub segments: Vec<PathSegment>,
+}
+
+impl CriticalPath {
+    pub fn duration(&self) -> Duration {
+        self.segments.iter().fold(Duration::new(0, 0), |total, s| total + s.duration())
+    }
+
+    /// Time attributed to each span on the path, longest first.
+    // `or_default` is newer than f2's toolchain.
+    #[allow(clippy::unwrap_or_default)]
+    pub fn by_span(&self) -> Vec<(SpanId, Duration)> {
+        let mut totals: HashMap<SpanId, Duration> = HashMap::new();
+        for segment in &self.segments {
+            *totals.entry(segment.span).or_insert_with(Duration::default) += segment.duration();
+        }
+        let mut totals: Vec<_> = totals.into_iter().collect();
+        totals.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        totals
+    }
+}
+
+/// The critical path of `root`, or `None` if there is no such span.  Open
+/// spans are treated as ending at the end of the trace.
+pub fn critical_path(trace: &Trace, root: SpanId) -> Option<CriticalPath> {
+    let span = trace.get(root)?;
+    let mut walker = Walker {
+        trace,
+        now: trace.end_time(),
+        segments: vec![],
+        depth: 0,
+    };
+    walker.walk(span, span.end_or(walker.now), span.start);
+    let mut segments = walker.segments;
+    segments.reverse();
+    Some(CriticalPath { root, segments })
+}
+
+struct Walker<'a> {
+    trace: &'a Trace,
+    now: Duration,
+    // Built backward, so in reverse time order.
+    segments: Vec<PathSegment>,
+    depth: usize,
+}
+
+impl<'a> Walker<'a> {
+    fn emit(&mut self, span: SpanId, kind: SegmentKind, start: Duration, end: Duration) {
+        if start >= end {
+            return;
+        }
+        if let Some(last) = self.segments.last_mut() {
+            if last.span == span && last.kind == kind && last.start == end {
+                last.start = start;
+                return;
+            }
+        }
+        self.segments.push(PathSegment { span, kind, start, end });
+    }
+
+    // Walk `span`, unless that's too deep, returning how far back it got.
+    fn descend(&mut self, span: &'a Span, t: Duration, floor: Duration) -> Option<Duration> {
+        if self.depth >= MAX_DEPTH {
+            return None;
+        }
+        self.depth += 1;
+        let reached = self.walk(span, t, floor);
+        self.depth -= 1;
+        Some(reached)
+    }
+
+    // Attribute `span`'s time from `t` back to `floor` (or its start, if
+    // later), returning how far back it got.
+    fn walk(&mut self, span: &'a Span, mut t: Duration, floor: Duration) -> Duration {
+        let floor = cmp::max(floor, span.start);
+        let now = self.now;
+        let trace = self.trace;
+        let children: Vec<&Span> = trace.children(span.id).iter().filter_map(|&id| trace.get(id)).collect();
+        let segments = span.segments(now);
+
+        while t > floor {
+            let mut lower = floor;
+            for child in &children {
+                if child.end_or(now) < t {
+                    lower = cmp::max(lower, child.end_or(now));
+                }
+            }
+
+            if let Some(segment) = segments.iter().find(|s| s.start < t && t <= s.end) {
+                // On-CPU: blame whichever child we were running, if any.
+                let running_child = children.iter()
+                    .filter(|c| c.end_or(now) >= t)
+                    .filter_map(|c| {
+                        let segments = c.segments(now);
+                        segments.iter().find(|s| s.start < t && t <= s.end).map(|s| (*c, s.start))
+                    })
+                    .max_by_key(|&(c, start)| (start, c.start));
+                if let Some((child, start)) = running_child {
+                    if let Some(reached) = self.descend(child, t, cmp::max(floor, start)) {
+                        t = reached;
+                        continue;
+                    }
+                }
+                lower = cmp::max(lower, segment.start);
+                for child in &children {
+                    for s in child.segments(now) {
+                        if s.end < t {
+                            lower = cmp::max(lower, s.end);
+                        }
+                    }
+                }
+                self.emit(span.id, SegmentKind::Running, lower, t);
+                t = lower;
+                continue;
+            }
+
+            // Off-CPU: if a child is still open, it's what we're waiting on.
+            let open_child = children.iter()
+                .filter(|c| c.start < t && c.end_or(now) >= t)
+                .max_by_key(|c| (c.end_or(now), c.start));
+            if let Some(child) = open_child {
+                if let Some(reached) = self.descend(child, t, floor) {
+                    t = reached;
+                    continue;
+                }
+            }
+
+            // Off-CPU, so we've been waiting since we last ran.
+            for segment in &segments {
+                if segment.end < t {
+                    lower = cmp::max(lower, segment.end);
+                }
+            }
+            let wakeup = span.woken_by.iter()
+                .filter(|w| w.ts < t && w.ts >= lower && w.waking_span != span.id)
+                .max_by_key(|w| w.ts);
+            let waker = wakeup.and_then(|w| trace.get(w.waking_span).map(|waker| (w.ts, waker)));
+            match waker {
+                Some((ts, waker)) if self.depth < MAX_DEPTH => {
+                    self.emit(span.id, SegmentKind::Runnable, ts, t);
+                    let reached = self.descend(waker, ts, lower).unwrap();
+                    self.emit(span.id, SegmentKind::Waiting, lower, reached);
+                },
+                _ => self.emit(span.id, SegmentKind::Waiting, lower, t),
+            }
+            t = lower;
+        }
+        t
+    }
+}
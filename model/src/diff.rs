@@ -0,0 +1,79 @@
+//! Compare two runs span by span.  Spans are matched by name path (see
+//! `Trace::name_paths`), since ids differ from run to run.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+use stats::Percentiles;
+use trace::Trace;
+
+/// Finished spans at one name path in one trace.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Summary {
+    pub count: usize,
+    pub latency: Percentiles,
+    pub cpu_time: Duration,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathDiff {
+    pub path: String,
+    /// `None` if the path only appears in the candidate.
+    pub baseline: Option<Summary>,
+    /// `None` if the path has disappeared from the candidate.
+    pub candidate: Option<Summary>,
+}
+
+impl PathDiff {
+    pub fn is_new(&self) -> bool {
+        self.baseline.is_none()
+    }
+
+    pub fn is_gone(&self) -> bool {
+        self.candidate.is_none()
+    }
+}
+
+// `or_default` is newer than f2's toolchain.
+#[allow(clippy::unwrap_or_default)]
+fn summarize(trace: &Trace) -> BTreeMap<String, Summary> {
+    let paths = trace.name_paths();
+    let mut spans: BTreeMap<String, (Summary, Vec<Duration>)> = BTreeMap::new();
+    for span in trace.spans() {
+        let end = match span.end {
+            Some(end) => end,
+            None => continue,
+        };
+        let &mut (ref mut summary, ref mut latencies) = spans.entry(paths[&span.id].clone())
+            .or_insert_with(Default::default);
+        summary.count += 1;
+        summary.cpu_time += span.cpu_time(end);
+        latencies.push(end - span.start);
+    }
+    spans.into_iter()
+        .map(|(path, (mut summary, mut latencies))| {
+            latencies.sort();
+            summary.latency = Percentiles::of(&latencies);
+            (path, summary)
+        })
+        .collect()
+}
+
+/// Every name path in either trace, sorted by path.
+pub fn diff(baseline: &Trace, candidate: &Trace) -> Vec<PathDiff> {
+    let mut baseline = summarize(baseline);
+    let candidate = summarize(candidate);
+    let mut diffs: Vec<PathDiff> = candidate.into_iter()
+        .map(|(path, summary)| PathDiff {
+            baseline: baseline.remove(&path),
+            candidate: Some(summary),
+            path,
+        })
+        .collect();
+    diffs.extend(baseline.into_iter().map(|(path, summary)| PathDiff {
+        path,
+        baseline: Some(summary),
+        candidate: None,
+    }));
+    diffs.sort_by(|a, b| a.path.cmp(&b.path));
+    diffs
+}
@@ -0,0 +1,252 @@
+use std::fmt;
+use std::time::Duration;
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde_json;
+
+/// Serialized as a number, but also read from a decimal or `0x`-prefixed hex
+/// string, which JavaScript can't round the way it does numbers above 2^53.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize)]
+pub struct SpanId(pub u64);
+
+struct SpanIdVisitor;
+
+impl<'de> Visitor<'de> for SpanIdVisitor {
+    type Value = SpanId;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a span id as a number, or a decimal or 0x-prefixed hex string")
+    }
+
+    fn visit_u64<E: de::Error>(self, n: u64) -> Result<SpanId, E> {
+        Ok(SpanId(n))
+    }
+
+    fn visit_i64<E: de::Error>(self, n: i64) -> Result<SpanId, E> {
+        if n < 0 {
+            return Err(E::invalid_value(de::Unexpected::Signed(n), &self));
+        }
+        Ok(SpanId(n as u64))
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<SpanId, E> {
+        let parsed = if s.starts_with("0x") || s.starts_with("0X") {
+            u64::from_str_radix(&s[2..], 16)
+        } else {
+            s.parse()
+        };
+        parsed.map(SpanId).map_err(|_| E::invalid_value(de::Unexpected::Str(s), &self))
+    }
+}
+
+impl<'de> Deserialize<'de> for SpanId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<SpanId, D::Error> {
+        deserializer.deserialize_any(SpanIdVisitor)
+    }
+}
+
+struct TimestampVisitor;
+
+impl<'de> Visitor<'de> for TimestampVisitor {
+    type Value = Duration;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a timestamp as {{\"secs\", \"nanos\"}}, integer nanoseconds or float seconds")
+    }
+
+    fn visit_u64<E: de::Error>(self, nanos: u64) -> Result<Duration, E> {
+        Ok(Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32))
+    }
+
+    fn visit_i64<E: de::Error>(self, nanos: i64) -> Result<Duration, E> {
+        if nanos < 0 {
+            return Err(E::invalid_value(de::Unexpected::Signed(nanos), &self));
+        }
+        self.visit_u64(nanos as u64)
+    }
+
+    // `Range::contains` is newer than f2's toolchain.
+    #[allow(clippy::manual_range_contains)]
+    fn visit_f64<E: de::Error>(self, secs: f64) -> Result<Duration, E> {
+        // Beyond about 580 years, nanoseconds overflow a u64.
+        if !(secs >= 0.0 && secs < 1.8e10) {
+            return Err(E::invalid_value(de::Unexpected::Float(secs), &self));
+        }
+        self.visit_u64((secs * 1e9).round() as u64)
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Duration, A::Error> {
+        let (mut secs, mut nanos) = (None, None);
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "secs" => secs = Some(map.next_value::<u64>()?),
+                "nanos" => nanos = Some(map.next_value::<u32>()?),
+                _ => {
+                    map.next_value::<de::IgnoredAny>()?;
+                },
+            }
+        }
+        let secs = secs.ok_or_else(|| de::Error::missing_field("secs"))?;
+        let nanos = nanos.ok_or_else(|| de::Error::missing_field("nanos"))?;
+        if nanos >= 1_000_000_000 {
+            return Err(de::Error::invalid_value(de::Unexpected::Unsigned(nanos as u64), &"nanos under a second"));
+        }
+        Ok(Duration::new(secs, nanos))
+    }
+}
+
+/// Reads a timestamp written as `{"secs": .., "nanos": ..}` (how `Duration`
+/// serializes), as integer nanoseconds, or as float seconds, which is easiest
+/// by hand.  Note that integers are always nanoseconds: `"ts": 265` is 265ns,
+/// and 265 seconds is `"ts": 265.0`.
+fn deserialize_ts<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    deserializer.deserialize_any(TimestampVisitor)
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub enum AsyncOutcome {
+    Success,
+    Cancelled,
+    Error(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum TraceEvent {
+    AsyncStart {
+        name: String,
+        id: SpanId,
+        parent_id: SpanId,
+        #[serde(deserialize_with = "deserialize_ts")]
+        ts: Duration,
+        #[serde(default)]
+        metadata: serde_json::Value,
+    },
+    AsyncOnCPU {
+        id: SpanId,
+        #[serde(deserialize_with = "deserialize_ts")]
+        ts: Duration,
+    },
+    AsyncOffCPU {
+        id: SpanId,
+        #[serde(deserialize_with = "deserialize_ts")]
+        ts: Duration,
+    },
+    AsyncEnd {
+        id: SpanId,
+        #[serde(deserialize_with = "deserialize_ts")]
+        ts: Duration,
+        outcome: AsyncOutcome,
+    },
+
+    SyncStart {
+        name: String,
+        id: SpanId,
+        parent_id: SpanId,
+        #[serde(deserialize_with = "deserialize_ts")]
+        ts: Duration,
+        #[serde(default)]
+        metadata: serde_json::Value,
+    },
+    SyncEnd {
+        id: SpanId,
+        #[serde(deserialize_with = "deserialize_ts")]
+        ts: Duration,
+    },
+
+    ThreadStart {
+        name: String,
+        id: SpanId,
+        #[serde(deserialize_with = "deserialize_ts")]
+        ts: Duration,
+    },
+    ThreadEnd {
+        id: SpanId,
+        #[serde(deserialize_with = "deserialize_ts")]
+        ts: Duration,
+    },
+
+    IdleStart {
+        id: SpanId,
+        parent_id: SpanId,
+        #[serde(deserialize_with = "deserialize_ts")]
+        ts: Duration,
+    },
+    IdleEnd {
+        id: SpanId,
+        #[serde(deserialize_with = "deserialize_ts")]
+        ts: Duration,
+    },
+
+    Wakeup {
+        waking_span: SpanId,
+        parked_span: SpanId,
+        #[serde(deserialize_with = "deserialize_ts")]
+        ts: Duration,
+    },
+
+    // A point event within span `id`, such as a message being sent.
+    Mark {
+        id: SpanId,
+        name: String,
+        #[serde(deserialize_with = "deserialize_ts")]
+        ts: Duration,
+        #[serde(default)]
+        metadata: serde_json::Value,
+    },
+}
+
+impl TraceEvent {
+    pub fn ts(&self) -> Duration {
+        use self::TraceEvent::*;
+        match *self {
+            AsyncStart { ts, .. }
+            | AsyncOnCPU { ts, .. }
+            | AsyncOffCPU { ts, .. }
+            | AsyncEnd { ts, .. }
+            | SyncStart { ts, .. }
+            | SyncEnd { ts, .. }
+            | ThreadStart { ts, .. }
+            | ThreadEnd { ts, .. }
+            | IdleStart { ts, .. }
+            | IdleEnd { ts, .. }
+            | Wakeup { ts, .. }
+            | Mark { ts, .. } => ts,
+        }
+    }
+
+    /// The span this event is about.  `None` for wakeups, which involve two.
+    pub fn id(&self) -> Option<SpanId> {
+        use self::TraceEvent::*;
+        match *self {
+            AsyncStart { id, .. }
+            | AsyncOnCPU { id, .. }
+            | AsyncOffCPU { id, .. }
+            | AsyncEnd { id, .. }
+            | SyncStart { id, .. }
+            | SyncEnd { id, .. }
+            | ThreadStart { id, .. }
+            | ThreadEnd { id, .. }
+            | IdleStart { id, .. }
+            | IdleEnd { id, .. }
+            | Mark { id, .. } => Some(id),
+            Wakeup { .. } => None,
+        }
+    }
+
+    pub fn parent_id(&self) -> Option<SpanId> {
+        use self::TraceEvent::*;
+        match *self {
+            AsyncStart { parent_id, .. }
+            | SyncStart { parent_id, .. }
+            | IdleStart { parent_id, .. } => Some(parent_id),
+            SyncEnd { .. }
+            | IdleEnd { .. }
+            | ThreadStart { .. }
+            | ThreadEnd { .. }
+            | AsyncOnCPU { .. }
+            | AsyncOffCPU { .. }
+            | AsyncEnd { .. }
+            | Wakeup { .. }
+            | Mark { .. } => None,
+        }
+    }
+}
@@ -0,0 +1,77 @@
+//! Collapsed stacks (`thread;parent;child 1234`) for flame graph tools such
+//! as inferno and flamegraph.pl.  Each span's stack is its name path (see
+//! `Trace::name_paths`), and its weight is its self time in microseconds: the
+//! time not covered by any of its children.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+use spans::{Segment, Span};
+use trace::Trace;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Weight {
+    /// On-CPU time, from `AsyncOnCPU`/`AsyncOffCPU` and sync spans.
+    Cpu,
+    /// Wall-clock time from start to end.
+    Wall,
+}
+
+fn intervals(span: &Span, weight: Weight, now: Duration) -> Vec<Segment> {
+    match weight {
+        Weight::Cpu => span.segments(now),
+        Weight::Wall => vec![Segment { start: span.start, end: span.end_or(now) }],
+    }
+}
+
+// Total length of `own` not covered by `holes`.
+fn uncovered(own: &[Segment], mut holes: Vec<Segment>) -> Duration {
+    holes.sort_by_key(|h| h.start);
+    let mut total = Duration::new(0, 0);
+    for segment in own {
+        let mut t = segment.start;
+        for hole in &holes {
+            if hole.end <= t {
+                continue;
+            }
+            if hole.start >= segment.end {
+                break;
+            }
+            if hole.start > t {
+                total += hole.start - t;
+            }
+            t = hole.end;
+            if t >= segment.end {
+                break;
+            }
+        }
+        if segment.end > t {
+            total += segment.end - t;
+        }
+    }
+    total
+}
+
+/// Folded stacks and their weights in microseconds, sorted by stack.
+/// Stacks with no weight are left out.
+// `or_default` is newer than f2's toolchain.
+#[allow(clippy::unwrap_or_default)]
+pub fn folded(trace: &Trace, weight: Weight) -> Vec<(String, u64)> {
+    let now = trace.end_time();
+    let paths = trace.name_paths();
+    let mut totals: BTreeMap<String, Duration> = BTreeMap::new();
+    for span in trace.spans() {
+        let own = intervals(span, weight, now);
+        let holes: Vec<Segment> = trace.children(span.id).iter()
+            .filter_map(|&id| trace.get(id))
+            .flat_map(|child| intervals(child, weight, now))
+            .collect();
+        let time = uncovered(&own, holes);
+        if time > Duration::new(0, 0) {
+            *totals.entry(paths[&span.id].clone()).or_insert_with(Duration::default) += time;
+        }
+    }
+    totals.into_iter()
+        .map(|(stack, time)| (stack, (time.as_secs() * 1_000_000_000 + u64::from(time.subsec_nanos())) / 1000))
+        .filter(|&(_, micros)| micros > 0)
+        .collect()
+}
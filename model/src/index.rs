@@ -0,0 +1,129 @@
+//! Finds the spans overlapping a time range without scanning the whole
+//! trace.  Entries are kept in insertion order and grouped into fixed-size
+//! blocks that remember the range they cover.  Spans finish roughly in time
+//! order, so blocks stay narrow and most of them are skipped by a query,
+//! while inserting stays cheap enough for a trace that is still streaming in.
+
+use std::slice;
+use std::time::Duration;
+
+const BLOCK_SIZE: usize = 64;
+
+#[derive(Copy, Clone, Debug)]
+struct Bounds {
+    start: Duration,
+    end: Duration,
+}
+
+#[derive(Clone, Debug)]
+struct Entry<T> {
+    start: Duration,
+    end: Duration,
+    value: T,
+}
+
+#[derive(Clone, Debug)]
+pub struct IntervalIndex<T> {
+    entries: Vec<Entry<T>>,
+    // `blocks[i]` covers `entries[i * BLOCK_SIZE..(i + 1) * BLOCK_SIZE]`.
+    blocks: Vec<Bounds>,
+}
+
+impl<T> Default for IntervalIndex<T> {
+    fn default() -> Self {
+        IntervalIndex { entries: vec![], blocks: vec![] }
+    }
+}
+
+impl<T> IntervalIndex<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, start: Duration, end: Duration, value: T) {
+        if self.entries.len() == self.blocks.len() * BLOCK_SIZE {
+            self.blocks.push(Bounds { start, end });
+        } else {
+            let bounds = self.blocks.last_mut().unwrap();
+            if start < bounds.start {
+                bounds.start = start;
+            }
+            if end > bounds.end {
+                bounds.end = end;
+            }
+        }
+        self.entries.push(Entry { start, end, value });
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.blocks.clear();
+    }
+
+    /// Every value, in insertion order.
+    pub fn iter<'a>(&'a self) -> Iter<'a, T> {
+        Iter { inner: self.entries.iter() }
+    }
+
+    /// Values whose interval overlaps `start..end`, in insertion order.
+    pub fn overlapping<'a>(&'a self, start: Duration, end: Duration) -> Overlapping<'a, T> {
+        Overlapping { index: self, start, end, block: 0, pos: 0 }
+    }
+}
+
+pub struct Iter<'a, T: 'a> {
+    inner: slice::Iter<'a, Entry<T>>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        self.inner.next().map(|e| &e.value)
+    }
+}
+
+pub struct Overlapping<'a, T: 'a> {
+    index: &'a IntervalIndex<T>,
+    start: Duration,
+    end: Duration,
+    block: usize,
+    pos: usize,
+}
+
+impl<'a, T> Iterator for Overlapping<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        let index = self.index;
+        loop {
+            if self.pos == self.block * BLOCK_SIZE {
+                // Entering a new block: skip ahead to the next one that can
+                // contain a match.
+                while let Some(bounds) = index.blocks.get(self.block) {
+                    if bounds.start < self.end && bounds.end > self.start {
+                        break;
+                    }
+                    self.block += 1;
+                }
+                self.pos = self.block * BLOCK_SIZE;
+            }
+            let entry = index.entries.get(self.pos)?;
+            self.pos += 1;
+            if self.pos == (self.block + 1) * BLOCK_SIZE {
+                self.block += 1;
+            }
+            if entry.start < self.end && entry.end > self.start {
+                return Some(&entry.value);
+            }
+        }
+    }
+}
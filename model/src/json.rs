@@ -0,0 +1,84 @@
+//! Options for writing events as JSON.  Readers accept every form, so these
+//! only matter to writers, and to consumers outside Rust such as the
+//! TypeScript frontend, where numbers are doubles and ids above 2^53 get
+//! rounded into each other.
+
+use std::time::Duration;
+use serde_json::{self, Value};
+use event::TraceEvent;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum IdFormat {
+    Number,
+    /// A decimal string.
+    String,
+    /// A `0x`-prefixed hex string.
+    Hex,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TimestampFormat {
+    /// `{"secs": 1, "nanos": 500}`, as `Duration` serializes.
+    Struct,
+    /// Integer nanoseconds, such as `1000000500`.
+    Nanos,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct JsonFormat {
+    pub ids: IdFormat,
+    pub timestamps: TimestampFormat,
+}
+
+impl Default for JsonFormat {
+    fn default() -> Self {
+        JsonFormat { ids: IdFormat::Number, timestamps: TimestampFormat::Struct }
+    }
+}
+
+// Fields holding span ids, across all event types.
+const ID_FIELDS: &[&str] = &["id", "parent_id", "waking_span", "parked_span"];
+
+fn nanos(ts: Duration) -> u64 {
+    ts.as_secs().saturating_mul(1_000_000_000).saturating_add(u64::from(ts.subsec_nanos()))
+}
+
+impl JsonFormat {
+    pub const LATEST_VERSION: u32 = 3;
+
+    /// The format with a given version number, for config flags: version 1
+    /// is the original encoding, version 2 writes ids as strings, and
+    /// version 3 also writes timestamps as integer nanoseconds.  The
+    /// TypeScript frontend reads bare numbers as seconds, so stick to version
+    /// 2 for it.
+    pub fn version(version: u32) -> Option<Self> {
+        match version {
+            1 => Some(JsonFormat::default()),
+            2 => Some(JsonFormat { ids: IdFormat::String, timestamps: TimestampFormat::Struct }),
+            3 => Some(JsonFormat { ids: IdFormat::String, timestamps: TimestampFormat::Nanos }),
+            _ => None,
+        }
+    }
+
+    pub fn to_value(&self, event: &TraceEvent) -> Value {
+        let mut value = serde_json::to_value(event).expect("events always serialize");
+        // Externally tagged: `{"AsyncStart": {...fields}}`.
+        for fields in value.as_object_mut().into_iter().flat_map(|o| o.values_mut()) {
+            if self.timestamps == TimestampFormat::Nanos {
+                fields["ts"] = Value::from(nanos(event.ts()));
+            }
+            for &field in ID_FIELDS {
+                let id = match fields.get(field).and_then(Value::as_u64) {
+                    Some(id) => id,
+                    None => continue,
+                };
+                fields[field] = match self.ids {
+                    IdFormat::Number => continue,
+                    IdFormat::String => Value::String(id.to_string()),
+                    IdFormat::Hex => Value::String(format!("0x{:x}", id)),
+                };
+            }
+        }
+        value
+    }
+}
@@ -0,0 +1,30 @@
+//! The trace format and the span model built from it, shared by the backend,
+//! the server, f2 and the analysis tools.
+
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+
+pub mod binary;
+pub mod chrome;
+pub mod critical_path;
+pub mod diff;
+mod event;
+pub mod folded;
+pub mod index;
+pub mod json;
+pub mod lod;
+pub mod slice;
+pub mod spans;
+pub mod stats;
+mod trace;
+
+pub use event::{AsyncOutcome, SpanId, TraceEvent};
+pub use index::IntervalIndex;
+pub use spans::{Reconstructor, Segment, Span, SpanKind, Wakeup};
+pub use trace::Trace;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,307 @@
+//! Level-of-detail summaries, for drawing traces too big to show span by span
+//! when zoomed out.  Time is cut into buckets, and for each thread and bucket
+//! we keep how long the thread was busy, the span it spent most of that time
+//! in, and how many spans started.  Buckets come in levels, each `FACTOR`
+//! times as wide as the last, so any view can be drawn from about as many
+//! buckets as it has room for.
+//!
+//! Busy means running a sync span or polling an async one, with nested spans'
+//! time going to the innermost.  A stretch of busy time is recorded in the
+//! buckets it partly covers at each level, and in the widest ones it covers
+//! completely, so long spans cost no more than short ones.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::time::Duration;
+use event::{SpanId, TraceEvent};
+use spans::IDLE_SPAN_NAME;
+
+/// Width of the finest buckets, in nanoseconds.
+pub const BASE_WIDTH: u64 = 16_000;
+pub const FACTOR: u64 = 4;
+/// The widest buckets are about three days.
+pub const LEVELS: usize = 18;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Bucket {
+    pub start: Duration,
+    /// The fraction of the bucket the thread was busy for.
+    pub busy: f64,
+    /// The span the thread was busy in for longest.
+    pub name: Option<String>,
+    /// Spans started.
+    pub count: u32,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Row {
+    pub thread: String,
+    /// Only buckets with anything in them, in order.
+    pub buckets: Vec<Bucket>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Summary {
+    /// Of each bucket.
+    pub width: Duration,
+    pub rows: Vec<Row>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct Accum {
+    busy: u64,
+    count: u32,
+    // Busy time by name.
+    names: Vec<(u32, u64)>,
+    // Busy throughout with this name, as are the buckets inside, which
+    // aren't recorded separately.
+    covered: Option<u32>,
+}
+
+impl Accum {
+    fn add(&mut self, name: u32, busy: u64) {
+        self.busy += busy;
+        match self.names.iter_mut().find(|n| n.0 == name) {
+            Some(n) => n.1 += busy,
+            None => self.names.push((name, busy)),
+        }
+    }
+}
+
+fn nanos(d: Duration) -> u64 {
+    d.as_secs().saturating_mul(1_000_000_000).saturating_add(d.subsec_nanos() as u64)
+}
+
+fn duration(nanos: u64) -> Duration {
+    Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+}
+
+fn width(level: usize) -> u64 {
+    BASE_WIDTH * FACTOR.pow(level as u32)
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Thread {
+    name: String,
+    // Spans on-CPU and their names, innermost last.
+    running: Vec<(SpanId, u32)>,
+    // When busy time has been recorded up to.
+    last: u64,
+    levels: Vec<HashMap<u64, Accum>>,
+}
+
+impl Thread {
+    fn new(name: String, ts: u64) -> Self {
+        Thread {
+            name,
+            running: vec![],
+            last: ts,
+            levels: (0..LEVELS).map(|_| HashMap::new()).collect(),
+        }
+    }
+
+    // Record the thread busy in `name` over `[from, to)`.
+    // `div_ceil` and `or_default` are newer than f2's toolchain.
+    #[allow(clippy::manual_div_ceil, clippy::unwrap_or_default)]
+    fn busy(&mut self, from: u64, to: u64, name: u32) {
+        for level in 0..LEVELS {
+            let w = width(level);
+            let (first, end) = (from / w, (to - 1) / w + 1);
+            // Buckets whose parents are wholly inside are implied by them.
+            let (skip_start, skip_end) = if level + 1 < LEVELS {
+                let parent = w * FACTOR;
+                ((from + parent - 1) / parent * FACTOR, to / parent * FACTOR)
+            } else {
+                (0, 0)
+            };
+            let ranges = if skip_start < skip_end {
+                [(first, skip_start), (skip_end, end)]
+            } else {
+                [(first, end), (0, 0)]
+            };
+            for &(a, b) in &ranges {
+                for i in a..b {
+                    let start = i * w;
+                    let overlap = cmp::min(to, start + w) - cmp::max(from, start);
+                    let accum = self.levels[level].entry(i).or_insert_with(Accum::default);
+                    accum.add(name, overlap);
+                    if overlap == w {
+                        accum.covered = Some(name);
+                    }
+                }
+            }
+        }
+    }
+
+    fn advance(&mut self, ts: u64) {
+        if ts <= self.last {
+            return;
+        }
+        if let Some(&(_, name)) = self.running.last() {
+            let last = self.last;
+            self.busy(last, ts, name);
+        }
+        self.last = ts;
+    }
+
+    #[allow(clippy::unwrap_or_default)]
+    fn started(&mut self, ts: u64) {
+        for level in 0..LEVELS {
+            self.levels[level].entry(ts / width(level)).or_insert_with(Accum::default).count += 1;
+        }
+    }
+
+    // The bucket as it stands at `now`, counting whatever's running since the
+    // thread's last event, which isn't recorded until its next.
+    fn bucket(&self, level: usize, i: u64, names: &[String], now: u64) -> Option<Bucket> {
+        let w = width(level);
+        let mut accum = self.levels[level].get(&i).cloned().unwrap_or_default();
+        if accum.busy == 0 {
+            let mut parent = i;
+            for l in level + 1..LEVELS {
+                parent /= FACTOR;
+                if let Some(name) = self.levels[l].get(&parent).and_then(|a| a.covered) {
+                    accum.add(name, w);
+                    break;
+                }
+            }
+        }
+        if let Some(&(_, name)) = self.running.last() {
+            let (from, to) = (cmp::max(self.last, i * w), cmp::min(now, (i + 1) * w));
+            if from < to {
+                accum.add(name, to - from);
+            }
+        }
+        if accum.busy == 0 && accum.count == 0 {
+            return None;
+        }
+        let name = accum.names.iter()
+            .max_by_key(|&&(id, busy)| (busy, !id))
+            .map(|&(id, _)| names[id as usize].clone());
+        Some(Bucket {
+            start: duration(i * w),
+            busy: (accum.busy as f64 / w as f64).min(1.0),
+            name,
+            count: accum.count,
+        })
+    }
+}
+
+/// Builds summaries from a trace's events, as they arrive.  It can be
+/// serialized to carry on from where it left off later, which keeps the
+/// buckets and the spans still open.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Builder {
+    threads: Vec<Thread>,
+    // Open spans' threads and names.
+    spans: HashMap<SpanId, (usize, u32)>,
+    // The same for spans that have ended, for any tasks they spawned that
+    // outlive them.  It isn't saved, so after reloading, those tasks go on
+    // the latest thread, as orphans do.
+    #[serde(skip)]
+    ended: HashMap<SpanId, (usize, u32)>,
+    names: Vec<String>,
+    // Rebuilt from `names` after reloading.
+    #[serde(skip)]
+    name_ids: HashMap<String, u32>,
+    now: u64,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn intern(&mut self, name: &str) -> u32 {
+        if self.name_ids.len() < self.names.len() {
+            self.name_ids = self.names.iter().enumerate().map(|(i, n)| (n.clone(), i as u32)).collect();
+        }
+        if let Some(&id) = self.name_ids.get(name) {
+            return id;
+        }
+        let id = self.names.len() as u32;
+        self.names.push(name.to_string());
+        self.name_ids.insert(name.to_string(), id);
+        id
+    }
+
+    fn start(&mut self, id: SpanId, parent_id: SpanId, name: &str, ts: u64, running: bool) {
+        // Spans whose parents we never saw go on the latest thread.
+        let thread = match self.spans.get(&parent_id).or_else(|| self.ended.get(&parent_id)) {
+            Some(&(thread, _)) => thread,
+            None if !self.threads.is_empty() => self.threads.len() - 1,
+            None => return,
+        };
+        let name = self.intern(name);
+        self.spans.insert(id, (thread, name));
+        let thread = &mut self.threads[thread];
+        thread.advance(ts);
+        thread.started(ts);
+        if running {
+            thread.running.push((id, name));
+        }
+    }
+
+    fn stop(&mut self, id: SpanId, ts: u64) {
+        if let Some(&(thread, _)) = self.spans.get(&id) {
+            let thread = &mut self.threads[thread];
+            thread.advance(ts);
+            thread.running.retain(|&(s, _)| s != id);
+        }
+    }
+
+    fn end(&mut self, id: SpanId, ts: u64) {
+        self.stop(id, ts);
+        if let Some(span) = self.spans.remove(&id) {
+            self.ended.insert(id, span);
+        }
+    }
+
+    pub fn add_event(&mut self, event: &TraceEvent) {
+        let ts = nanos(event.ts());
+        self.now = cmp::max(self.now, ts);
+        match *event {
+            TraceEvent::ThreadStart { ref name, id, .. } => {
+                self.spans.insert(id, (self.threads.len(), 0));
+                self.threads.push(Thread::new(name.clone(), ts));
+            },
+            TraceEvent::AsyncStart { ref name, id, parent_id, .. } => self.start(id, parent_id, name, ts, false),
+            TraceEvent::SyncStart { ref name, id, parent_id, .. } => self.start(id, parent_id, name, ts, true),
+            TraceEvent::IdleStart { id, parent_id, .. } => self.start(id, parent_id, IDLE_SPAN_NAME, ts, false),
+            TraceEvent::AsyncOnCPU { id, .. } => {
+                if let Some(&(thread, name)) = self.spans.get(&id) {
+                    let thread = &mut self.threads[thread];
+                    thread.advance(ts);
+                    thread.running.push((id, name));
+                }
+            },
+            TraceEvent::AsyncOffCPU { id, .. } => self.stop(id, ts),
+            TraceEvent::AsyncEnd { id, .. }
+            | TraceEvent::SyncEnd { id, .. }
+            | TraceEvent::IdleEnd { id, .. }
+            | TraceEvent::ThreadEnd { id, .. } => self.end(id, ts),
+            TraceEvent::Wakeup { .. } | TraceEvent::Mark { .. } => (),
+        }
+    }
+
+    /// Summarise `[start, end]` in buckets from the finest level with no
+    /// more than about `buckets` of them across it.  Spans still running are
+    /// counted up to the latest event so far.
+    pub fn summarise(&self, start: Duration, end: Duration, buckets: u64) -> Summary {
+        let (start, end) = (nanos(start), nanos(end));
+        let buckets = cmp::max(buckets, 1);
+        let mut level = 0;
+        while level + 1 < LEVELS && width(level).saturating_mul(buckets) < end.saturating_sub(start) {
+            level += 1;
+        }
+        let w = width(level);
+        let names = &self.names;
+        let rows = self.threads.iter()
+            .map(|thread| Row {
+                thread: thread.name.clone(),
+                buckets: (start / w..end / w + 1).filter_map(|i| thread.bucket(level, i, names, self.now)).collect(),
+            })
+            .collect();
+        Summary { width: duration(w), rows }
+    }
+}
@@ -0,0 +1,165 @@
+//! Cut a self-contained trace out of a bigger one: either a window of time or
+//! the subtree under one span.
+//!
+//! Every span in the slice keeps its original start event, as does every
+//! ancestor needed to reach its thread, so the output validates on its own.
+//! Spans still running at the end of the slice are closed there, with async
+//! ones ending as `Cancelled`.  Ancestors only included for structure keep
+//! just their start and end.
+
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use event::{AsyncOutcome, SpanId, TraceEvent};
+use spans::{Span, SpanKind};
+use trace::Trace;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Selection {
+    /// Spans overlapping `[start, end]`, with events outside it left out.
+    Window { start: Duration, end: Duration },
+    /// A span and all its descendants.
+    Subtree(SpanId),
+}
+
+// How much of a span the slice keeps.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Keep {
+    All,
+    StartAndEnd,
+}
+
+fn descendants(trace: &Trace, root: SpanId) -> HashSet<SpanId> {
+    let mut found = HashSet::new();
+    let mut stack = vec![root];
+    while let Some(id) = stack.pop() {
+        if found.insert(id) {
+            stack.extend_from_slice(trace.children(id));
+        }
+    }
+    found
+}
+
+fn start_event(span: &Span) -> TraceEvent {
+    let (name, id, ts, metadata) = (span.name.clone(), span.id, span.start, span.metadata.clone());
+    // Only threads have no parent.
+    let parent_id = span.parent_id.unwrap_or(id);
+    match span.kind {
+        SpanKind::Thread => TraceEvent::ThreadStart { name, id, ts },
+        SpanKind::Async => TraceEvent::AsyncStart { name, id, parent_id, ts, metadata },
+        SpanKind::Sync => TraceEvent::SyncStart { name, id, parent_id, ts, metadata },
+        SpanKind::Idle => TraceEvent::IdleStart { id, parent_id, ts },
+    }
+}
+
+fn end_event(span: &Span, ts: Duration) -> TraceEvent {
+    let id = span.id;
+    match span.kind {
+        SpanKind::Thread => TraceEvent::ThreadEnd { id, ts },
+        SpanKind::Async => {
+            let outcome = match span.outcome {
+                Some(ref outcome) if span.end == Some(ts) => outcome.clone(),
+                _ => AsyncOutcome::Cancelled,
+            };
+            TraceEvent::AsyncEnd { id, ts, outcome }
+        },
+        SpanKind::Sync => TraceEvent::SyncEnd { id, ts },
+        SpanKind::Idle => TraceEvent::IdleEnd { id, ts },
+    }
+}
+
+/// The events of the slice of `trace` picked by `selection`, in time order.
+pub fn slice(trace: &Trace, selection: Selection) -> Vec<TraceEvent> {
+    let now = trace.end_time();
+    let (start, end, selected) = match selection {
+        Selection::Window { start, end } => {
+            let end = cmp::min(end, now);
+            let ids = trace.spans().into_iter()
+                .filter(|s| s.start <= end && s.end_or(now) >= start)
+                .map(|s| s.id)
+                .collect();
+            (start, end, ids)
+        },
+        Selection::Subtree(root) => (Duration::new(0, 0), now, descendants(trace, root)),
+    };
+
+    let mut keep: HashMap<SpanId, Keep> = HashMap::new();
+    for &id in &selected {
+        if trace.get(id).is_some() {
+            keep.insert(id, Keep::All);
+        }
+    }
+    for &id in &selected {
+        let mut span = trace.get(id);
+        // Bounded, in case of a malformed trace with a cycle.
+        for _ in 0..trace.len() {
+            span = match span.and_then(|s| s.parent_id).and_then(|p| trace.get(p)) {
+                Some(parent) => {
+                    keep.entry(parent.id).or_insert(Keep::StartAndEnd);
+                    Some(parent)
+                },
+                None => break,
+            };
+        }
+    }
+
+    let depth_of = |span: &Span| {
+        let mut depth = 0;
+        let mut parent = span.parent_id;
+        while let Some(p) = parent.and_then(|p| trace.get(p)) {
+            depth += 1;
+            if depth > trace.len() {
+                break;
+            }
+            parent = p.parent_id;
+        }
+        depth
+    };
+    let in_window = |ts: Duration| start <= ts && ts <= end;
+
+    // Events tagged for sorting: at equal times parents start before their
+    // children, a span goes off-CPU before it's polled again, and children
+    // end before their parents.
+    let mut events: Vec<((Duration, usize, usize, u64), TraceEvent)> = vec![];
+    for (&id, &how) in &keep {
+        let span = trace.get(id).unwrap();
+        let depth = depth_of(span);
+        let close = cmp::min(span.end_or(now), end);
+        events.push(((span.start, 0, depth, id.0), start_event(span)));
+        events.push(((close, 4, !depth, id.0), end_event(span, close)));
+        if how == Keep::StartAndEnd {
+            continue;
+        }
+        if span.kind == SpanKind::Async {
+            for segment in span.segments(close) {
+                let on = cmp::max(segment.start, start);
+                let off = cmp::min(segment.end, close);
+                if on < off {
+                    events.push(((on, 2, depth, id.0), TraceEvent::AsyncOnCPU { id, ts: on }));
+                    events.push(((off, 1, depth, id.0), TraceEvent::AsyncOffCPU { id, ts: off }));
+                }
+            }
+        }
+        for mark in span.marks.iter().filter(|m| in_window(m.ts) && m.ts <= close) {
+            let event = TraceEvent::Mark {
+                id,
+                name: mark.name.clone(),
+                ts: mark.ts,
+                metadata: mark.metadata.clone(),
+            };
+            events.push(((mark.ts, 3, depth, id.0), event));
+        }
+        // Wakeups from outside the slice are kept as long as the waker is
+        // there at all.
+        for wakeup in span.woken_by.iter().filter(|w| in_window(w.ts) && keep.contains_key(&w.waking_span)) {
+            let event = TraceEvent::Wakeup {
+                waking_span: wakeup.waking_span,
+                parked_span: wakeup.parked_span,
+                ts: wakeup.ts,
+            };
+            events.push(((wakeup.ts, 3, depth, id.0), event));
+        }
+    }
+    events.sort_by_key(|&(key, _)| key);
+    events.into_iter().map(|(_, event)| event).collect()
+}
@@ -0,0 +1,276 @@
+//! Rebuilds spans from a stream of events.  A `Reconstructor` only holds the
+//! spans that are still open; each span is handed back when it ends, so
+//! callers decide what to keep.
+
+use std::collections::HashMap;
+use std::collections::hash_map;
+use std::fmt;
+use std::time::Duration;
+use serde_json;
+use event::{AsyncOutcome, SpanId, TraceEvent};
+
+/// The name given to idle spans, which have none of their own.
+pub const IDLE_SPAN_NAME: &str = "idle";
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum SpanKind {
+    Thread,
+    Async,
+    Sync,
+    Idle,
+}
+
+/// A stretch of time a span spent on-CPU.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Segment {
+    pub start: Duration,
+    pub end: Duration,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Wakeup {
+    pub waking_span: SpanId,
+    pub parked_span: SpanId,
+    pub ts: Duration,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mark {
+    pub name: String,
+    pub ts: Duration,
+    pub metadata: serde_json::Value,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Span {
+    pub id: SpanId,
+    /// `None` for threads.
+    pub parent_id: Option<SpanId>,
+    pub name: String,
+    pub kind: SpanKind,
+    pub metadata: serde_json::Value,
+    pub start: Duration,
+    pub end: Option<Duration>,
+    pub outcome: Option<AsyncOutcome>,
+    /// Finished on-CPU segments, in order.  Sync spans are on-CPU for their
+    /// whole lifetime; threads and idle spans never are.
+    pub on_cpu: Vec<Segment>,
+    /// When the current on-CPU segment started, if there is one.
+    pub on_cpu_since: Option<Duration>,
+    /// Wakeups sent by this span.
+    pub wakes: Vec<Wakeup>,
+    /// Wakeups received by this span.
+    pub woken_by: Vec<Wakeup>,
+    pub marks: Vec<Mark>,
+}
+
+impl Span {
+    fn new(id: SpanId, parent_id: Option<SpanId>, name: String, kind: SpanKind,
+           metadata: serde_json::Value, start: Duration) -> Self {
+        Span {
+            id,
+            parent_id,
+            name,
+            kind,
+            metadata,
+            start,
+            end: None,
+            outcome: None,
+            on_cpu: vec![],
+            on_cpu_since: if kind == SpanKind::Sync { Some(start) } else { None },
+            wakes: vec![],
+            woken_by: vec![],
+            marks: vec![],
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.end.is_some()
+    }
+
+    /// The span's end, or `now` if it's still open.
+    pub fn end_or(&self, now: Duration) -> Duration {
+        self.end.unwrap_or(now)
+    }
+
+    /// Number of times the span went on-CPU.
+    pub fn polls(&self) -> usize {
+        self.on_cpu.len() + if self.on_cpu_since.is_some() { 1 } else { 0 }
+    }
+
+    /// All on-CPU segments, with the current one (if any) cut off at `now`.
+    pub fn segments(&self, now: Duration) -> Vec<Segment> {
+        let mut segments = self.on_cpu.clone();
+        if let Some(start) = self.on_cpu_since {
+            segments.push(Segment { start, end: now });
+        }
+        segments
+    }
+
+    pub fn cpu_time(&self, now: Duration) -> Duration {
+        self.segments(now).iter().fold(Duration::new(0, 0), |total, s| total + (s.end - s.start))
+    }
+
+    // Events for a span from before it started, or before it went on-CPU,
+    // would make for segments that end before they start.
+    fn check(&self, ts: Duration) -> Result<(), Error> {
+        if ts < self.on_cpu_since.unwrap_or(self.start) {
+            return Err(Error::Backwards(self.id, ts));
+        }
+        Ok(())
+    }
+
+    fn off_cpu(&mut self, ts: Duration) {
+        if let Some(start) = self.on_cpu_since.take() {
+            self.on_cpu.push(Segment { start, end: ts });
+        }
+    }
+}
+
+/// An event the reconstructor couldn't make sense of.  The event is dropped;
+/// use `validate` in the backend for a full account of what's wrong.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    UnknownSpan(SpanId),
+    DoubleStart(SpanId),
+    /// The end event doesn't match how the span was started.
+    WrongKind(SpanId),
+    /// An event from before the span started, or went on-CPU, at this time.
+    Backwards(SpanId, Duration),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnknownSpan(id) => write!(f, "unknown span {}", id.0),
+            Error::DoubleStart(id) => write!(f, "span {} started twice", id.0),
+            Error::WrongKind(id) => write!(f, "span {} ended with the wrong kind of event", id.0),
+            Error::Backwards(id, ts) => write!(f, "event at {:?} for span {} is before it started or went on-CPU", ts, id.0),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Reconstructor {
+    active: HashMap<SpanId, Span>,
+    end_time: Duration,
+}
+
+impl Reconstructor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The latest timestamp seen so far.
+    pub fn end_time(&self) -> Duration {
+        self.end_time
+    }
+
+    pub fn get(&self, id: SpanId) -> Option<&Span> {
+        self.active.get(&id)
+    }
+
+    /// Spans that have started but not ended, in no particular order.
+    pub fn active<'a>(&'a self) -> hash_map::Values<'a, SpanId, Span> {
+        self.active.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.active.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.active.is_empty()
+    }
+
+    fn start(&mut self, span: Span) -> Result<Option<Span>, Error> {
+        if self.active.contains_key(&span.id) {
+            return Err(Error::DoubleStart(span.id));
+        }
+        self.active.insert(span.id, span);
+        Ok(None)
+    }
+
+    fn span_mut(&mut self, id: SpanId) -> Result<&mut Span, Error> {
+        self.active.get_mut(&id).ok_or(Error::UnknownSpan(id))
+    }
+
+    fn end(&mut self, id: SpanId, kind: SpanKind, ts: Duration) -> Result<Option<Span>, Error> {
+        match self.active.get(&id) {
+            Some(span) if span.kind != kind => return Err(Error::WrongKind(id)),
+            Some(span) => span.check(ts)?,
+            None => return Err(Error::UnknownSpan(id)),
+        }
+        let mut span = self.active.remove(&id).unwrap();
+        span.off_cpu(ts);
+        span.end = Some(ts);
+        Ok(Some(span))
+    }
+
+    /// Apply one event, returning the span it finished, if any.
+    pub fn add_event(&mut self, event: TraceEvent) -> Result<Option<Span>, Error> {
+        if event.ts() > self.end_time {
+            self.end_time = event.ts();
+        }
+        match event {
+            TraceEvent::ThreadStart { name, id, ts } => {
+                self.start(Span::new(id, None, name, SpanKind::Thread, serde_json::Value::Null, ts))
+            },
+            TraceEvent::AsyncStart { name, id, parent_id, ts, metadata } => {
+                self.start(Span::new(id, Some(parent_id), name, SpanKind::Async, metadata, ts))
+            },
+            TraceEvent::SyncStart { name, id, parent_id, ts, metadata } => {
+                self.start(Span::new(id, Some(parent_id), name, SpanKind::Sync, metadata, ts))
+            },
+            TraceEvent::IdleStart { id, parent_id, ts } => {
+                let span = Span::new(id, Some(parent_id), IDLE_SPAN_NAME.to_string(), SpanKind::Idle,
+                                     serde_json::Value::Null, ts);
+                self.start(span)
+            },
+            TraceEvent::AsyncOnCPU { id, ts } => {
+                let span = self.span_mut(id)?;
+                span.check(ts)?;
+                span.off_cpu(ts);
+                span.on_cpu_since = Some(ts);
+                Ok(None)
+            },
+            TraceEvent::AsyncOffCPU { id, ts } => {
+                let span = self.span_mut(id)?;
+                span.check(ts)?;
+                span.off_cpu(ts);
+                Ok(None)
+            },
+            TraceEvent::AsyncEnd { id, ts, outcome } => {
+                let result = self.end(id, SpanKind::Async, ts);
+                result.map(|span| span.map(|mut span| {
+                    span.outcome = Some(outcome);
+                    span
+                }))
+            },
+            TraceEvent::SyncEnd { id, ts } => self.end(id, SpanKind::Sync, ts),
+            TraceEvent::ThreadEnd { id, ts } => self.end(id, SpanKind::Thread, ts),
+            TraceEvent::IdleEnd { id, ts } => self.end(id, SpanKind::Idle, ts),
+            TraceEvent::Wakeup { waking_span, parked_span, ts } => {
+                let wakeup = Wakeup { waking_span, parked_span, ts };
+                if let Some(span) = self.active.get_mut(&waking_span) {
+                    span.wakes.push(wakeup);
+                }
+                self.span_mut(parked_span)?.woken_by.push(wakeup);
+                Ok(None)
+            },
+            TraceEvent::Mark { id, name, ts, metadata } => {
+                self.span_mut(id)?.marks.push(Mark { name, ts, metadata });
+                Ok(None)
+            },
+        }
+    }
+
+    /// Give up on the spans that are still open, in order of starting.
+    // `into_values` is too new for f2's toolchain.
+    #[allow(clippy::iter_kv_map)]
+    pub fn into_active(self) -> Vec<Span> {
+        let mut spans: Vec<_> = self.active.into_iter().map(|(_, span)| span).collect();
+        spans.sort_by_key(|s| s.start);
+        spans
+    }
+}
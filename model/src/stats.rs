@@ -0,0 +1,116 @@
+//! Aggregate latency and CPU numbers per span name.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+use serde_json;
+use event::AsyncOutcome;
+use spans::{Span, SpanKind};
+use trace::Trace;
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Percentiles {
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl Percentiles {
+    /// Nearest-rank percentiles of `values`, which must be sorted.
+    pub(crate) fn of(values: &[Duration]) -> Self {
+        let rank = |p: usize| {
+            if values.is_empty() {
+                return Duration::default();
+            }
+            let i = (p as f64 / 100.0 * values.len() as f64).ceil() as usize;
+            values[i.max(1) - 1]
+        };
+        Percentiles {
+            p50: rank(50),
+            p90: rank(90),
+            p99: rank(99),
+            max: values.last().cloned().unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NameStats {
+    pub name: String,
+    /// The value of the grouping metadata key, if grouping and the span had
+    /// it.
+    pub group: Option<String>,
+    /// Finished spans.  Open spans are counted in `open` but otherwise left
+    /// out, since their latency isn't known yet.
+    pub count: usize,
+    pub open: usize,
+    pub errors: usize,
+    pub cancelled: usize,
+    /// Wall-clock time from start to end.
+    pub latency: Percentiles,
+    pub cpu_time: Duration,
+    /// Time spent started but off-CPU.  Only async spans can wait; sync spans
+    /// are on-CPU throughout, and threads and idle spans never are.
+    pub off_cpu_time: Duration,
+    pub polls: usize,
+}
+
+impl NameStats {
+    pub fn polls_per_span(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        self.polls as f64 / self.count as f64
+    }
+}
+
+fn group_of(span: &Span, key: &str) -> Option<String> {
+    let value = span.metadata.get(key)?;
+    match *value {
+        serde_json::Value::String(ref s) => Some(s.clone()),
+        serde_json::Value::Null => None,
+        _ => Some(value.to_string()),
+    }
+}
+
+/// Statistics for every span name in `trace`, sorted by name.  With
+/// `group_by`, each name is further split by the value of that metadata key.
+pub fn stats(trace: &Trace, group_by: Option<&str>) -> Vec<NameStats> {
+    let mut groups: BTreeMap<(String, Option<String>), (NameStats, Vec<Duration>)> = BTreeMap::new();
+    for span in trace.spans() {
+        let group = group_by.and_then(|key| group_of(span, key));
+        let key = (span.name.clone(), group.clone());
+        let &mut (ref mut stats, ref mut latencies) = groups.entry(key).or_insert_with(|| {
+            let stats = NameStats { name: span.name.clone(), group, ..NameStats::default() };
+            (stats, vec![])
+        });
+        let end = match span.end {
+            Some(end) => end,
+            None => {
+                stats.open += 1;
+                continue;
+            },
+        };
+        let latency = end - span.start;
+        let cpu_time = span.cpu_time(end);
+        stats.count += 1;
+        match span.outcome {
+            Some(AsyncOutcome::Error(_)) => stats.errors += 1,
+            Some(AsyncOutcome::Cancelled) => stats.cancelled += 1,
+            _ => (),
+        }
+        latencies.push(latency);
+        stats.cpu_time += cpu_time;
+        if span.kind == SpanKind::Async && latency > cpu_time {
+            stats.off_cpu_time += latency - cpu_time;
+        }
+        stats.polls += span.polls();
+    }
+    groups.into_iter()
+        .map(|(_, (mut stats, mut latencies))| {
+            latencies.sort();
+            stats.latency = Percentiles::of(&latencies);
+            stats
+        })
+        .collect()
+}
@@ -0,0 +1,589 @@
+use std::time::Duration;
+use serde_json;
+use binary::{self, DecodeError};
+use chrome::chrome;
+use critical_path::{critical_path, PathSegment, SegmentKind};
+use diff::diff;
+use folded::{folded, Weight};
+use json::{IdFormat, JsonFormat, TimestampFormat};
+use lod;
+use slice::{slice, Selection};
+use spans::Error;
+use stats::{stats, Percentiles};
+use {AsyncOutcome, IntervalIndex, Reconstructor, Segment, SpanId, SpanKind, Trace, TraceEvent};
+
+fn ms(n: u64) -> Duration {
+    Duration::from_millis(n)
+}
+
+#[test]
+fn test_reconstruct() {
+    let events = vec![
+        TraceEvent::ThreadStart { name: "main".to_string(), id: SpanId(1), ts: ms(0) },
+        TraceEvent::AsyncStart {
+            name: "task".to_string(), id: SpanId(2), parent_id: SpanId(1), ts: ms(1),
+            metadata: json_null(),
+        },
+        TraceEvent::AsyncOnCPU { id: SpanId(2), ts: ms(2) },
+        TraceEvent::AsyncOffCPU { id: SpanId(2), ts: ms(3) },
+        TraceEvent::SyncStart {
+            name: "work".to_string(), id: SpanId(3), parent_id: SpanId(1), ts: ms(4),
+            metadata: json_null(),
+        },
+        TraceEvent::Wakeup { waking_span: SpanId(3), parked_span: SpanId(2), ts: ms(5) },
+        TraceEvent::SyncEnd { id: SpanId(3), ts: ms(6) },
+        TraceEvent::AsyncOnCPU { id: SpanId(2), ts: ms(7) },
+        TraceEvent::AsyncEnd { id: SpanId(2), ts: ms(9), outcome: AsyncOutcome::Success },
+    ];
+    let mut reconstructor = Reconstructor::new();
+    let mut finished = vec![];
+    for event in events {
+        if let Some(span) = reconstructor.add_event(event).unwrap() {
+            finished.push(span);
+        }
+    }
+    assert_eq!(reconstructor.end_time(), ms(9));
+    assert_eq!(finished.len(), 2);
+
+    let work = &finished[0];
+    assert_eq!(work.kind, SpanKind::Sync);
+    assert_eq!(work.on_cpu, vec![Segment { start: ms(4), end: ms(6) }]);
+    assert_eq!(work.wakes.len(), 1);
+
+    let task = &finished[1];
+    assert_eq!(task.parent_id, Some(SpanId(1)));
+    assert_eq!(task.outcome, Some(AsyncOutcome::Success));
+    // Ending a span takes it off-CPU.
+    assert_eq!(task.on_cpu, vec![
+        Segment { start: ms(2), end: ms(3) },
+        Segment { start: ms(7), end: ms(9) },
+    ]);
+    assert_eq!(task.polls(), 2);
+    assert_eq!(task.cpu_time(ms(9)), ms(3));
+    assert_eq!(task.woken_by[0].waking_span, SpanId(3));
+
+    let open = reconstructor.into_active();
+    assert_eq!(open.len(), 1);
+    assert_eq!(open[0].name, "main");
+    assert!(!open[0].is_finished());
+}
+
+#[test]
+fn test_reconstruct_errors() {
+    let mut reconstructor = Reconstructor::new();
+    let start = TraceEvent::ThreadStart { name: "main".to_string(), id: SpanId(1), ts: ms(0) };
+    reconstructor.add_event(start.clone()).unwrap();
+    assert_eq!(reconstructor.add_event(start), Err(Error::DoubleStart(SpanId(1))));
+    assert_eq!(
+        reconstructor.add_event(TraceEvent::SyncEnd { id: SpanId(1), ts: ms(1) }),
+        Err(Error::WrongKind(SpanId(1))),
+    );
+    assert_eq!(
+        reconstructor.add_event(TraceEvent::AsyncOnCPU { id: SpanId(2), ts: ms(1) }),
+        Err(Error::UnknownSpan(SpanId(2))),
+    );
+    // The bad end left the span open.
+    assert!(reconstructor.get(SpanId(1)).is_some());
+
+    // As do events from before the span started or went on-CPU.
+    reconstructor.add_event(async_start("early", 3, 1, 10)).unwrap();
+    let backwards = |ts| Err(Error::Backwards(SpanId(3), ms(ts)));
+    assert_eq!(reconstructor.add_event(TraceEvent::AsyncOnCPU { id: SpanId(3), ts: ms(5) }), backwards(5));
+    reconstructor.add_event(TraceEvent::AsyncOnCPU { id: SpanId(3), ts: ms(12) }).unwrap();
+    assert_eq!(reconstructor.add_event(TraceEvent::AsyncOffCPU { id: SpanId(3), ts: ms(11) }), backwards(11));
+    let end = |ts| TraceEvent::AsyncEnd { id: SpanId(3), ts: ms(ts), outcome: AsyncOutcome::Success };
+    assert_eq!(reconstructor.add_event(end(7)), backwards(7));
+    let span = reconstructor.add_event(end(13)).unwrap().unwrap();
+    assert_eq!(span.on_cpu, vec![Segment { start: ms(12), end: ms(13) }]);
+}
+
+#[test]
+fn test_interval_index() {
+    let mut index = IntervalIndex::new();
+    for i in 0..1000 {
+        index.insert(ms(i), ms(i + 10), i);
+    }
+    // One long span that every query should find.
+    index.insert(ms(0), ms(2000), 1000);
+    assert_eq!(index.len(), 1001);
+
+    let found: Vec<_> = index.overlapping(ms(500), ms(502)).cloned().collect();
+    let mut expected: Vec<_> = (491..502).collect();
+    expected.push(1000);
+    assert_eq!(found, expected);
+
+    assert_eq!(index.overlapping(ms(1500), ms(1600)).cloned().collect::<Vec<_>>(), vec![1000]);
+    assert_eq!(index.overlapping(ms(3000), ms(4000)).count(), 0);
+    assert_eq!(index.iter().count(), 1001);
+}
+
+#[test]
+fn test_event_accessors() {
+    let line = r#"{"Wakeup":{"waking_span":1,"parked_span":2,"ts":{"secs":1,"nanos":5}}}"#;
+    let event: TraceEvent = serde_json::from_str(line).unwrap();
+    assert_eq!(event.ts(), Duration::new(1, 5));
+    assert_eq!(event.id(), None);
+    assert_eq!(event.parent_id(), None);
+}
+
+#[test]
+fn test_critical_path() {
+    // A request that does some work, waits for a query on another thread to
+    // wake it up, and then finishes off.
+    let trace = Trace::from_events(vec![
+        TraceEvent::ThreadStart { name: "main".to_string(), id: SpanId(1), ts: ms(0) },
+        TraceEvent::ThreadStart { name: "db".to_string(), id: SpanId(2), ts: ms(0) },
+        async_start("request", 10, 1, 0),
+        TraceEvent::AsyncOnCPU { id: SpanId(10), ts: ms(0) },
+        sync_start("parse", 11, 1, 2),
+        TraceEvent::SyncEnd { id: SpanId(11), ts: ms(8) },
+        TraceEvent::AsyncOffCPU { id: SpanId(10), ts: ms(10) },
+        sync_start("query", 20, 2, 20),
+        TraceEvent::Wakeup { waking_span: SpanId(20), parked_span: SpanId(10), ts: ms(80) },
+        TraceEvent::SyncEnd { id: SpanId(20), ts: ms(80) },
+        TraceEvent::AsyncOnCPU { id: SpanId(10), ts: ms(85) },
+        TraceEvent::AsyncEnd { id: SpanId(10), ts: ms(100), outcome: AsyncOutcome::Success },
+    ]);
+    // `parse` ran under the thread rather than the request, so it isn't on
+    // the path even though it overlaps it.
+    assert_eq!(trace.children(SpanId(1)), &[SpanId(10), SpanId(11)]);
+
+    let path = critical_path(&trace, SpanId(10)).unwrap();
+    let segment = |span, kind, start, end| PathSegment { span: SpanId(span), kind, start: ms(start), end: ms(end) };
+    assert_eq!(path.segments, vec![
+        segment(10, SegmentKind::Running, 0, 10),
+        segment(10, SegmentKind::Waiting, 10, 20),
+        segment(20, SegmentKind::Running, 20, 80),
+        segment(10, SegmentKind::Runnable, 80, 85),
+        segment(10, SegmentKind::Running, 85, 100),
+    ]);
+    assert_eq!(path.duration(), ms(100));
+    assert_eq!(path.by_span(), vec![(SpanId(20), ms(60)), (SpanId(10), ms(40))]);
+    assert!(critical_path(&trace, SpanId(99)).is_none());
+}
+
+#[test]
+fn test_critical_path_children() {
+    let trace = Trace::from_events(vec![
+        TraceEvent::ThreadStart { name: "main".to_string(), id: SpanId(1), ts: ms(0) },
+        sync_start("request", 10, 1, 0),
+        sync_start("fast", 11, 10, 2),
+        TraceEvent::SyncEnd { id: SpanId(11), ts: ms(4) },
+        sync_start("slow", 12, 10, 5),
+        sync_start("inner", 13, 12, 6),
+        TraceEvent::SyncEnd { id: SpanId(13), ts: ms(9) },
+        TraceEvent::SyncEnd { id: SpanId(12), ts: ms(10) },
+        TraceEvent::SyncEnd { id: SpanId(10), ts: ms(12) },
+    ]);
+    let path = critical_path(&trace, SpanId(10)).unwrap();
+    let spans: Vec<_> = path.segments.iter().map(|s| (s.span.0, s.start, s.end)).collect();
+    assert_eq!(spans, vec![
+        (10, ms(0), ms(2)),
+        (11, ms(2), ms(4)),
+        (10, ms(4), ms(5)),
+        (12, ms(5), ms(6)),
+        (13, ms(6), ms(9)),
+        (12, ms(9), ms(10)),
+        (10, ms(10), ms(12)),
+    ]);
+}
+
+#[test]
+fn test_critical_path_deep() {
+    // Nesting too deep to recurse all the way into.
+    let mut events = vec![TraceEvent::ThreadStart { name: "main".to_string(), id: SpanId(1), ts: ms(0) }];
+    let depth = 100_000;
+    for i in 0..depth {
+        events.push(sync_start("nested", 10 + i, 9 + i, 1));
+    }
+    for i in (0..depth).rev() {
+        events.push(TraceEvent::SyncEnd { id: SpanId(10 + i), ts: ms(2) });
+    }
+    let trace = Trace::from_events(events);
+    let path = critical_path(&trace, SpanId(10)).unwrap();
+    assert_eq!(path.duration(), ms(1));
+    assert!(path.segments.iter().all(|s| s.span.0 < 10 + depth));
+
+    // Spans that are each other's parents.
+    let trace = Trace::from_events(vec![
+        TraceEvent::ThreadStart { name: "main".to_string(), id: SpanId(1), ts: ms(0) },
+        sync_start("a", 10, 11, 1),
+        sync_start("b", 11, 10, 1),
+        TraceEvent::SyncEnd { id: SpanId(11), ts: ms(3) },
+        TraceEvent::SyncEnd { id: SpanId(10), ts: ms(3) },
+    ]);
+    assert_eq!(critical_path(&trace, SpanId(10)).unwrap().duration(), ms(2));
+}
+
+#[test]
+fn test_stats() {
+    let mut events = vec![
+        TraceEvent::ThreadStart { name: "main".to_string(), id: SpanId(1), ts: ms(0) },
+    ];
+    // Ten requests taking 10ms, 20ms, ..., 100ms, with 1ms on-CPU per poll.
+    for i in 1..11 {
+        let id = 100 + i;
+        events.push(TraceEvent::AsyncStart {
+            name: "request".to_string(), id: SpanId(id), parent_id: SpanId(1), ts: ms(0),
+            metadata: json!({ "method": if i % 2 == 0 { "GET" } else { "PUT" } }),
+        });
+        events.push(TraceEvent::AsyncOnCPU { id: SpanId(id), ts: ms(0) });
+        events.push(TraceEvent::AsyncOffCPU { id: SpanId(id), ts: ms(1) });
+        events.push(TraceEvent::AsyncOnCPU { id: SpanId(id), ts: ms(i * 10 - 1) });
+        let outcome = match i {
+            3 => AsyncOutcome::Error("oops".to_string()),
+            4 => AsyncOutcome::Cancelled,
+            _ => AsyncOutcome::Success,
+        };
+        events.push(TraceEvent::AsyncEnd { id: SpanId(id), ts: ms(i * 10), outcome });
+    }
+    events.push(sync_start("open", 200, 1, 200));
+    let trace = Trace::from_events(events);
+
+    let all = stats(&trace, None);
+    let names: Vec<_> = all.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, vec!["main", "open", "request"]);
+    assert_eq!((all[1].count, all[1].open), (0, 1));
+
+    let request = &all[2];
+    assert_eq!(request.count, 10);
+    assert_eq!((request.errors, request.cancelled), (1, 1));
+    assert_eq!(request.latency, Percentiles { p50: ms(50), p90: ms(90), p99: ms(100), max: ms(100) });
+    assert_eq!(request.cpu_time, ms(20));
+    assert_eq!(request.off_cpu_time, ms(550 - 20));
+    assert_eq!(request.polls_per_span(), 2.0);
+
+    let by_method = stats(&trace, Some("method"));
+    let groups: Vec<_> = by_method.iter()
+        .filter(|s| s.name == "request")
+        .map(|s| (s.group.clone().unwrap(), s.count, s.latency.max))
+        .collect();
+    assert_eq!(groups, vec![("GET".to_string(), 5, ms(100)), ("PUT".to_string(), 5, ms(90))]);
+}
+
+#[test]
+fn test_folded() {
+    let trace = Trace::from_events(vec![
+        TraceEvent::ThreadStart { name: "main".to_string(), id: SpanId(1), ts: ms(0) },
+        async_start("request", 10, 1, 10),
+        TraceEvent::AsyncOnCPU { id: SpanId(10), ts: ms(10) },
+        sync_start("parse;json", 11, 10, 12),
+        TraceEvent::SyncEnd { id: SpanId(11), ts: ms(15) },
+        TraceEvent::AsyncOffCPU { id: SpanId(10), ts: ms(20) },
+        TraceEvent::AsyncOnCPU { id: SpanId(10), ts: ms(40) },
+        TraceEvent::AsyncEnd { id: SpanId(10), ts: ms(50), outcome: AsyncOutcome::Success },
+        TraceEvent::ThreadEnd { id: SpanId(1), ts: ms(100) },
+    ]);
+    assert_eq!(folded(&trace, Weight::Cpu), vec![
+        ("main;request".to_string(), 17_000),
+        ("main;request;parse:json".to_string(), 3_000),
+    ]);
+    assert_eq!(folded(&trace, Weight::Wall), vec![
+        ("main".to_string(), 60_000),
+        ("main;request".to_string(), 37_000),
+        ("main;request;parse:json".to_string(), 3_000),
+    ]);
+}
+
+#[test]
+fn test_diff() {
+    let run = |spans: &[(&str, u64, u64)]| {
+        let mut events = vec![
+            TraceEvent::ThreadStart { name: "main".to_string(), id: SpanId(1), ts: ms(0) },
+        ];
+        for (i, &(name, start, end)) in spans.iter().enumerate() {
+            let id = 10 + i as u64;
+            events.push(sync_start(name, id, 1, start));
+            events.push(TraceEvent::SyncEnd { id: SpanId(id), ts: ms(end) });
+        }
+        Trace::from_events(events)
+    };
+    let baseline = run(&[("a", 0, 10), ("a", 10, 20), ("b", 20, 25)]);
+    let candidate = run(&[("a", 0, 30), ("c", 30, 31)]);
+
+    let diffs = diff(&baseline, &candidate);
+    let paths: Vec<_> = diffs.iter().map(|d| d.path.as_str()).collect();
+    // The thread is still open in both, so it has no summary on either side.
+    assert_eq!(paths, vec!["main;a", "main;b", "main;c"]);
+
+    let a = (diffs[0].baseline.unwrap(), diffs[0].candidate.unwrap());
+    assert_eq!((a.0.count, a.1.count), (2, 1));
+    assert_eq!((a.0.latency.max, a.1.latency.max), (ms(10), ms(30)));
+    assert_eq!((a.0.cpu_time, a.1.cpu_time), (ms(20), ms(30)));
+    assert!(diffs[1].is_gone());
+    assert!(diffs[2].is_new());
+}
+
+#[test]
+fn test_binary() {
+    let events = vec![
+        TraceEvent::ThreadStart { name: "main".to_string(), id: SpanId(1), ts: ms(0) },
+        TraceEvent::AsyncStart {
+            name: "task".to_string(), id: SpanId(2), parent_id: SpanId(1), ts: Duration::new(1, 5),
+            metadata: json!({ "key": [1, "two"] }),
+        },
+        TraceEvent::AsyncOnCPU { id: SpanId(2), ts: ms(2) },
+        TraceEvent::AsyncOffCPU { id: SpanId(2), ts: ms(3) },
+        TraceEvent::Wakeup { waking_span: SpanId(1), parked_span: SpanId(2), ts: ms(4) },
+        TraceEvent::Mark { id: SpanId(2), name: "mark".to_string(), ts: ms(4), metadata: json_null() },
+        TraceEvent::AsyncEnd { id: SpanId(2), ts: ms(5), outcome: AsyncOutcome::Error("boom".to_string()) },
+        TraceEvent::IdleStart { id: SpanId(3), parent_id: SpanId(1), ts: ms(6) },
+        TraceEvent::IdleEnd { id: SpanId(3), ts: ms(7) },
+        sync_start("sync", 4, 1, 8),
+        TraceEvent::SyncEnd { id: SpanId(4), ts: ms(9) },
+        TraceEvent::ThreadEnd { id: SpanId(1), ts: ms(10) },
+    ];
+    let mut buf = vec![];
+    for event in &events {
+        binary::encode(event, &mut buf);
+    }
+
+    let mut decoded = vec![];
+    let mut rest = &buf[..];
+    while let Some((event, len)) = binary::decode(rest) {
+        decoded.push(event.unwrap());
+        rest = &rest[len..];
+    }
+    assert!(rest.is_empty());
+    assert_eq!(decoded, events);
+
+    // Partial records wait for more input, and bad ones are skipped whole.
+    assert!(binary::decode(&buf[..5]).is_none());
+    let mut bad = vec![];
+    binary::encode(&events[0], &mut bad);
+    bad[4] = 99;
+    assert_eq!(binary::decode(&bad), Some((Err(DecodeError::UnknownTag(99)), bad.len())));
+    let mut bad = vec![];
+    binary::encode(&TraceEvent::AsyncOnCPU { id: SpanId(1), ts: Duration::new(!0, 0) }, &mut bad);
+    let nanos = bad.len() - 4;
+    bad[nanos..].copy_from_slice(&[0xff; 4]);
+    assert_eq!(binary::decode(&bad), Some((Err(DecodeError::InvalidTimestamp(!0)), bad.len())));
+}
+
+#[test]
+fn test_chrome() {
+    let trace = Trace::from_events(vec![
+        TraceEvent::ThreadStart { name: "main".to_string(), id: SpanId(1), ts: ms(0) },
+        async_start("task", 2, 1, 1),
+        TraceEvent::AsyncOnCPU { id: SpanId(2), ts: ms(2) },
+        TraceEvent::AsyncOffCPU { id: SpanId(2), ts: ms(3) },
+        sync_start("sync", 3, 1, 4),
+        TraceEvent::SyncEnd { id: SpanId(3), ts: ms(6) },
+    ]);
+    let exported = chrome(&trace);
+    let events = exported["traceEvents"].as_array().unwrap();
+    let phases: Vec<&str> = events.iter().map(|e| e["ph"].as_str().unwrap()).collect();
+    assert_eq!(phases, vec!["M", "b", "e", "X", "X"]);
+    assert_eq!(events[0]["args"]["name"], "main");
+    // The open async span is cut off at the end of the trace.
+    assert_eq!(events[2]["ts"], 6000.0);
+    assert_eq!((&events[3]["ts"], &events[3]["dur"]), (&json!(2000.0), &json!(1000.0)));
+    assert_eq!((&events[4]["name"], &events[4]["tid"]), (&json!("sync"), &json!(1)));
+}
+
+#[test]
+fn test_slice() {
+    let trace = Trace::from_events(vec![
+        TraceEvent::ThreadStart { name: "main".to_string(), id: SpanId(1), ts: ms(0) },
+        async_start("a", 2, 1, 1),
+        TraceEvent::AsyncOnCPU { id: SpanId(2), ts: ms(1) },
+        async_start("b", 3, 2, 2),
+        TraceEvent::AsyncOffCPU { id: SpanId(2), ts: ms(3) },
+        TraceEvent::AsyncOnCPU { id: SpanId(3), ts: ms(4) },
+        TraceEvent::Mark { id: SpanId(3), name: "m".to_string(), ts: ms(5), metadata: json_null() },
+        TraceEvent::AsyncOffCPU { id: SpanId(3), ts: ms(6) },
+        TraceEvent::Wakeup { waking_span: SpanId(3), parked_span: SpanId(2), ts: ms(6) },
+        TraceEvent::AsyncEnd { id: SpanId(3), ts: ms(7), outcome: AsyncOutcome::Success },
+        sync_start("c", 4, 1, 8),
+        TraceEvent::SyncEnd { id: SpanId(4), ts: ms(9) },
+        TraceEvent::AsyncOnCPU { id: SpanId(2), ts: ms(9) },
+        TraceEvent::AsyncOffCPU { id: SpanId(2), ts: ms(10) },
+        TraceEvent::AsyncEnd { id: SpanId(2), ts: ms(10), outcome: AsyncOutcome::Success },
+    ]);
+
+    // The window starts while b is polling and ends before a finishes, so
+    // both are reopened at the start and a is closed at the end.
+    let window = slice(&trace, Selection::Window { start: ms(5), end: ms(7) });
+    assert_eq!(window, vec![
+        TraceEvent::ThreadStart { name: "main".to_string(), id: SpanId(1), ts: ms(0) },
+        async_start("a", 2, 1, 1),
+        async_start("b", 3, 2, 2),
+        TraceEvent::AsyncOnCPU { id: SpanId(3), ts: ms(5) },
+        TraceEvent::Mark { id: SpanId(3), name: "m".to_string(), ts: ms(5), metadata: json_null() },
+        TraceEvent::AsyncOffCPU { id: SpanId(3), ts: ms(6) },
+        TraceEvent::Wakeup { waking_span: SpanId(3), parked_span: SpanId(2), ts: ms(6) },
+        TraceEvent::AsyncEnd { id: SpanId(3), ts: ms(7), outcome: AsyncOutcome::Success },
+        TraceEvent::AsyncEnd { id: SpanId(2), ts: ms(7), outcome: AsyncOutcome::Cancelled },
+        TraceEvent::ThreadEnd { id: SpanId(1), ts: ms(7) },
+    ]);
+
+    // b's subtree keeps only the starts and ends of its ancestors, and
+    // leaves out c entirely.
+    let subtree = slice(&trace, Selection::Subtree(SpanId(3)));
+    assert_eq!(subtree, vec![
+        TraceEvent::ThreadStart { name: "main".to_string(), id: SpanId(1), ts: ms(0) },
+        async_start("a", 2, 1, 1),
+        async_start("b", 3, 2, 2),
+        TraceEvent::AsyncOnCPU { id: SpanId(3), ts: ms(4) },
+        TraceEvent::Mark { id: SpanId(3), name: "m".to_string(), ts: ms(5), metadata: json_null() },
+        TraceEvent::AsyncOffCPU { id: SpanId(3), ts: ms(6) },
+        TraceEvent::AsyncEnd { id: SpanId(3), ts: ms(7), outcome: AsyncOutcome::Success },
+        TraceEvent::AsyncEnd { id: SpanId(2), ts: ms(10), outcome: AsyncOutcome::Success },
+        TraceEvent::ThreadEnd { id: SpanId(1), ts: ms(10) },
+    ]);
+    assert!(slice(&trace, Selection::Subtree(SpanId(99))).is_empty());
+}
+
+#[test]
+fn test_json_ids() {
+    let big = 1 << 60 | 1;
+    let event = TraceEvent::Wakeup { waking_span: SpanId(big), parked_span: SpanId(2), ts: ms(1) };
+    let as_number = JsonFormat::version(1).unwrap().to_value(&event);
+    let as_string = JsonFormat::version(2).unwrap().to_value(&event);
+    let as_hex = JsonFormat { ids: IdFormat::Hex, timestamps: TimestampFormat::Struct }.to_value(&event);
+    assert_eq!(as_number["Wakeup"]["waking_span"], json!(big));
+    assert_eq!(as_string["Wakeup"]["waking_span"], json!(big.to_string()));
+    assert_eq!(as_hex["Wakeup"]["waking_span"], json!("0x1000000000000001"));
+    assert_eq!(as_hex["Wakeup"]["parked_span"], json!("0x2"));
+    for value in &[as_number, as_string, as_hex] {
+        assert_eq!(serde_json::from_value::<TraceEvent>(value.clone()).unwrap(), event);
+    }
+    assert!(JsonFormat::version(JsonFormat::LATEST_VERSION + 1).is_none());
+
+    for bad in &["-1", "\"12x\"", "\"0xg\"", "1.5"] {
+        assert!(serde_json::from_str::<SpanId>(bad).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn test_json_timestamps() {
+    let event = TraceEvent::AsyncOnCPU { id: SpanId(1), ts: Duration::new(2, 500) };
+    let compact = JsonFormat::version(3).unwrap().to_value(&event);
+    assert_eq!(compact, json!({ "AsyncOnCPU": { "id": "1", "ts": 2_000_000_500u64 } }));
+
+    // Struct, integer nanoseconds and float seconds all read back, as do
+    // hand-written events without metadata.
+    for ts in &[r#"{"secs": 2, "nanos": 500}"#, "2000000500", "2.0000005"] {
+        let line = format!(r#"{{"AsyncOnCPU": {{"id": 1, "ts": {}}}}}"#, ts);
+        assert_eq!(serde_json::from_str::<TraceEvent>(&line).unwrap(), event, "{}", ts);
+    }
+    let start: TraceEvent = serde_json::from_str(r#"{"ThreadStart": {"name": "t", "id": 0, "ts": 0.25}}"#).unwrap();
+    assert_eq!(start.ts(), ms(250));
+    let start: TraceEvent = serde_json::from_str(r#"{"AsyncStart": {"name": "a", "id": 1, "parent_id": 0, "ts": 1}}"#).unwrap();
+    assert_eq!(start, TraceEvent::AsyncStart {
+        name: "a".to_string(), id: SpanId(1), parent_id: SpanId(0), ts: Duration::new(0, 1), metadata: json_null(),
+    });
+
+    let overflow = r#"{"secs": 18446744073709551615, "nanos": 1500000000}"#;
+    for bad in &["-1", "-0.5", r#"{"secs": 1}"#, r#"{"secs": 1, "nanos": 1000000000}"#, overflow, "\"1\""] {
+        let line = format!(r#"{{"AsyncOnCPU": {{"id": 1, "ts": {}}}}}"#, bad);
+        assert!(serde_json::from_str::<TraceEvent>(&line).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn test_lod() {
+    let events = vec![
+        TraceEvent::ThreadStart { name: "main".to_string(), id: SpanId(1), ts: ms(0) },
+        sync_start("outer", 2, 1, 0),
+        async_start("task", 3, 2, 2),
+        TraceEvent::AsyncOnCPU { id: SpanId(3), ts: ms(4) },
+        TraceEvent::AsyncOffCPU { id: SpanId(3), ts: ms(6) },
+        TraceEvent::SyncEnd { id: SpanId(2), ts: ms(10) },
+        TraceEvent::AsyncEnd { id: SpanId(3), ts: ms(11), outcome: AsyncOutcome::Success },
+        sync_start("late", 4, 1, 12),
+        TraceEvent::SyncEnd { id: SpanId(4), ts: ms(13) },
+        sync_start("open", 5, 1, 40),
+        // Tasks can outlive the spans that spawned them.
+        async_start("detached", 6, 4, 41),
+        TraceEvent::AsyncOnCPU { id: SpanId(6), ts: ms(41) },
+        TraceEvent::AsyncOffCPU { id: SpanId(6), ts: ms(42) },
+        async_start("orphan", 7, 99, 42),
+    ];
+    let mut builder = lod::Builder::new();
+    for event in &events {
+        builder.add_event(event);
+    }
+
+    // Buckets of 16.384ms.
+    let summary = builder.summarise(ms(0), ms(32), 2);
+    assert_eq!(summary.width, Duration::new(0, 16_384_000));
+    assert_eq!(summary.rows.len(), 1);
+    assert_eq!(summary.rows[0].thread, "main");
+    let bucket = &summary.rows[0].buckets[0];
+    assert_eq!(bucket.start, ms(0));
+    assert!((bucket.busy - 11.0 / 16.384).abs() < 1e-9);
+    assert_eq!(bucket.name, Some("outer".to_string()));
+    assert_eq!(bucket.count, 3);
+    assert_eq!(summary.rows[0].buckets.len(), 1);
+    let later = builder.summarise(ms(33), ms(49), 1);
+    assert_eq!(later.rows[0].buckets.iter().map(|b| b.count).sum::<u32>(), 3);
+
+    // Whatever the level, the busy time adds up, and the long span's time
+    // shows up in buckets it wasn't recorded in itself.
+    builder.add_event(&TraceEvent::Mark { id: SpanId(5), name: "m".to_string(), ts: ms(50), metadata: json_null() });
+    for &buckets in &[1, 7, 100, 1000, 10_000] {
+        let summary = builder.summarise(ms(0), ms(60), buckets);
+        let width = summary.width.as_secs() as f64 * 1e3 + summary.width.subsec_nanos() as f64 / 1e6;
+        let busy: f64 = summary.rows[0].buckets.iter().map(|b| b.busy * width).sum();
+        assert!((busy - 21.0).abs() < 1e-6, "{} buckets: {}ms busy", buckets, busy);
+    }
+    let summary = builder.summarise(ms(5), ms(5), 1);
+    assert_eq!(summary.rows[0].buckets[0].name, Some("task".to_string()));
+    let summary = builder.summarise(ms(44), ms(45), 1000);
+    assert_eq!(summary.rows[0].buckets.len(), 63);
+    assert!(summary.rows[0].buckets.iter().all(|b| b.busy == 1.0 && b.name == Some("open".to_string())));
+}
+
+#[test]
+fn test_lod_summarise_midway() {
+    let mut builder = lod::Builder::new();
+    for event in &[
+        TraceEvent::ThreadStart { name: "a".to_string(), id: SpanId(1), ts: ms(0) },
+        sync_start("work", 2, 1, 0),
+        TraceEvent::ThreadStart { name: "b".to_string(), id: SpanId(3), ts: ms(50) },
+    ] {
+        builder.add_event(event);
+    }
+    let busy = |builder: &lod::Builder| -> f64 {
+        let summary = builder.summarise(ms(0), ms(60), 1);
+        let width = summary.width.as_secs() as f64 * 1e3 + summary.width.subsec_nanos() as f64 / 1e6;
+        summary.rows[0].buckets.iter().map(|b| b.busy * width).sum()
+    };
+    // Still running, so counted up to the latest event on any thread.
+    assert!((busy(&builder) - 50.0).abs() < 1e-6);
+    // Thread a's events can lag behind b's, and asking for a summary in the
+    // meantime mustn't have counted past them.
+    builder.add_event(&TraceEvent::SyncEnd { id: SpanId(2), ts: ms(20) });
+    assert!((busy(&builder) - 20.0).abs() < 1e-6);
+
+    // Ended spans aren't saved, and the reloaded builder carries on the same.
+    let saved = serde_json::to_string(&builder).unwrap();
+    let mut reloaded: lod::Builder = serde_json::from_str(&saved).unwrap();
+    // Only the threads are still open.
+    let spans = serde_json::to_value(&builder).unwrap()["spans"].as_object().unwrap().len();
+    assert_eq!(spans, 2);
+    for event in &[sync_start("work", 4, 3, 55), TraceEvent::SyncEnd { id: SpanId(4), ts: ms(58) }] {
+        builder.add_event(event);
+        reloaded.add_event(event);
+    }
+    assert_eq!(reloaded.summarise(ms(0), ms(60), 100), builder.summarise(ms(0), ms(60), 100));
+}
+
+fn async_start(name: &str, id: u64, parent_id: u64, ts: u64) -> TraceEvent {
+    TraceEvent::AsyncStart {
+        name: name.to_string(), id: SpanId(id), parent_id: SpanId(parent_id), ts: ms(ts),
+        metadata: json_null(),
+    }
+}
+
+fn sync_start(name: &str, id: u64, parent_id: u64, ts: u64) -> TraceEvent {
+    TraceEvent::SyncStart {
+        name: name.to_string(), id: SpanId(id), parent_id: SpanId(parent_id), ts: ms(ts),
+        metadata: json_null(),
+    }
+}
+
+fn json_null() -> serde_json::Value {
+    serde_json::Value::Null
+}
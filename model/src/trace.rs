@@ -0,0 +1,143 @@
+//! A whole trace in memory: every span seen so far, finished or not, with
+//! lookups by id, by parent and by time.
+
+use std::collections::HashMap;
+use std::time::Duration;
+use event::{SpanId, TraceEvent};
+use index::IntervalIndex;
+use spans::{Error, Reconstructor, Span};
+
+#[derive(Debug, Default)]
+pub struct Trace {
+    active: Reconstructor,
+    finished: HashMap<SpanId, Span>,
+    // Finished spans by time.  Open spans are few enough to scan.
+    index: IntervalIndex<SpanId>,
+    children: HashMap<SpanId, Vec<SpanId>>,
+}
+
+impl Trace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build a trace from events, dropping any the reconstructor rejects.
+    pub fn from_events<I: IntoIterator<Item = TraceEvent>>(events: I) -> Self {
+        let mut trace = Trace::new();
+        for event in events {
+            let _ = trace.add_event(event);
+        }
+        trace
+    }
+
+    // `or_default` is newer than f2's toolchain.
+    #[allow(clippy::unwrap_or_default)]
+    pub fn add_event(&mut self, event: TraceEvent) -> Result<(), Error> {
+        let started = match (event.id(), event.parent_id()) {
+            (Some(id), Some(parent_id)) => Some((id, parent_id)),
+            _ => None,
+        };
+        if let Some(span) = self.active.add_event(event)? {
+            let end = span.end_or(span.start);
+            self.index.insert(span.start, end, span.id);
+            self.finished.insert(span.id, span);
+        }
+        if let Some((id, parent_id)) = started {
+            self.children.entry(parent_id).or_insert_with(Vec::new).push(id);
+        }
+        Ok(())
+    }
+
+    /// The latest timestamp seen so far; open spans are treated as ending
+    /// here.
+    pub fn end_time(&self) -> Duration {
+        self.active.end_time()
+    }
+
+    pub fn len(&self) -> usize {
+        self.active.len() + self.finished.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, id: SpanId) -> Option<&Span> {
+        self.active.get(id).or_else(|| self.finished.get(&id))
+    }
+
+    /// Children of `id`, in the order they started.
+    pub fn children(&self, id: SpanId) -> &[SpanId] {
+        self.children.get(&id).map(|c| &c[..]).unwrap_or(&[])
+    }
+
+    /// Every span, in no particular order.
+    pub fn spans(&self) -> Vec<&Span> {
+        self.active.active().chain(self.finished.values()).collect()
+    }
+
+    /// Spans with the given name, in order of starting.
+    pub fn find(&self, name: &str) -> Vec<&Span> {
+        let mut spans: Vec<_> = self.spans().into_iter().filter(|s| s.name == name).collect();
+        spans.sort_by_key(|s| s.start);
+        spans
+    }
+
+    /// Spans overlapping `start..end`, with open spans first.
+    pub fn overlapping(&self, start: Duration, end: Duration) -> Vec<&Span> {
+        self.active.active()
+            .filter(|s| s.start < end)
+            .chain(self.index.overlapping(start, end).map(|id| &self.finished[id]))
+            .collect()
+    }
+
+    /// Each span's name path: the names of its thread and ancestors down to
+    /// itself, separated by `;`.  Any `;` or newline in a name is replaced,
+    /// so the path splits back into one name per span.
+    pub fn name_paths(&self) -> HashMap<SpanId, String> {
+        let mut paths: HashMap<SpanId, String> = HashMap::new();
+        for span in self.spans() {
+            if paths.contains_key(&span.id) {
+                continue;
+            }
+            // Walk up to the first ancestor we already know, bounded in case
+            // of a malformed trace with a cycle.
+            let mut chain = vec![span];
+            let mut prefix = String::new();
+            while let Some(parent) = chain.last().unwrap().parent_id.and_then(|p| self.get(p)) {
+                if let Some(path) = paths.get(&parent.id) {
+                    prefix = path.clone();
+                    break;
+                }
+                if chain.len() > self.len() {
+                    break;
+                }
+                chain.push(parent);
+            }
+            for span in chain.into_iter().rev() {
+                if paths.contains_key(&span.id) {
+                    continue;
+                }
+                if !prefix.is_empty() {
+                    prefix.push(';');
+                }
+                prefix.push_str(&span.name.replace(';', ":").replace('\n', " "));
+                paths.insert(span.id, prefix.clone());
+            }
+        }
+        paths
+    }
+
+    /// The thread `id` was started under, found by following parents.
+    pub fn thread(&self, id: SpanId) -> Option<&Span> {
+        let mut span = self.get(id)?;
+        // Bounded, so that a malformed trace with a cycle can't hang us.
+        for _ in 0..self.len() {
+            match span.parent_id {
+                Some(parent) => span = self.get(parent)?,
+                None => return Some(span),
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,86 @@
+//! Level-of-detail summaries of traces, built up as they're written, for
+//! queries that zoom out too far to show every span.  Like indexes, they're
+//! cached next to the trace, as `<trace>.lod`, and extended as it grows, so
+//! each event's only ever summarised once.
+
+use std::fs::{self, File};
+use std::io::{
+    self,
+    BufRead,
+    BufReader,
+    BufWriter,
+    Seek,
+    SeekFrom,
+    Write,
+};
+use std::path::{Path, PathBuf};
+use serde_json;
+use cyclotron_model::lod::Builder;
+use index;
+
+// 2 leaves out ended spans, and busy time that summaries used to record for
+// spans still running.
+const VERSION: u32 = 2;
+
+#[derive(Deserialize, Serialize)]
+pub struct Detail {
+    version: u32,
+    // How much of the trace has been read, always at the end of a line.
+    size: u64,
+    fingerprint: (u64, u64),
+    pub builder: Builder,
+}
+
+/// Where the summaries for `trace` are kept.
+pub fn detail_path(trace: &Path) -> PathBuf {
+    let mut name = trace.file_name().map(|n| n.to_owned()).unwrap_or_default();
+    name.push(".lod");
+    trace.with_file_name(name)
+}
+
+impl Detail {
+    pub fn new() -> Self {
+        Detail { version: VERSION, size: 0, fingerprint: (0, 0), builder: Builder::new() }
+    }
+
+    /// The cached summaries for `trace`, or empty ones if there aren't
+    /// usable ones.
+    pub fn load(trace: &Path) -> Self {
+        let file = File::open(detail_path(trace));
+        let detail = file.ok().and_then(|f| serde_json::from_reader::<_, Detail>(BufReader::new(f)).ok());
+        detail.filter(|d| d.version == VERSION).unwrap_or_else(Detail::new)
+    }
+
+    pub fn save(&self, trace: &Path) -> io::Result<()> {
+        index::replace(&detail_path(trace), |path| {
+            let mut file = BufWriter::new(File::create(path)?);
+            serde_json::to_writer(&mut file, self)?;
+            file.flush()
+        })
+    }
+
+    /// Add whatever's been written to `trace` since the last update, and
+    /// return whether there was anything.
+    pub fn update(&mut self, trace: &Path) -> io::Result<bool> {
+        if fs::metadata(trace)?.len() < self.size || (self.size > 0 && index::fingerprint(trace, self.size)? != self.fingerprint) {
+            *self = Detail::new();
+        }
+        let mut file = File::open(trace)?;
+        file.seek(SeekFrom::Start(self.size))?;
+        let mut reader = BufReader::new(file);
+        let mut line = String::new();
+        let start = self.size;
+        loop {
+            line.clear();
+            let n = reader.read_line(&mut line)?;
+            if n == 0 || !line.ends_with('\n') {
+                self.fingerprint = index::fingerprint(trace, self.size)?;
+                return Ok(self.size > start);
+            }
+            self.size += n as u64;
+            if let Ok(event) = serde_json::from_str(&line) {
+                self.builder.add_event(&event);
+            }
+        }
+    }
+}
@@ -0,0 +1,353 @@
+//! An index of where things are in a JSON trace, so time window queries only
+//! read the part of the file they need.  It's cached next to the trace, as
+//! `<trace>.idx`, and extended as the trace grows.  Traces are expected to
+//! only ever be appended to: one that shrinks is indexed again from scratch.
+//!
+//! The file is split into blocks of whole lines, each with the earliest and
+//! latest timestamps in it.  Threads log independently, so a file is only
+//! roughly in time order, but any block preceded only by events before a
+//! window can be skipped, as can everything from a block onward if nothing
+//! there is before the window's end.  Spans' start and end lines are indexed
+//! too, so the start events of those still open can be picked out, as are the
+//! on-CPU events of those running at the start of each block.
+//!
+//! A trace that's been replaced is noticed by hashing the start and end of
+//! what's been indexed, and indexed again too.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{
+    self,
+    BufRead,
+    BufReader,
+    BufWriter,
+    Read,
+    Seek,
+    SeekFrom,
+    Write,
+};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use serde_json;
+use cyclotron_model::{SpanId, TraceEvent};
+
+const MAGIC: &[u8] = b"CYCLOIDX";
+const VERSION: u32 = 2;
+pub const BLOCK_SIZE: u64 = 256 * 1024;
+// Stands for `None` in the index file.
+const NONE: u64 = !0;
+// How much of each end of the indexed part of the trace to hash.
+const FINGERPRINT_SIZE: u64 = 4096;
+
+// Numbers temporary files, so concurrent saves don't share one.
+static SAVES: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone, Debug, PartialEq)]
+struct Block {
+    offset: u64,
+    min: Option<Duration>,
+    max: Option<Duration>,
+    // Offsets of the on-CPU lines of spans running when the block starts.
+    on_cpu: Vec<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceIndex {
+    block_size: u64,
+    // How much of the trace is indexed, always at the end of a line.
+    size: u64,
+    // Hashes of the first and last bytes indexed.
+    fingerprint: (u64, u64),
+    blocks: Vec<Block>,
+    // Offsets of each span's start line and, once it's ended, its end line.
+    spans: HashMap<SpanId, (u64, Option<u64>)>,
+    // Offsets of the on-CPU lines of spans running at the end.
+    running: HashMap<SpanId, u64>,
+}
+
+fn min(a: Option<Duration>, b: Option<Duration>) -> Option<Duration> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(cmp::min(a, b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+fn max(a: Option<Duration>, b: Option<Duration>) -> Option<Duration> {
+    cmp::max(a, b)
+}
+
+/// Where the index for `trace` is kept.
+pub fn index_path(trace: &Path) -> PathBuf {
+    let mut name = trace.file_name().map(|n| n.to_owned()).unwrap_or_default();
+    name.push(".idx");
+    trace.with_file_name(name)
+}
+
+/// Whether a file in the traces directory is an index or summaries cached
+/// for a trace, or one being saved, rather than a trace.
+pub fn is_cache(name: &str) -> bool {
+    [".idx", ".lod"].iter().any(|ext| name.ends_with(ext) || name.contains(&format!("{}.tmp", ext)))
+}
+
+/// Write `path` with `write`, replacing the old one all at once so it's
+/// never seen half written.
+pub fn replace<F: FnOnce(&Path) -> io::Result<()>>(path: &Path, write: F) -> io::Result<()> {
+    let mut name = path.file_name().unwrap().to_owned();
+    name.push(format!(".tmp{}-{}", process::id(), SAVES.fetch_add(1, Ordering::SeqCst)));
+    let tmp = path.with_file_name(name);
+    let result = write(&tmp).and_then(|()| fs::rename(&tmp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+// FNV-1a, which unlike `DefaultHasher` is the same from one build to the
+// next.
+fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |h, &b| (h ^ b as u64).wrapping_mul(0x100_0000_01b3))
+}
+
+/// Hashes of the start and end of the first `size` bytes of `trace`, to tell
+/// whether it's been replaced since.
+pub fn fingerprint(trace: &Path, size: u64) -> io::Result<(u64, u64)> {
+    let mut file = File::open(trace)?;
+    let mut read = |offset: u64| -> io::Result<u64> {
+        let mut buf = vec![0; cmp::min(size - offset, FINGERPRINT_SIZE) as usize];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut buf)?;
+        Ok(hash(&buf))
+    };
+    Ok((read(0)?, read(size.saturating_sub(FINGERPRINT_SIZE))?))
+}
+
+impl TraceIndex {
+    pub fn new(block_size: u64) -> Self {
+        TraceIndex {
+            block_size,
+            size: 0,
+            fingerprint: (0, 0),
+            blocks: vec![],
+            spans: HashMap::new(),
+            running: HashMap::new(),
+        }
+    }
+
+    /// The cached index for `trace`, or an empty one if there isn't a usable
+    /// one.
+    pub fn load(trace: &Path) -> Self {
+        let mut buf = vec![];
+        let read = File::open(index_path(trace)).and_then(|mut f| f.read_to_end(&mut buf));
+        match read.ok().and_then(|_| Self::decode(&buf)) {
+            Some(index) => index,
+            None => TraceIndex::new(BLOCK_SIZE),
+        }
+    }
+
+    // The index file is the magic and version, then little-endian u64s: the
+    // block size, indexed size, fingerprint, block count, each block's offset,
+    // min and max timestamps in nanoseconds, and count and offsets of on-CPU
+    // lines, span count, each span's id, start offset and end offset, running
+    // span count, and each running span's id and on-CPU offset.
+    fn decode(buf: &[u8]) -> Option<Self> {
+        if !buf.starts_with(MAGIC) || buf.len() < MAGIC.len() + 4 {
+            return None;
+        }
+        let mut version = [0; 4];
+        version.copy_from_slice(&buf[MAGIC.len()..MAGIC.len() + 4]);
+        if u32::from_le_bytes(version) != VERSION || buf.len() % 8 != (MAGIC.len() + 4) % 8 {
+            return None;
+        }
+        let mut words = buf[MAGIC.len() + 4..].chunks(8).map(|c| {
+            let mut word = [0; 8];
+            word.copy_from_slice(c);
+            u64::from_le_bytes(word)
+        });
+        let ts = |n: u64| if n == NONE { None } else { Some(Duration::from_nanos(n)) };
+        let mut index = TraceIndex::new(words.next()?);
+        index.size = words.next()?;
+        index.fingerprint = (words.next()?, words.next()?);
+        for _ in 0..words.next()? {
+            let (offset, min, max) = (words.next()?, words.next()?, words.next()?);
+            let on_cpu = (0..words.next()?).map(|_| words.next()).collect::<Option<_>>()?;
+            index.blocks.push(Block { offset, min: ts(min), max: ts(max), on_cpu });
+        }
+        for _ in 0..words.next()? {
+            let (id, start, end) = (words.next()?, words.next()?, words.next()?);
+            index.spans.insert(SpanId(id), (start, if end == NONE { None } else { Some(end) }));
+        }
+        for _ in 0..words.next()? {
+            let (id, offset) = (words.next()?, words.next()?);
+            index.running.insert(SpanId(id), offset);
+        }
+        Some(index)
+    }
+
+    /// Save the index for `trace`, replacing the old one all at once so it's
+    /// never seen half written.
+    pub fn save(&self, trace: &Path) -> io::Result<()> {
+        replace(&index_path(trace), |path| self.write(path))
+    }
+
+    fn write(&self, path: &Path) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let nanos = |ts: Option<Duration>| ts.map(|ts| ts.as_secs() * 1_000_000_000 + ts.subsec_nanos() as u64);
+        file.write_all(MAGIC)?;
+        file.write_all(&VERSION.to_le_bytes())?;
+        let mut words = vec![self.block_size, self.size, self.fingerprint.0, self.fingerprint.1, self.blocks.len() as u64];
+        for block in &self.blocks {
+            words.extend_from_slice(&[block.offset, nanos(block.min).unwrap_or(NONE), nanos(block.max).unwrap_or(NONE)]);
+            words.push(block.on_cpu.len() as u64);
+            words.extend_from_slice(&block.on_cpu);
+        }
+        words.push(self.spans.len() as u64);
+        for (id, &(start, end)) in &self.spans {
+            words.extend_from_slice(&[id.0, start, end.unwrap_or(NONE)]);
+        }
+        words.push(self.running.len() as u64);
+        for (id, &offset) in &self.running {
+            words.extend_from_slice(&[id.0, offset]);
+        }
+        for word in words {
+            file.write_all(&word.to_le_bytes())?;
+        }
+        file.flush()
+    }
+
+    fn event(&mut self, offset: u64, event: &TraceEvent) {
+        let ts = Some(event.ts());
+        {
+            let block = self.blocks.last_mut().unwrap();
+            block.min = min(block.min, ts);
+            block.max = max(block.max, ts);
+        }
+        match *event {
+            TraceEvent::ThreadStart { id, .. }
+            | TraceEvent::AsyncStart { id, .. }
+            | TraceEvent::SyncStart { id, .. }
+            | TraceEvent::IdleStart { id, .. } => {
+                self.spans.insert(id, (offset, None));
+            },
+            TraceEvent::AsyncEnd { id, .. }
+            | TraceEvent::SyncEnd { id, .. }
+            | TraceEvent::ThreadEnd { id, .. }
+            | TraceEvent::IdleEnd { id, .. } => {
+                if let Some(span) = self.spans.get_mut(&id) {
+                    span.1 = Some(offset);
+                }
+                self.running.remove(&id);
+            },
+            TraceEvent::AsyncOnCPU { id, .. } => {
+                self.running.insert(id, offset);
+            },
+            TraceEvent::AsyncOffCPU { id, .. } => {
+                self.running.remove(&id);
+            },
+            TraceEvent::Wakeup { .. } | TraceEvent::Mark { .. } => (),
+        }
+    }
+
+    /// Index whatever's been added to `trace` since the last update, and
+    /// return whether there was anything.
+    pub fn update(&mut self, trace: &Path) -> io::Result<bool> {
+        if fs::metadata(trace)?.len() < self.size || (self.size > 0 && fingerprint(trace, self.size)? != self.fingerprint) {
+            *self = TraceIndex::new(self.block_size);
+        }
+        let mut file = File::open(trace)?;
+        file.seek(SeekFrom::Start(self.size))?;
+        let mut reader = BufReader::new(file);
+        let mut line = String::new();
+        let start = self.size;
+        loop {
+            line.clear();
+            let n = reader.read_line(&mut line)?;
+            if n == 0 || !line.ends_with('\n') {
+                self.fingerprint = fingerprint(trace, self.size)?;
+                return Ok(self.size > start);
+            }
+            let offset = self.size;
+            self.size += n as u64;
+            let new_block = self.blocks.last().map(|b| offset >= b.offset + self.block_size).unwrap_or(true);
+            if new_block {
+                let mut on_cpu: Vec<u64> = self.running.values().cloned().collect();
+                on_cpu.sort();
+                self.blocks.push(Block { offset, min: None, max: None, on_cpu });
+            }
+            // Lines that don't parse are left for whoever reads them to
+            // report.
+            if let Ok(event) = serde_json::from_str(&line) {
+                self.event(offset, &event);
+            }
+        }
+    }
+
+    /// The earliest and latest timestamps in the trace.
+    pub fn range(&self) -> (Option<Duration>, Option<Duration>) {
+        self.blocks.iter().fold((None, None), |(start, end), b| (min(start, b.min), max(end, b.max)))
+    }
+
+    /// Where to start reading to see every event from `start` on: the start
+    /// of the last block only preceded by events before it.
+    pub fn seek(&self, start: Duration) -> u64 {
+        let mut before = None;
+        let mut offset = 0;
+        for block in &self.blocks {
+            if before.map(|b| b >= start).unwrap_or(false) {
+                break;
+            }
+            offset = block.offset;
+            before = max(before, block.max);
+        }
+        offset
+    }
+
+    /// Offsets of the start lines of spans that started before `offset` and
+    /// hadn't ended by then, and, if `offset` is a block's start, the on-CPU
+    /// lines of those running there, in file order.
+    pub fn open_at(&self, offset: u64) -> Vec<u64> {
+        let mut lines: Vec<u64> = self.spans.values()
+            .filter(|&&(start, end)| start < offset && end.map(|e| e >= offset).unwrap_or(true))
+            .map(|&(start, _)| start)
+            .collect();
+        if let Some(block) = self.blocks.iter().find(|b| b.offset == offset) {
+            lines.extend_from_slice(&block.on_cpu);
+        }
+        lines.sort();
+        lines
+    }
+
+    /// Where to stop reading once everything up to `end` has been seen, or
+    /// `None` to read to the end of what's indexed.
+    pub fn stop(&self, end: Duration) -> Option<u64> {
+        let mut after = None;
+        let mut stop = None;
+        for block in self.blocks.iter().rev() {
+            after = min(after, block.min);
+            if after.map(|a| a <= end).unwrap_or(false) {
+                break;
+            }
+            stop = Some(block.offset);
+        }
+        stop
+    }
+}
+
+/// The lines starting at each of `offsets` in `trace`, without newlines.
+pub fn read_lines_at(trace: &Path, offsets: &[u64]) -> io::Result<Vec<String>> {
+    let mut reader = BufReader::new(File::open(trace)?);
+    let mut lines = vec![];
+    for &offset in offsets {
+        reader.seek(SeekFrom::Start(offset))?;
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if line.ends_with('\n') {
+            line.pop();
+        }
+        lines.push(line);
+    }
+    Ok(lines)
+}
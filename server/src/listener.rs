@@ -0,0 +1,103 @@
+//! HTTP and websockets on one port.  Each connection's request line is
+//! peeked at, without consuming it, to decide which server gets it:
+//! `GET /ws/...` goes to the websocket streamer and everything else to hyper.
+
+use std::io::{self, Write};
+use std::net::{self, SocketAddr};
+use std::thread;
+use std::time::Duration;
+use failure::Error;
+use futures::{Future, Stream};
+use futures::sync::mpsc;
+use hyper::Chunk;
+use hyper::server::Http;
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::Core;
+use websocket::sync::server::upgrade::IntoWs;
+use CyclotronServer;
+
+const WS_PREFIX: &[u8] = b"GET /ws/";
+const BAD_REQUEST: &[u8] = b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+// How long to wait for a request line that's arriving in pieces.
+const PEEK_TRIES: usize = 100;
+const PEEK_INTERVAL: Duration = Duration::from_millis(10);
+
+pub fn is_websocket(stream: &net::TcpStream) -> io::Result<bool> {
+    let mut buf = [0; 8];
+    for _ in 0..PEEK_TRIES {
+        let n = stream.peek(&mut buf)?;
+        // Closed, decided, or possibly a websocket but not all here yet.
+        if n == 0 || n == WS_PREFIX.len() || buf[..n] != WS_PREFIX[..n] {
+            return Ok(buf[..n] == *WS_PREFIX);
+        }
+        thread::sleep(PEEK_INTERVAL);
+    }
+    Ok(false)
+}
+
+fn accept(stream: net::TcpStream, server: &CyclotronServer, http: &mpsc::UnboundedSender<net::TcpStream>) {
+    match is_websocket(&stream) {
+        Ok(true) => match stream.into_ws() {
+            Ok(upgrade) => {
+                if let Err(e) = server.stream(upgrade) {
+                    println!("Failed on stream: {:?}", e);
+                }
+            },
+            Err((mut stream, _, _, e)) => {
+                // The request has been read, so it's too late to hand the
+                // connection to hyper; answer it here instead.
+                println!("Bad websocket request: {:?}", e);
+                let _ = stream.write_all(BAD_REQUEST);
+            },
+        },
+        Ok(false) => {
+            let _ = http.unbounded_send(stream);
+        },
+        Err(e) => println!("Failed to peek at request: {:?}", e),
+    }
+}
+
+/// Serve forever on `addr`.
+pub fn run(addr: &SocketAddr, server: CyclotronServer) -> Result<(), Error> {
+    serve(net::TcpListener::bind(addr)?, server)
+}
+
+/// Serve forever on connections to `listener`.
+pub fn serve(listener: net::TcpListener, server: CyclotronServer) -> Result<(), Error> {
+    let (tx, rx) = mpsc::unbounded();
+
+    let cyclotron = server.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(s) => s,
+                Err(e) => {
+                    println!("Failed to accept: {:?}", e);
+                    continue;
+                },
+            };
+            // Websocket streams run for as long as the client's connected,
+            // and even HTTP ones can take a while to show their request
+            // line, so each gets its own thread.
+            let (cyclotron, tx) = (cyclotron.clone(), tx.clone());
+            thread::spawn(move || accept(stream, &cyclotron, &tx));
+        }
+    });
+
+    let mut core = Core::new()?;
+    let handle = core.handle();
+    let http = Http::<Chunk>::new();
+    let connections = rx.for_each(|stream| {
+        match TcpStream::from_stream(stream, &handle) {
+            Ok(stream) => {
+                let connection = http.serve_connection(stream, server.clone())
+                    .map(|_| ())
+                    .map_err(|e| println!("Failed on HTTP connection: {:?}", e));
+                handle.spawn(connection);
+            },
+            Err(e) => println!("Failed to register connection: {:?}", e),
+        }
+        Ok(())
+    });
+    core.run(connections).map_err(|()| format_err!("Listener thread stopped"))
+}
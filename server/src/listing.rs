@@ -0,0 +1,172 @@
+//! The traces directory as the trace picker sees it, with a summary of each
+//! JSON trace.  Summaries are cached and picked up where they left off as
+//! traces grow, so listing a directory of live traces stays cheap.  Each is
+//! taken out of the cache while its trace is scanned, so listings don't hold
+//! each other up.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{
+    self,
+    BufRead,
+    BufReader,
+    Read,
+    Seek,
+    SeekFrom,
+};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde_json;
+use index;
+use cyclotron_model::TraceEvent;
+use cyclotron_model::binary::MAGIC;
+
+// A trace modified this recently is taken to still be being written.
+const LIVE_WINDOW: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Summary {
+    /// In the order they started.
+    pub threads: Vec<String>,
+    pub spans: usize,
+    pub start: Option<Duration>,
+    pub end: Option<Duration>,
+    // How far the file's been scanned, always at the end of a line, and the
+    // fingerprint of that much.
+    #[serde(skip)]
+    offset: u64,
+    #[serde(skip)]
+    fingerprint: (u64, u64),
+}
+
+#[derive(Debug, Serialize)]
+pub struct TraceInfo {
+    pub name: String,
+    pub size: u64,
+    /// Since the Unix epoch.
+    pub modified: Duration,
+    pub live: bool,
+    /// Missing for binary traces and files that couldn't be read.
+    pub summary: Option<Summary>,
+}
+
+impl Summary {
+    fn event(&mut self, event: &TraceEvent) {
+        match *event {
+            TraceEvent::ThreadStart { ref name, .. } => {
+                self.threads.push(name.clone());
+                self.spans += 1;
+            },
+            TraceEvent::AsyncStart { .. } | TraceEvent::SyncStart { .. } | TraceEvent::IdleStart { .. } => {
+                self.spans += 1;
+            },
+            _ => (),
+        }
+        let ts = event.ts();
+        if self.start.map(|s| ts < s).unwrap_or(true) {
+            self.start = Some(ts);
+        }
+        if self.end.map(|e| ts > e).unwrap_or(true) {
+            self.end = Some(ts);
+        }
+    }
+
+    // Scan whole lines added since the last call.  Lines that don't parse
+    // are skipped, as the viewer would.
+    fn update(&mut self, path: &Path) -> io::Result<()> {
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut reader = BufReader::new(file);
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let n = reader.read_line(&mut line)?;
+            if n == 0 || !line.ends_with('\n') {
+                self.fingerprint = index::fingerprint(path, self.offset)?;
+                return Ok(());
+            }
+            self.offset += n as u64;
+            if let Ok(event) = serde_json::from_str(&line) {
+                self.event(&event);
+            }
+        }
+    }
+}
+
+fn is_binary(path: &Path) -> io::Result<bool> {
+    let mut header = vec![];
+    File::open(path)?.take(MAGIC.len() as u64).read_to_end(&mut header)?;
+    Ok(header == MAGIC)
+}
+
+#[derive(Default)]
+pub struct Listing {
+    summaries: Mutex<HashMap<PathBuf, Summary>>,
+}
+
+impl Listing {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn summary(&self, path: &Path, size: u64) -> io::Result<Option<Summary>> {
+        if is_binary(path)? {
+            return Ok(None);
+        }
+        let cached = self.summaries.lock().unwrap().remove(path);
+        let mut summary = cached.unwrap_or_default();
+        // Truncated or replaced since the last scan, so start over.
+        let replaced = size < summary.offset
+            || (summary.offset > 0 && index::fingerprint(path, summary.offset)? != summary.fingerprint);
+        if replaced {
+            summary = Summary::default();
+        }
+        let updated = summary.update(path);
+        let result = summary.clone();
+        self.summaries.lock().unwrap().insert(path.to_owned(), summary);
+        updated?;
+        Ok(Some(result))
+    }
+
+    /// Every file in `dir`, by name.
+    pub fn list(&self, dir: &Path) -> io::Result<Vec<TraceInfo>> {
+        let now = SystemTime::now();
+        let mut traces = vec![];
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() {
+                continue;
+            }
+            // The websocket can only ask for traces by UTF-8 name.
+            let name = match entry.file_name().into_string() {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+            if index::is_cache(&name) {
+                continue;
+            }
+            let path = entry.path();
+            let modified = metadata.modified()?;
+            let summary = match self.summary(&path, metadata.len()) {
+                Ok(summary) => summary,
+                Err(e) => {
+                    println!("Failed to summarise {:?}: {:?}", path, e);
+                    None
+                },
+            };
+            traces.push(TraceInfo {
+                name,
+                size: metadata.len(),
+                modified: modified.duration_since(UNIX_EPOCH).unwrap_or_default(),
+                live: now.duration_since(modified).map(|d| d < LIVE_WINDOW).unwrap_or(true),
+                summary,
+            });
+        }
+        // Forget traces that have gone.
+        self.summaries.lock().unwrap().retain(|path, _| path.exists());
+        traces.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(traces)
+    }
+}
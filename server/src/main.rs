@@ -1,13 +1,26 @@
-extern crate cyclotron_backend;
+
+extern crate cyclotron_model;
 extern crate docopt;
 extern crate hyper;
 #[macro_use]
 extern crate serde_derive;
 extern crate websocket;
 extern crate futures;
+extern crate futures_cpupool;
 #[macro_use]
 extern crate failure;
+extern crate notify;
+extern crate percent_encoding;
 extern crate serde_json;
+extern crate tokio_core;
+
+mod detail;
+mod index;
+mod listener;
+mod listing;
+mod paths;
+mod query;
+mod tail;
 
 use std::fs::{
     self,
@@ -19,30 +32,28 @@ use std::net::{
     SocketAddrV4,
     TcpStream,
 };
+use std::collections::HashMap;
 use std::io::{
     self,
-    BufRead,
-    BufReader,
     Read,
 };
-use std::time::Duration;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use cyclotron_backend::TraceEvent;
 use failure::Error;
+use percent_encoding::percent_decode;
 use futures::{
     future,
 };
 use futures::future::Future;
+use futures_cpupool::CpuPool;
 use docopt::Docopt;
 use hyper::{
     Method,
     StatusCode,
 };
+use hyper::header::ContentType;
 use hyper::server::{
-    Http,
-    NewService,
     Request,
     Response,
     Service,
@@ -51,15 +62,27 @@ use websocket::{Message, OwnedMessage};
 use websocket::server::upgrade::WsUpgrade;
 use websocket::server::upgrade::sync::Buffer;
 use websocket::sync::Server;
+use detail::Detail;
+use index::TraceIndex;
+use listing::Listing;
+use paths::PathError;
+use query::{Filter, Header, Query, Reply};
+use tail::Tail;
 
 struct Inner {
     traces_dir: PathBuf,
     frontend_dir: PathBuf,
+    indexes: HashMap<PathBuf, TraceIndex>,
+    details: HashMap<PathBuf, Detail>,
 }
 
 #[derive(Clone)]
 struct CyclotronServer {
     inner: Arc<Mutex<Inner>>,
+    listing: Arc<Listing>,
+    // For requests too slow to answer on the event loop, like listings,
+    // which scan every trace the first time.
+    pool: CpuPool,
 }
 
 impl CyclotronServer {
@@ -67,8 +90,14 @@ impl CyclotronServer {
         let inner = Inner {
             traces_dir: PathBuf::from(&args.flag_traces),
             frontend_dir: PathBuf::from(&args.flag_frontend),
+            indexes: HashMap::new(),
+            details: HashMap::new(),
         };
-        Self { inner: Arc::new(Mutex::new(inner)) }
+        Self {
+            inner: Arc::new(Mutex::new(inner)),
+            listing: Arc::new(Listing::new()),
+            pool: CpuPool::new_num_cpus(),
+        }
     }
 
     fn serve_traces(&self) -> Response {
@@ -90,16 +119,52 @@ impl CyclotronServer {
         let inner = self.inner.lock().unwrap();
         let mut buf = String::new();
         for entry in fs::read_dir(&inner.traces_dir)? {
-            buf = (buf + entry?.file_name().to_str().unwrap()) + " ";
+            let name = entry?.file_name();
+            let name = name.to_str().unwrap();
+            if !index::is_cache(name) {
+                buf = (buf + name) + " ";
+            }
         }
         Ok(buf)
     }
 
+    fn serve_trace_list(&self) -> Response {
+        let mut response = Response::new();
+        match self._serve_trace_list() {
+            Ok(buf) => {
+                response.headers_mut().set(ContentType::json());
+                response.set_body(buf);
+            },
+            Err(e) => {
+                println!("Failed to list traces: {:?}", e);
+                response.set_status(StatusCode::InternalServerError);
+                return response;
+            },
+        }
+        response
+    }
+
+    fn _serve_trace_list(&self) -> Result<Vec<u8>, Error> {
+        let traces_dir = self.inner.lock().unwrap().traces_dir.clone();
+        let traces = self.listing.list(&traces_dir)?;
+        Ok(serde_json::to_vec(&traces)?)
+    }
+
     fn serve_frontend(&self, p: &str) -> Response {
         let inner = self.inner.lock().unwrap();
-        let path = inner.frontend_dir.clone().join(p);
-
         let mut response = Response::new();
+        let path = match paths::resolve(&inner.frontend_dir, p) {
+            Ok(path) => path,
+            Err(e) => {
+                println!("Refused to serve {:?}: {}", p, e);
+                let status = match e {
+                    PathError::Forbidden => StatusCode::Forbidden,
+                    PathError::Io(_) => StatusCode::NotFound,
+                };
+                response.set_status(status);
+                return response;
+            },
+        };
         let file = match File::open(&path) {
             Ok(f) => f,
             Err(e) => {
@@ -119,69 +184,154 @@ impl CyclotronServer {
         response
     }
 
+    // Run `f` on the up to date index of `path`.  The index is taken out
+    // while it's updated, so other traces can be listed and queried
+    // meanwhile.
+    fn with_index<T, F: FnOnce(&TraceIndex) -> T>(&self, path: &Path, f: F) -> io::Result<T> {
+        let cached = self.inner.lock().unwrap().indexes.remove(path);
+        let mut index = cached.unwrap_or_else(|| TraceIndex::load(path));
+        let updated = index.update(path);
+        if let Ok(true) = updated {
+            if let Err(e) = index.save(path) {
+                println!("Failed to save index for {:?}: {:?}", path, e);
+            }
+        }
+        let result = f(&index);
+        self.inner.lock().unwrap().indexes.insert(path.to_owned(), index);
+        updated?;
+        Ok(result)
+    }
+
+    // Run `f` on the up to date summaries of `path`, taken out while they're
+    // updated as with indexes.
+    fn with_detail<T, F: FnOnce(&Detail) -> T>(&self, path: &Path, f: F) -> io::Result<T> {
+        let cached = self.inner.lock().unwrap().details.remove(path);
+        let mut detail = cached.unwrap_or_else(|| Detail::load(path));
+        let updated = detail.update(path);
+        if let Ok(true) = updated {
+            if let Err(e) = detail.save(path) {
+                println!("Failed to save summaries for {:?}: {:?}", path, e);
+            }
+        }
+        let result = f(&detail);
+        self.inner.lock().unwrap().details.insert(path.to_owned(), detail);
+        updated?;
+        Ok(result)
+    }
+
     fn stream(&self, conn: WsUpgrade<TcpStream, Option<Buffer>>) -> Result<(), Error> {
         if !conn.protocols().contains(&"cyclotron-ws".into()) {
             conn.reject().map_err(|(_, e)| e)?;
             return Ok(());
         }
+        let uri = conn.uri();
         let mut client = conn.use_protocol("cyclotron-ws")
             .accept()
             .map_err(|(_, e)| e)?;
         println!("New connection from {:?}", client.peer_addr()?);
 
-        let path = match client.recv_message()? {
-            OwnedMessage::Text(s) => {
-                let inner = self.inner.lock().unwrap();
-                inner.traces_dir.clone().join(s)
-            },
-            r => return Err(format_err!("Unexpected message {:?}", r).into()),
+        // Clients on the HTTP port can name the trace in the URL to get all
+        // of it; otherwise they send a query.  Those on the legacy websocket
+        // port send a bare trace name instead.
+        let name = uri.strip_prefix("/ws/").unwrap_or("").split('?').next().unwrap();
+        let (query, header) = if !name.is_empty() {
+            (Query::whole(percent_decode(name.as_bytes()).decode_utf8()?.into_owned()), false)
+        } else {
+            match client.recv_message()? {
+                OwnedMessage::Text(ref s) if s.trim_start().starts_with('{') => match Query::parse(s) {
+                    Ok(query) => (query, true),
+                    Err(e) => {
+                        println!("Bad query {:?}: {}", s, e);
+                        // 1002 is "protocol error".
+                        client.send_message(&Message::close_because(1002, e))?;
+                        return Ok(());
+                    },
+                },
+                OwnedMessage::Text(s) => (Query::whole(s), false),
+                r => return Err(format_err!("Unexpected message {:?}", r)),
+            }
         };
-
-        let mut file = BufReader::new(File::open(&path)?);
-
-        // First, push the whole file over the socket
-        let mut fragment = loop {
-            let mut buf = String::new();
-            let num_read = file.read_line(&mut buf)?;
-
-            if num_read == 0 || !buf.ends_with("\n") {
-                break buf;
-            } else {
-                buf.pop();
-                // let event: TraceEvent = serde_json::from_str(&buf)?;
-                // println!("Read {:?}", event);
-                client.send_message(&Message::text(buf.as_str()))?;
+        let path = {
+            let inner = self.inner.lock().unwrap();
+            match paths::resolve(&inner.traces_dir, &query.trace) {
+                Err(PathError::Forbidden) => {
+                    println!("Refused to stream {:?}", query.trace);
+                    // 1008 is "policy violation".
+                    client.send_message(&Message::close_because(1008, "trace outside traces directory"))?;
+                    return Ok(());
+                },
+                r => r?,
             }
         };
 
-        loop {
-            let num_read = file.read_line(&mut fragment)?;
+        // Queries are planned with the trace's index: windows skip to the
+        // part of the file they need, picking up the starts of spans that
+        // are still open there.
+        let (offset, earlier, stop, range) = if header {
+            self.with_index(&path, |index| {
+                let offset = match query.start {
+                    Some(start) if query.offset == 0 => index.seek(start),
+                    _ => query.offset,
+                };
+                let earlier = if query.offset == 0 { index.open_at(offset) } else { vec![] };
+                let stop = if query.follow { None } else { query.end.and_then(|end| index.stop(end)) };
+                (offset, earlier, stop, index.range())
+            })?
+        } else {
+            (query.offset, vec![], None, (None, None))
+        };
+        let mut tail = Tail::open_at(&path, offset)?;
+        if header {
+            let header = Header {
+                version: query::VERSION,
+                size: fs::metadata(&path)?.len(),
+                start: range.0,
+                end: range.1,
+            };
+            client.send_message(&Message::text(serde_json::to_string(&Reply::Header(header))?))?;
+        }
 
-            if num_read == 0 || !fragment.ends_with("\n") {
-                // Just poll, sigh.
-                thread::sleep(Duration::from_millis(250));
-                continue;
+        // Zoomed out views get a summary instead of the events themselves.
+        if let Some(resolution) = query.resolution {
+            let start = query.start.or(range.0).unwrap_or_default();
+            let end = query.end.or(range.1).unwrap_or_default();
+            let mut summary = self.with_detail(&path, |d| d.builder.summarise(start, end, resolution))?;
+            if let Some(ref threads) = query.threads {
+                summary.rows.retain(|row| threads.contains(&row.thread));
             }
-
-            fragment.pop();
-            // let event: TraceEvent = serde_json::from_str(&fragment)?;
-            // println!("Read {:?}", event);
-            client.send_message(&Message::text(fragment.as_str()))?;
-
-            fragment.clear();
+            client.send_message(&Message::text(serde_json::to_string(&Reply::Summary(summary))?))?;
+            client.send_message(&Message::close())?;
+            return Ok(());
         }
 
-    }
-}
-
-impl NewService for CyclotronServer {
-    type Request = Request;
-    type Response = Response;
-    type Error = hyper::Error;
-    type Instance = Self;
-
-    fn new_service(&self) -> Result<Self, io::Error> {
-        Ok(self.clone())
+        let mut filter = Filter::new(&query);
+        let mut lines = vec![];
+        for line in index::read_lines_at(&path, &earlier)? {
+            filter.line(line, &mut lines);
+        }
+        loop {
+            let past_stop = stop.map(|stop| tail.position() >= stop).unwrap_or(false);
+            let line = if past_stop { None } else { tail.read_line()? };
+            let done = match line {
+                Some(line) => filter.line(line, &mut lines),
+                None if query.follow => {
+                    tail.wait();
+                    continue;
+                },
+                None => {
+                    filter.finish(&mut lines);
+                    true
+                },
+            };
+            for line in lines.drain(..) {
+                client.send_message(&Message::text(line))?;
+            }
+            if done {
+                break;
+            }
+        }
+        client.send_message(&Message::close())?;
+        Ok(())
     }
 }
 
@@ -189,15 +339,27 @@ impl Service for CyclotronServer {
     type Request = Request;
     type Response = Response;
     type Error = hyper::Error;
-    type Future = Box<Future<Item=Self::Response, Error=Self::Error>>;
+    type Future = Box<dyn Future<Item=Self::Response, Error=Self::Error>>;
 
     fn call(&self, req: Request) -> Self::Future {
         match (req.method(), req.path()) {
+            // Refused whichever route it'd have matched, so a traversal
+            // attempt is never mistaken for a missing page.
+            (_, p) if p.split('/').any(|c| c == "..") => {
+                println!("Refused to serve {:?}: {}", p, PathError::Forbidden);
+                let mut response = Response::new();
+                response.set_status(StatusCode::Forbidden);
+                Box::new(future::ok(response))
+            },
             (&Method::Get, "/") => {
                 Box::new(future::ok(self.serve_traces()))
             },
+            (&Method::Get, "/api/traces") => {
+                let server = self.clone();
+                Box::new(self.pool.spawn_fn(move || Ok(server.serve_trace_list())))
+            },
             (&Method::Get, p) if p.starts_with("/frontend/") => {
-                let response = self.serve_frontend(p.trim_left_matches("/frontend/"));
+                let response = self.serve_frontend(p.trim_start_matches("/frontend/"));
                 Box::new(future::ok(response))
             },
             _ => {
@@ -209,17 +371,17 @@ impl Service for CyclotronServer {
     }
 }
 
-const USAGE: &'static str = "
+const USAGE: &str = "
 Cyclotron trace server.
 
 Usage:
-   cyclotron-server --http=<port> --ws=<port> --traces=<path> --frontend=<path>
+   cyclotron-server --http=<port> [--ws=<port>] --traces=<path> --frontend=<path>
    cyclotron-server (-h | --help)
 
 Options:
   -h --help          Show this screen.
-  --http=<port>      Port for HTTP server
-  --ws=<port>        Port for websocket server
+  --http=<port>      Port for HTTP, and websockets at /ws/<trace>
+  --ws=<port>        Extra port for websocket clients that send the trace name
   --traces=<path>    Directory of available traces
   --frontend=<path>  Isaac's /frontend directory
 ";
@@ -227,7 +389,7 @@ Options:
 #[derive(Debug, Deserialize)]
 struct Args {
     flag_http: u16,
-    flag_ws: u16,
+    flag_ws: Option<u16>,
     flag_traces: String,
     flag_frontend: String,
 }
@@ -239,21 +401,25 @@ fn main() {
     let server = CyclotronServer::new(&args);
     let addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), args.flag_http));
 
-    let cyclotron = server.clone();
-    thread::spawn(move || {
-        let cyclotron = cyclotron;
-        let addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), args.flag_ws));
-        let ws_server = Server::bind(addr).unwrap();
+    if let Some(port) = args.flag_ws {
+        let cyclotron = server.clone();
+        thread::spawn(move || {
+            let addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port));
+            let ws_server = Server::bind(addr).unwrap();
 
-        for connection in ws_server.filter_map(Result::ok) {
-            // Spawn a thread per connection
-            let cyclotron_ = cyclotron.clone();
-            thread::spawn(move || match cyclotron_.stream(connection) {
-                Ok(_) => (),
-                Err(e) => println!("Failed on stream: {:?}", e),
-            });
-        }
-    });
+            for connection in ws_server.filter_map(Result::ok) {
+                // Spawn a thread per connection
+                let cyclotron_ = cyclotron.clone();
+                thread::spawn(move || match cyclotron_.stream(connection) {
+                    Ok(_) => (),
+                    Err(e) => println!("Failed on stream: {:?}", e),
+                });
+            }
+        });
+    }
 
-    Http::new().bind(&addr, server).unwrap().run().unwrap();
+    listener::run(&addr, server).unwrap();
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,49 @@
+//! Resolving file names from requests against the directories we serve, so
+//! nothing outside them can be read.
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Debug)]
+pub enum PathError {
+    /// The name points outside the directory, or could only be reached
+    /// through a symlink leading out of it.
+    Forbidden,
+    Io(io::Error),
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PathError::Forbidden => write!(f, "path outside served directory"),
+            PathError::Io(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for PathError {}
+
+impl From<io::Error> for PathError {
+    fn from(e: io::Error) -> Self {
+        PathError::Io(e)
+    }
+}
+
+/// The canonical path of `name` under `root`.  Absolute names and `..` are
+/// refused outright, before touching the filesystem, so the answer doesn't
+/// say what exists elsewhere; symlinks are then followed and have to stay
+/// under `root` too.
+pub fn resolve(root: &Path, name: &str) -> Result<PathBuf, PathError> {
+    let relative = Path::new(name);
+    if !relative.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+        return Err(PathError::Forbidden);
+    }
+    let root = root.canonicalize()?;
+    let path = root.join(relative).canonicalize()?;
+    if !path.starts_with(&root) {
+        return Err(PathError::Forbidden);
+    }
+    Ok(path)
+}
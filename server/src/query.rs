@@ -0,0 +1,251 @@
+//! The websocket query protocol.  A client that doesn't name a trace in its
+//! URL sends a query as its first message:
+//!
+//! ```text
+//! {"version": 2, "trace": "test2.log", "offset": 0,
+//!  "start": {"secs": 1, "nanos": 0}, "end": {"secs": 2, "nanos": 0},
+//!  "name": "poll", "threads": ["CPU 0"], "follow": false}
+//! ```
+//!
+//! Only `version` and `trace` are required.  The server replies with a
+//! `{"Header": ...}` message giving the trace's size and time range, then
+//! sends matching events, a line of the trace each, in file order.  It closes
+//! the socket at the first event past `end`, with or without `follow`: threads
+//! log independently, so any of the window's events logged after that are
+//! left out either way.  Otherwise, it closes the socket at the end of the
+//! file, unless following it.
+//!
+//! Version 2 adds `resolution`, for views too zoomed out to draw each span:
+//! the header is followed by a single `{"Summary": ...}` message, giving for
+//! each thread how busy it was over buckets of time, about `resolution` of
+//! them across the window (up to `MAX_RESOLUTION`), and the socket is
+//! closed.  Summaries can be limited to `threads`, but not by `name`.
+//! Version 1 queries are still accepted.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::time::Duration;
+use serde_json;
+use cyclotron_model::{SpanId, TraceEvent};
+use cyclotron_model::lod::Summary;
+
+pub const VERSION: u32 = 2;
+/// Summaries have at most this many buckets across the window, whatever
+/// `resolution` asks for.
+pub const MAX_RESOLUTION: u64 = 4096;
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Query {
+    pub version: u32,
+    pub trace: String,
+    /// Byte offset to start reading at, such as the `size` from an earlier
+    /// header.  Reading starts at the first whole line from there.
+    #[serde(default)]
+    pub offset: u64,
+    /// Only events from `[start, end]`.  Spans that started earlier but are
+    /// still open at `start` keep their start events.
+    #[serde(default)]
+    pub start: Option<Duration>,
+    #[serde(default)]
+    pub end: Option<Duration>,
+    /// Only spans whose names contain this, along with everything under
+    /// them and their threads.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Only spans on these threads.
+    #[serde(default)]
+    pub threads: Option<Vec<String>>,
+    #[serde(default)]
+    pub follow: bool,
+    /// About how many buckets to summarise the window in, instead of
+    /// sending its events.
+    #[serde(default)]
+    pub resolution: Option<u64>,
+}
+
+impl Query {
+    /// All of `trace`, followed as it's written, as clients that aren't
+    /// sending queries get.
+    pub fn whole(trace: String) -> Self {
+        Query {
+            version: VERSION,
+            trace,
+            offset: 0,
+            start: None,
+            end: None,
+            name: None,
+            threads: None,
+            follow: true,
+            resolution: None,
+        }
+    }
+
+    pub fn parse(message: &str) -> Result<Self, String> {
+        let mut query: Query = serde_json::from_str(message).map_err(|e| e.to_string())?;
+        if query.version == 0 || query.version > VERSION {
+            return Err(format!("unsupported query version {}", query.version));
+        }
+        if query.version < 2 && query.resolution.is_some() {
+            return Err("resolution needs query version 2".to_string());
+        }
+        query.resolution = query.resolution.map(|r| cmp::min(r, MAX_RESOLUTION));
+        Ok(query)
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Header {
+    pub version: u32,
+    /// Of the whole trace, in bytes, when the query arrived.
+    pub size: u64,
+    pub start: Option<Duration>,
+    pub end: Option<Duration>,
+}
+
+/// Messages the server sends that aren't trace events.
+#[derive(Clone, Debug, Serialize)]
+pub enum Reply {
+    Header(Header),
+    Summary(Summary),
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Shown {
+    // On one of the requested threads.
+    thread: bool,
+    // Under a span with a matching name.
+    matched: bool,
+    visible: bool,
+}
+
+/// Picks the lines of a trace a query asked for, one at a time.
+pub struct Filter {
+    query: Query,
+    spans: HashMap<SpanId, Shown>,
+    // Start and on-CPU events before the window, held back until it opens in
+    // case their spans are still running in it.  Keyed by span and whether
+    // it's the on-CPU event, with the line number for ordering.
+    held: HashMap<(SpanId, bool), (usize, String)>,
+    opened: bool,
+    lines: usize,
+}
+
+impl Filter {
+    pub fn new(query: &Query) -> Self {
+        Filter {
+            query: query.clone(),
+            spans: HashMap::new(),
+            held: HashMap::new(),
+            opened: false,
+            lines: 0,
+        }
+    }
+
+    fn child(&mut self, id: SpanId, parent_id: SpanId, name: Option<&str>) -> bool {
+        let parent = self.spans.get(&parent_id).cloned().unwrap_or(Shown {
+            thread: self.query.threads.is_none(),
+            matched: self.query.name.is_none(),
+            visible: false,
+        });
+        let matched = parent.matched || match (name, self.query.name.as_ref()) {
+            (Some(name), Some(filter)) => name.contains(filter.as_str()),
+            _ => false,
+        };
+        let visible = parent.thread && matched;
+        self.spans.insert(id, Shown { thread: parent.thread, matched, visible });
+        visible
+    }
+
+    fn visible(&mut self, event: &TraceEvent) -> bool {
+        let id = match *event {
+            TraceEvent::ThreadStart { ref name, id, .. } => {
+                let thread = self.query.threads.as_ref().map(|t| t.contains(name)).unwrap_or(true);
+                let matched = self.query.name.is_none();
+                self.spans.insert(id, Shown { thread, matched, visible: thread });
+                return thread;
+            },
+            TraceEvent::AsyncStart { ref name, id, parent_id, .. }
+            | TraceEvent::SyncStart { ref name, id, parent_id, .. } => {
+                return self.child(id, parent_id, Some(name));
+            },
+            TraceEvent::IdleStart { id, parent_id, .. } => return self.child(id, parent_id, None),
+            TraceEvent::Wakeup { parked_span, .. } => parked_span,
+            _ => event.id().unwrap(),
+        };
+        self.spans.get(&id).map(|s| s.visible).unwrap_or(false)
+    }
+
+    fn hold(&mut self, event: &TraceEvent, line: String) {
+        let key = match *event {
+            TraceEvent::ThreadStart { id, .. }
+            | TraceEvent::AsyncStart { id, .. }
+            | TraceEvent::SyncStart { id, .. }
+            | TraceEvent::IdleStart { id, .. } => (id, false),
+            TraceEvent::AsyncOnCPU { id, .. } => (id, true),
+            TraceEvent::AsyncOffCPU { id, .. } => {
+                self.held.remove(&(id, true));
+                return;
+            },
+            TraceEvent::AsyncEnd { id, .. }
+            | TraceEvent::SyncEnd { id, .. }
+            | TraceEvent::ThreadEnd { id, .. }
+            | TraceEvent::IdleEnd { id, .. } => {
+                self.held.remove(&(id, false));
+                self.held.remove(&(id, true));
+                return;
+            },
+            TraceEvent::Wakeup { .. } | TraceEvent::Mark { .. } => return,
+        };
+        self.held.insert(key, (self.lines, line));
+    }
+
+    /// Add the lines to send for the next line of the trace to `out`, and
+    /// return whether it was past the end of the window.  Lines that don't
+    /// parse are passed on for the client to report.
+    pub fn line(&mut self, line: String, out: &mut Vec<String>) -> bool {
+        let query = &self.query;
+        if query.start.is_none() && query.end.is_none() && query.name.is_none() && query.threads.is_none() {
+            out.push(line);
+            return false;
+        }
+        self.lines += 1;
+        let event: TraceEvent = match serde_json::from_str(&line) {
+            Ok(event) => event,
+            Err(_) => {
+                out.push(line);
+                return false;
+            },
+        };
+        let visible = self.visible(&event);
+        let ts = event.ts();
+        if self.query.end.map(|end| ts > end).unwrap_or(false) {
+            self.finish(out);
+            return true;
+        }
+        if !visible {
+            return false;
+        }
+        if !self.opened && self.query.start.map(|start| ts < start).unwrap_or(false) {
+            self.hold(&event, line);
+            return false;
+        }
+        self.finish(out);
+        out.push(line);
+        false
+    }
+
+    /// Open the window, if nothing in it has yet, adding the held back
+    /// events for spans still running to `out`.  Call at the end of the
+    /// trace, as a window can be empty.
+    pub fn finish(&mut self, out: &mut Vec<String>) {
+        if self.opened {
+            return;
+        }
+        // Threads log independently, so once the window's open, stragglers
+        // from before it are let through as they are.
+        self.opened = true;
+        let mut held: Vec<_> = self.held.drain().map(|(_, v)| v).collect();
+        held.sort();
+        out.extend(held.into_iter().map(|(_, line)| line));
+    }
+}
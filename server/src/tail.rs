@@ -0,0 +1,123 @@
+use std::fs::File;
+use std::io::{
+    self,
+    BufRead,
+    BufReader,
+    Seek,
+    SeekFrom,
+};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+use notify::{
+    self,
+    RawEvent,
+    RecommendedWatcher,
+    RecursiveMode,
+    Watcher,
+};
+
+// How often to check for more without change notifications.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+// Some filesystems (network mounts, mostly) accept a watch but never report
+// changes, so even with notifications, check every so often.
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Follows a file that's still being written, a line at a time, waking up on
+/// change notifications where the platform has them and polling otherwise.
+pub struct Tail {
+    reader: BufReader<File>,
+    // A partial line at the end of the file, waiting for the rest.
+    fragment: Vec<u8>,
+    // Whether the first line read is the end of one that started before
+    // where we opened the file.
+    skip: bool,
+    // Offset of the end of the last line read.
+    position: u64,
+    // Held to keep the watch alive.
+    watcher: Option<(RecommendedWatcher, Receiver<RawEvent>)>,
+}
+
+impl Tail {
+    /// Follow the file from the first whole line at or after byte `offset`.
+    pub fn open_at(path: &Path, offset: u64) -> io::Result<Self> {
+        // Watch before reading anything, so writes made while catching up
+        // aren't missed.
+        let (tx, rx) = mpsc::channel();
+        let watcher = notify::raw_watcher(tx)
+            .and_then(|mut w| w.watch(path, RecursiveMode::NonRecursive).map(|()| w));
+        let watcher = match watcher {
+            Ok(w) => Some((w, rx)),
+            Err(e) => {
+                println!("Can't watch {:?}, polling instead: {:?}", path, e);
+                None
+            },
+        };
+        // Back up a byte and skip to the end of that line, so a line starting
+        // at `offset` is kept but not one it falls in the middle of.
+        let mut file = File::open(path)?;
+        if offset > 0 {
+            file.seek(SeekFrom::Start(offset - 1))?;
+        }
+        Ok(Tail {
+            reader: BufReader::new(file),
+            fragment: vec![],
+            skip: offset > 0,
+            position: offset.saturating_sub(1),
+            watcher,
+        })
+    }
+
+    /// The next whole line, without its newline, or `None` if the writer
+    /// hasn't finished one yet.
+    pub fn read_line(&mut self) -> io::Result<Option<String>> {
+        loop {
+            self.reader.read_until(b'\n', &mut self.fragment)?;
+            if self.fragment.last() != Some(&b'\n') {
+                return Ok(None);
+            }
+            self.position += self.fragment.len() as u64;
+            self.fragment.pop();
+            let line = self.fragment.split_off(0);
+            if self.skip {
+                self.skip = false;
+                continue;
+            }
+            return String::from_utf8(line)
+                .map(Some)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+        }
+    }
+
+    /// Where the next line starts.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Block until the file may have grown.
+    pub fn wait(&mut self) {
+        let lost = match self.watcher {
+            Some((_, ref rx)) => match rx.recv_timeout(NOTIFY_TIMEOUT) {
+                Err(RecvTimeoutError::Disconnected) => true,
+                _ => {
+                    // One write can raise several notifications; they're all
+                    // handled by the next read.
+                    while rx.try_recv().is_ok() {}
+                    false
+                },
+            },
+            None => {
+                thread::sleep(POLL_INTERVAL);
+                false
+            },
+        };
+        // The watcher's gone away, and with it any notifications, so don't
+        // spin on the closed channel.
+        if lost {
+            println!("Lost the watch, polling instead");
+            self.watcher = None;
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
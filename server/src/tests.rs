@@ -0,0 +1,571 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use serde_json::{self, Value};
+use cyclotron_model::{AsyncOutcome, SpanId, TraceEvent};
+use cyclotron_model::binary::MAGIC;
+use detail::{self, Detail};
+use index::{self, TraceIndex};
+use listing::Listing;
+use paths::{self, PathError};
+use query::{self, Filter, Query};
+use tail::Tail;
+use websocket::{ClientBuilder, Message, OwnedMessage};
+use {listener, Args, CyclotronServer};
+
+// A served directory holding `trace.log`, next to a file that isn't served.
+fn setup(name: &str) -> (PathBuf, PathBuf) {
+    let base = Path::new("/tmp").join(name);
+    let _ = fs::remove_dir_all(&base);
+    let root = base.join("served");
+    fs::create_dir_all(root.join("nested")).unwrap();
+    File::create(root.join("trace.log")).unwrap();
+    File::create(root.join("nested").join("trace.log")).unwrap();
+    File::create(base.join("secret")).unwrap();
+    (root, base)
+}
+
+fn forbidden(root: &Path, name: &str) -> bool {
+    matches!(paths::resolve(root, name), Err(PathError::Forbidden))
+}
+
+#[test]
+fn test_resolve() {
+    let (root, _) = setup("test_resolve");
+    let root_ = root.canonicalize().unwrap();
+    assert_eq!(paths::resolve(&root, "trace.log").unwrap(), root_.join("trace.log"));
+    assert_eq!(paths::resolve(&root, "./nested/trace.log").unwrap(), root_.join("nested/trace.log"));
+    match paths::resolve(&root, "missing.log") {
+        Err(PathError::Io(_)) => (),
+        r => panic!("{:?}", r),
+    }
+}
+
+#[test]
+fn test_resolve_traversal() {
+    let (root, base) = setup("test_resolve_traversal");
+    assert!(forbidden(&root, "../secret"));
+    assert!(forbidden(&root, "nested/../../secret"));
+    // Even when it'd end up back inside.
+    assert!(forbidden(&root, "nested/../trace.log"));
+    assert!(forbidden(&root, "../../../../../../etc/passwd"));
+    assert!(forbidden(&root, "/etc/passwd"));
+    assert!(forbidden(&root, base.join("secret").to_str().unwrap()));
+    // Missing files outside aren't told apart from present ones.
+    assert!(forbidden(&root, "../missing"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_resolve_symlinks() {
+    use std::os::unix::fs::symlink;
+
+    let (root, base) = setup("test_resolve_symlinks");
+    symlink(base.join("secret"), root.join("escape")).unwrap();
+    symlink(&base, root.join("up")).unwrap();
+    symlink(root.join("trace.log"), root.join("alias.log")).unwrap();
+    assert!(forbidden(&root, "escape"));
+    assert!(forbidden(&root, "up/secret"));
+    assert!(paths::resolve(&root, "alias.log").is_ok());
+}
+
+fn ms(n: u64) -> Duration {
+    Duration::from_millis(n)
+}
+
+fn thread(name: &str, id: u64, ts: u64) -> TraceEvent {
+    TraceEvent::ThreadStart { name: name.to_string(), id: SpanId(id), ts: ms(ts) }
+}
+
+fn sync(name: &str, id: u64, parent_id: u64, ts: u64) -> TraceEvent {
+    TraceEvent::SyncStart {
+        name: name.to_string(),
+        id: SpanId(id),
+        parent_id: SpanId(parent_id),
+        ts: ms(ts),
+        metadata: Value::Null,
+    }
+}
+
+// The events the filter passes on, in order.
+fn filter(query: &Query, events: &[TraceEvent]) -> Vec<TraceEvent> {
+    let mut filter = Filter::new(query);
+    let mut out = vec![];
+    for event in events {
+        filter.line(serde_json::to_string(event).unwrap(), &mut out);
+    }
+    out.iter().map(|line| serde_json::from_str(line).unwrap()).collect()
+}
+
+#[test]
+fn test_query_parse() {
+    let query = Query::parse(r#"{"version": 1, "trace": "a.log"}"#).unwrap();
+    assert_eq!(query, Query { version: 1, follow: false, ..Query::whole("a.log".to_string()) });
+    let query = Query::parse(r#"{"version": 1, "trace": "a.log", "start": {"secs": 1, "nanos": 0},
+                                 "threads": ["CPU 0"], "follow": true}"#).unwrap();
+    assert_eq!(query.start, Some(Duration::from_secs(1)));
+    assert_eq!(query.threads, Some(vec!["CPU 0".to_string()]));
+    assert!(query.follow);
+    let query = Query::parse(r#"{"version": 2, "trace": "a.log", "resolution": 500}"#).unwrap();
+    assert_eq!(query.resolution, Some(500));
+    let query = Query::parse(r#"{"version": 2, "trace": "a.log", "resolution": 1000000000000}"#).unwrap();
+    assert_eq!(query.resolution, Some(query::MAX_RESOLUTION));
+    assert!(Query::parse(r#"{"version": 1, "trace": "a.log", "resolution": 500}"#).is_err());
+    assert!(Query::parse(r#"{"version": 3, "trace": "a.log"}"#).is_err());
+    assert!(Query::parse(r#"{"version": 1}"#).is_err());
+}
+
+#[test]
+fn test_filter_window() {
+    let id = SpanId(2);
+    let events = vec![
+        thread("main", 1, 0),
+        TraceEvent::AsyncStart { name: "a".to_string(), id, parent_id: SpanId(1), ts: ms(1), metadata: Value::Null },
+        sync("done early", 3, 1, 1),
+        TraceEvent::SyncEnd { id: SpanId(3), ts: ms(2) },
+        TraceEvent::AsyncOnCPU { id, ts: ms(1) },
+        TraceEvent::AsyncOffCPU { id, ts: ms(2) },
+        TraceEvent::AsyncOnCPU { id, ts: ms(25) },
+        TraceEvent::Mark { id, name: "before".to_string(), ts: ms(28), metadata: Value::Null },
+        TraceEvent::AsyncOffCPU { id, ts: ms(40) },
+        TraceEvent::AsyncEnd { id, ts: ms(60), outcome: AsyncOutcome::Success },
+    ];
+    let query = Query { start: Some(ms(30)), end: Some(ms(50)), ..Query::whole("a.log".to_string()) };
+    // The thread and the span that's running at the start of the window are
+    // kept, as is the span going on-CPU, since it's still on in the window.
+    let expected = vec![
+        events[0].clone(),
+        events[1].clone(),
+        events[6].clone(),
+        events[8].clone(),
+    ];
+    assert_eq!(filter(&query, &events), expected);
+
+    // Spans running through an empty window are still there.
+    let query = Query { start: Some(ms(30)), end: Some(ms(35)), ..query };
+    let mut f = Filter::new(&query);
+    let mut out = vec![];
+    for event in &events[..8] {
+        assert!(!f.line(serde_json::to_string(event).unwrap(), &mut out));
+    }
+    assert_eq!(out.len(), 0);
+    assert!(f.line(serde_json::to_string(&events[8]).unwrap(), &mut out));
+    assert_eq!(out.len(), 3);
+}
+
+#[test]
+fn test_filter_names_and_threads() {
+    let events = vec![
+        thread("a", 1, 0),
+        thread("b", 2, 0),
+        sync("poll x", 3, 1, 1),
+        sync("inner", 4, 3, 2),
+        TraceEvent::SyncEnd { id: SpanId(4), ts: ms(3) },
+        TraceEvent::SyncEnd { id: SpanId(3), ts: ms(4) },
+        sync("other", 5, 1, 5),
+        TraceEvent::SyncEnd { id: SpanId(5), ts: ms(6) },
+        sync("poll y", 6, 2, 5),
+        TraceEvent::SyncEnd { id: SpanId(6), ts: ms(6) },
+        TraceEvent::ThreadEnd { id: SpanId(1), ts: ms(7) },
+    ];
+    let query = Query {
+        name: Some("poll".to_string()),
+        threads: Some(vec!["a".to_string()]),
+        ..Query::whole("a.log".to_string())
+    };
+    let expected: Vec<_> = [0, 2, 3, 4, 5, 10].iter().map(|&i| events[i].clone()).collect();
+    assert_eq!(filter(&query, &events), expected);
+
+    let query = Query { name: Some("poll".to_string()), ..Query::whole("a.log".to_string()) };
+    let expected: Vec<_> = [0, 1, 2, 3, 4, 5, 8, 9, 10].iter().map(|&i| events[i].clone()).collect();
+    assert_eq!(filter(&query, &events), expected);
+}
+
+#[test]
+fn test_tail_offset() {
+    let path = Path::new("/tmp/test_tail_offset.log");
+    File::create(path).unwrap().write_all(b"one\ntwo\nthree\npart").unwrap();
+
+    let lines = |offset| {
+        let mut tail = Tail::open_at(path, offset).unwrap();
+        let mut lines = vec![];
+        while let Some(line) = tail.read_line().unwrap() {
+            lines.push(line);
+        }
+        lines
+    };
+    assert_eq!(lines(0), vec!["one", "two", "three"]);
+    assert_eq!(lines(4), vec!["two", "three"]);
+    assert_eq!(lines(5), vec!["three"]);
+    assert_eq!(lines(15), Vec::<String>::new());
+
+    let mut tail = Tail::open_at(path, 16).unwrap();
+    assert_eq!(tail.read_line().unwrap(), None);
+    OpenOptions::new().append(true).open(path).unwrap().write_all(b"ial\nnext\n").unwrap();
+    assert_eq!(tail.read_line().unwrap(), Some("next".to_string()));
+    let mut tail = Tail::open_at(path, 14).unwrap();
+    assert_eq!(tail.read_line().unwrap(), Some("partial".to_string()));
+}
+
+#[test]
+fn test_tail_wait() {
+    let path = Path::new("/tmp/test_tail_wait.log");
+    File::create(path).unwrap();
+    let mut tail = Tail::open_at(path, 0).unwrap();
+    assert_eq!(tail.read_line().unwrap(), None);
+
+    let writer = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        OpenOptions::new().append(true).open(path).unwrap().write_all(b"line\n").unwrap();
+    });
+    // Woken by the notification, well before the next poll would have been.
+    let start = Instant::now();
+    tail.wait();
+    assert!(start.elapsed() < Duration::from_millis(200), "{:?}", start.elapsed());
+    writer.join().unwrap();
+    assert_eq!(tail.read_line().unwrap(), Some("line".to_string()));
+}
+
+#[test]
+fn test_index() {
+    let path = Path::new("/tmp/test_index.log");
+    let micros = Duration::from_micros;
+    let mut events = vec![
+        thread("main", 1, 0),
+        TraceEvent::AsyncStart { name: "long".to_string(), id: SpanId(2), parent_id: SpanId(1), ts: ms(1), metadata: Value::Null },
+        thread("worker", 3, 0),
+        // On-CPU across the start of the window.
+        TraceEvent::AsyncStart { name: "poll".to_string(), id: SpanId(4), parent_id: SpanId(3), ts: ms(1), metadata: Value::Null },
+        TraceEvent::AsyncOnCPU { id: SpanId(4), ts: ms(80) },
+        TraceEvent::AsyncOffCPU { id: SpanId(4), ts: ms(105) },
+        TraceEvent::AsyncEnd { id: SpanId(4), ts: ms(106), outcome: AsyncOutcome::Success },
+    ];
+    for i in 0..200 {
+        let id = SpanId(100 + i);
+        events.push(sync("short", id.0, 1, 2 + i));
+        events.push(TraceEvent::SyncEnd { id, ts: micros(2500 + i * 1000) });
+    }
+    events.push(TraceEvent::AsyncEnd { id: SpanId(2), ts: ms(250), outcome: AsyncOutcome::Success });
+    events.sort_by_key(|e| e.ts());
+    let mut file = File::create(path).unwrap();
+    for event in &events {
+        writeln!(file, "{}", serde_json::to_string(event).unwrap()).unwrap();
+    }
+    let size = fs::metadata(path).unwrap().len();
+
+    let mut index = TraceIndex::new(1000);
+    assert!(index.update(path).unwrap());
+    assert!(!index.update(path).unwrap());
+    index.save(path).unwrap();
+    assert_eq!(TraceIndex::load(path), index);
+    assert_eq!(index.range(), (Some(ms(0)), Some(ms(250))));
+
+    let query = Query {
+        start: Some(ms(100)),
+        end: Some(ms(120)),
+        follow: false,
+        ..Query::whole("test_index.log".to_string())
+    };
+    let offset = index.seek(ms(100));
+    let earlier = index.open_at(offset);
+    let stop = index.stop(ms(120)).unwrap();
+    assert!(offset > size / 3 && stop < size * 2 / 3);
+    // The threads, the long span, the poll and its on-CPU event, and maybe a
+    // short one across the block boundary.
+    let lines: Vec<TraceEvent> = index::read_lines_at(path, &earlier).unwrap().iter()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(lines[..4], events[..4]);
+    assert_eq!(lines[4], TraceEvent::AsyncOnCPU { id: SpanId(4), ts: ms(80) });
+    assert!(lines.len() <= 6);
+
+    // Reading just the indexed part gives the same as reading everything.
+    let mut filter = Filter::new(&query);
+    let mut indexed = vec![];
+    for line in index::read_lines_at(path, &earlier).unwrap() {
+        filter.line(line, &mut indexed);
+    }
+    let mut tail = Tail::open_at(path, offset).unwrap();
+    while tail.position() < stop {
+        let line = tail.read_line().unwrap().unwrap();
+        filter.line(line, &mut indexed);
+    }
+    filter.finish(&mut indexed);
+    let indexed: Vec<TraceEvent> = indexed.iter().map(|l| serde_json::from_str(l).unwrap()).collect();
+    assert_eq!(indexed, self::filter(&query, &events));
+    assert_eq!(indexed.len(), 2 + 2 * 20 + 1 + 5);
+
+    // A trace that's shrunk is indexed again.
+    let mut file = File::create(path).unwrap();
+    writeln!(file, "{}", serde_json::to_string(&events[0]).unwrap()).unwrap();
+    assert!(index.update(path).unwrap());
+    assert_eq!(index.open_at(1000), vec![0]);
+
+    // So is one that's been replaced, even once it's grown past the old one.
+    index.save(path).unwrap();
+    let mut file = File::create(path).unwrap();
+    for event in events.iter().skip(2) {
+        writeln!(file, "{}", serde_json::to_string(event).unwrap()).unwrap();
+    }
+    let mut reloaded = TraceIndex::load(path);
+    assert!(reloaded.update(path).unwrap());
+    let mut fresh = TraceIndex::new(1000);
+    fresh.update(path).unwrap();
+    assert_eq!(reloaded, fresh);
+}
+
+#[test]
+fn test_detail() {
+    let path = Path::new("/tmp/test_detail.log");
+    let _ = fs::remove_file(detail::detail_path(path));
+    let mut file = File::create(path).unwrap();
+    for event in &[thread("main", 1, 0), sync("first", 2, 1, 1), TraceEvent::SyncEnd { id: SpanId(2), ts: ms(50) }] {
+        writeln!(file, "{}", serde_json::to_string(event).unwrap()).unwrap();
+    }
+    let summarise = |detail: &Detail| detail.builder.summarise(ms(0), ms(100), 10);
+    let mut detail = Detail::load(path);
+    assert!(detail.update(path).unwrap());
+    detail.save(path).unwrap();
+    let expected = summarise(&detail);
+
+    // The saved summaries carry on from where they left off.
+    let mut loaded = Detail::load(path);
+    assert!(!loaded.update(path).unwrap());
+    assert_eq!(summarise(&loaded), expected);
+    writeln!(file, "{}", serde_json::to_string(&sync("second", 3, 1, 60)).unwrap()).unwrap();
+    assert!(loaded.update(path).unwrap());
+    assert_eq!(summarise(&loaded).rows[0].buckets.iter().map(|b| b.count).sum::<u32>(), 2);
+
+    // Unless the trace's been replaced.
+    let mut file = File::create(path).unwrap();
+    for event in &[thread("other", 1, 0), sync("replaced", 2, 1, 1)] {
+        writeln!(file, "{}", serde_json::to_string(event).unwrap()).unwrap();
+    }
+    let mut reloaded = Detail::load(path);
+    assert!(reloaded.update(path).unwrap());
+    assert_eq!(summarise(&reloaded).rows[0].thread, "other");
+    assert_eq!(summarise(&reloaded).rows.len(), 1);
+}
+
+#[test]
+fn test_listing() {
+    let dir = Path::new("/tmp/test_listing");
+    let _ = fs::remove_dir_all(dir);
+    fs::create_dir_all(dir.join("nested")).unwrap();
+    let path = dir.join("trace.log");
+    let mut file = File::create(&path).unwrap();
+    for event in &[thread("main", 1, 0), sync("first", 2, 1, 5)] {
+        writeln!(file, "{}", serde_json::to_string(event).unwrap()).unwrap();
+    }
+    // Not yet a whole line.
+    write!(file, "{{\"ThreadStart\"").unwrap();
+    File::create(dir.join("trace.bin")).unwrap().write_all(MAGIC).unwrap();
+    File::create(dir.join("trace.log.idx")).unwrap();
+
+    // Binary traces, caches and directories aren't summarised.
+    let listing = Listing::new();
+    let traces = listing.list(dir).unwrap();
+    let names: Vec<_> = traces.iter().map(|t| t.name.as_str()).collect();
+    assert_eq!(names, vec!["trace.bin", "trace.log"]);
+    assert!(traces[0].summary.is_none());
+    assert_eq!(traces[1].size, fs::metadata(&path).unwrap().len());
+    assert!(traces[1].live);
+    let summary = traces[1].summary.clone().unwrap();
+    assert_eq!(summary.threads, vec!["main"]);
+    assert_eq!(summary.spans, 2);
+    assert_eq!((summary.start, summary.end), (Some(ms(0)), Some(ms(5))));
+
+    // Finishing the line and adding more picks up where the scan left off.
+    writeln!(file, ":{{\"name\":\"worker\",\"id\":3,\"ts\":{{\"secs\":0,\"nanos\":0}}}}}}").unwrap();
+    writeln!(file, "{}", serde_json::to_string(&sync("second", 4, 3, 9)).unwrap()).unwrap();
+    let summary = listing.list(dir).unwrap()[1].summary.clone().unwrap();
+    assert_eq!(summary.threads, vec!["main", "worker"]);
+    assert_eq!(summary.spans, 4);
+    assert_eq!(summary.end, Some(ms(9)));
+
+    // A truncated trace is scanned again from the start.
+    let mut file = File::create(&path).unwrap();
+    writeln!(file, "{}", serde_json::to_string(&thread("other", 1, 2)).unwrap()).unwrap();
+    let summary = listing.list(dir).unwrap()[1].summary.clone().unwrap();
+    assert_eq!(summary.threads, vec!["other"]);
+    assert_eq!(summary.spans, 1);
+    assert_eq!((summary.start, summary.end), (Some(ms(2)), Some(ms(2))));
+
+    // So is one replaced by another just as long.
+    let before = fs::metadata(&path).unwrap().len();
+    let mut file = File::create(&path).unwrap();
+    writeln!(file, "{}", serde_json::to_string(&thread("after", 1, 3)).unwrap()).unwrap();
+    assert_eq!(fs::metadata(&path).unwrap().len(), before);
+    let summary = listing.list(dir).unwrap()[1].summary.clone().unwrap();
+    assert_eq!(summary.threads, vec!["after"]);
+    assert_eq!(summary.start, Some(ms(3)));
+}
+
+// A connected pair of sockets.
+fn socket_pair() -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    (client, listener.accept().unwrap().0)
+}
+
+#[test]
+fn test_is_websocket() {
+    let (mut client, server) = socket_pair();
+    client.write_all(b"GET /ws/trace.log HTTP/1.1\r\n").unwrap();
+    assert!(listener::is_websocket(&server).unwrap());
+    // Only peeked at, so the request's still there to read.
+    let mut buf = [0; 8];
+    (&server).read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"GET /ws/");
+
+    let (mut client, server) = socket_pair();
+    client.write_all(b"GET /api/traces HTTP/1.1\r\n").unwrap();
+    assert!(!listener::is_websocket(&server).unwrap());
+
+    // Decided as soon as it can't be a websocket, however little has come.
+    let (mut client, server) = socket_pair();
+    client.write_all(b"POST").unwrap();
+    assert!(!listener::is_websocket(&server).unwrap());
+
+    // Waits for a request line that's arriving in pieces.
+    let (mut client, server) = socket_pair();
+    client.write_all(b"GET /w").unwrap();
+    let writer = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        client.write_all(b"s/").unwrap();
+        client
+    });
+    assert!(listener::is_websocket(&server).unwrap());
+    drop(writer.join().unwrap());
+
+    let (client, server) = socket_pair();
+    drop(client);
+    assert!(!listener::is_websocket(&server).unwrap());
+}
+
+// Serve `root` as both traces and frontend on a free port.
+fn serve(root: &Path) -> SocketAddr {
+    let args = Args {
+        flag_http: 0,
+        flag_ws: None,
+        flag_traces: root.to_str().unwrap().to_string(),
+        flag_frontend: root.to_str().unwrap().to_string(),
+    };
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = CyclotronServer::new(&args);
+    thread::spawn(move || listener::serve(listener, server));
+    addr
+}
+
+// Everything the server sends for `query` until it closes the socket.
+fn query_server(addr: SocketAddr, query: &str) -> Vec<String> {
+    query_server_closed(addr, query).0
+}
+
+// As `query_server`, also returning the close frame's status code, if any.
+fn query_server_closed(addr: SocketAddr, query: &str) -> (Vec<String>, Option<u16>) {
+    let mut client = ClientBuilder::new(&format!("ws://{}/ws/", addr)).unwrap()
+        .add_protocol("cyclotron-ws")
+        .connect_insecure()
+        .unwrap();
+    client.stream_ref().set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    client.send_message(&Message::text(query)).unwrap();
+    let mut lines = vec![];
+    loop {
+        match client.recv_message().unwrap() {
+            OwnedMessage::Text(line) => lines.push(line),
+            OwnedMessage::Close(data) => return (lines, data.map(|d| d.status_code)),
+            m => panic!("unexpected message {:?}", m),
+        }
+    }
+}
+
+#[test]
+fn test_window_follow() {
+    let (root, _) = setup("test_window_follow");
+    let events = vec![
+        thread("main", 1, 0),
+        thread("worker", 2, 0),
+        sync("in", 3, 1, 10),
+        TraceEvent::SyncEnd { id: SpanId(3), ts: ms(20) },
+        sync("after", 4, 1, 60),
+        // Logged late by the other thread, after the window's closed.
+        sync("straggler", 5, 2, 40),
+    ];
+    let mut file = File::create(root.join("trace.log")).unwrap();
+    for event in &events {
+        writeln!(file, "{}", serde_json::to_string(event).unwrap()).unwrap();
+    }
+    let addr = serve(&root);
+    let query = |follow: bool| {
+        let query = format!(
+            r#"{{"version":2,"trace":"trace.log","follow":{},"start":{{"secs":0,"nanos":5000000}},"end":{{"secs":0,"nanos":50000000}}}}"#,
+            follow,
+        );
+        query_server(addr, &query)
+    };
+
+    // Following or not, the window closes at the first event past it.
+    let lines = query(false);
+    assert_eq!(query(true), lines);
+    let sent: Vec<TraceEvent> = lines[1..].iter().map(|l| serde_json::from_str(l).unwrap()).collect();
+    assert_eq!(sent, &events[..4]);
+}
+
+#[test]
+fn test_listener() {
+    let (root, _) = setup("test_listener");
+    let event = thread("main", 1, 0);
+    writeln!(File::create(root.join("trace.log")).unwrap(), "{}", serde_json::to_string(&event).unwrap()).unwrap();
+    let addr = serve(&root);
+
+    // Plain HTTP goes to hyper.
+    let mut http = TcpStream::connect(addr).unwrap();
+    http.write_all(b"GET /api/traces HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+    let mut response = String::new();
+    http.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    assert!(response.contains("\"name\":\"trace.log\""), "{}", response);
+
+    // A websocket path without the upgrade headers gets an answer too.
+    let mut plain = TcpStream::connect(addr).unwrap();
+    plain.write_all(b"GET /ws/ HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut response = String::new();
+    plain.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request"), "{}", response);
+
+    // And websockets to the streamer, on the same port.
+    let mut client = ClientBuilder::new(&format!("ws://{}/ws/trace.log", addr)).unwrap()
+        .add_protocol("cyclotron-ws")
+        .connect_insecure()
+        .unwrap();
+    match client.recv_message().unwrap() {
+        OwnedMessage::Text(line) => assert_eq!(serde_json::from_str::<TraceEvent>(&line).unwrap(), event),
+        m => panic!("unexpected message {:?}", m),
+    }
+}
+
+#[test]
+fn test_serve_traversal() {
+    let (root, _) = setup("test_serve_traversal");
+    let addr = serve(&root);
+
+    for path in &["/../../etc/passwd", "/../secret", "/frontend/../../secret"] {
+        let mut http = TcpStream::connect(addr).unwrap();
+        write!(http, "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        http.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 403 Forbidden"), "{}: {}", path, response);
+    }
+
+    for trace in &["../x", "../secret", "/etc/passwd"] {
+        let query = format!(r#"{{"version":2,"trace":"{}"}}"#, trace);
+        let (lines, code) = query_server_closed(addr, &query);
+        assert_eq!(lines, Vec::<String>::new(), "{}", trace);
+        assert_eq!(code, Some(1008), "{}", trace);
+    }
+}